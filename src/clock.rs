@@ -0,0 +1,48 @@
+//! A source of the current time that tests can control.
+use std::time::Instant;
+
+/// `Send` so that a terminal can be handed to another thread.
+pub trait Clock: Send {
+    fn now(&self) -> Instant;
+}
+
+/// The real time.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A clock that only moves when it is told to. Clones share the same time, so
+/// a test can keep one and hand another to the terminal.
+#[cfg(any(test, feature = "test-support"))]
+#[derive(Debug, Clone)]
+pub struct FakeClock(std::sync::Arc<std::sync::Mutex<Instant>>);
+
+#[cfg(any(test, feature = "test-support"))]
+impl Default for FakeClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(any(test, feature = "test-support"))]
+impl FakeClock {
+    pub fn new() -> Self {
+        Self(std::sync::Arc::new(std::sync::Mutex::new(Instant::now())))
+    }
+
+    pub fn advance(&self, by: std::time::Duration) {
+        *self.0.lock().unwrap() += by;
+    }
+}
+
+#[cfg(any(test, feature = "test-support"))]
+impl Clock for FakeClock {
+    fn now(&self) -> Instant {
+        *self.0.lock().unwrap()
+    }
+}
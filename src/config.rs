@@ -0,0 +1,97 @@
+//! Settings for the debug GUI, read from `termulus/config.toml` in the config
+//! directory (`$XDG_CONFIG_HOME`, or `~/.config`).
+use std::{path::PathBuf, time::Duration};
+
+use anyhow::{Context, Result};
+
+use termulus::input::KeyRepeat;
+
+use crate::gui::Layout;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Config {
+    /// `layout = "columns"` or `layout = "auto"`.
+    pub layout: Layout,
+    /// Repeat held keys that the OS doesn't repeat, from a `[key_repeat]`
+    /// table with `delay` in milliseconds and `rate` in repeats per second.
+    pub key_repeat: Option<KeyRepeat>,
+}
+
+impl Config {
+    pub fn path() -> Option<PathBuf> {
+        let dir = std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+        Some(dir.join("termulus").join("config.toml"))
+    }
+
+    /// Read the config file, or use the defaults if there isn't one.
+    pub fn load() -> Result<Self> {
+        let Some(path) = Self::path() else {
+            return Ok(Self::default());
+        };
+        match std::fs::read_to_string(&path) {
+            Ok(text) => Self::parse(&text).with_context(|| format!("in {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("failed to read {}", path.display())),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let doc = text.parse::<toml_edit::Document>()?;
+        let mut config = Self::default();
+        if let Some(layout) = doc.get("layout") {
+            config.layout = match layout.as_str() {
+                Some("columns") => Layout::Columns,
+                Some("auto") => Layout::Auto,
+                _ => anyhow::bail!("layout should be \"columns\" or \"auto\", not {}", layout),
+            };
+        }
+        if let Some(table) = doc.get("key_repeat") {
+            let table = table
+                .as_table_like()
+                .context("key_repeat should be a table")?;
+            let number = |key: &str, default: i64| match table.get(key) {
+                None => Ok(default),
+                Some(value) => value
+                    .as_integer()
+                    .filter(|n| *n > 0)
+                    .with_context(|| format!("key_repeat.{} should be a positive number", key)),
+            };
+            config.key_repeat = Some(KeyRepeat {
+                delay: Duration::from_millis(number("delay", 500)? as u64),
+                interval: Duration::from_secs(1) / number("rate", 30)?.min(1000) as u32,
+            });
+        }
+        Ok(config)
+    }
+}
+
+#[test]
+fn test_parse_config() {
+    assert_eq!(Config::parse("").unwrap(), Config::default());
+    assert_eq!(
+        Config::parse("layout = \"auto\"\n").unwrap().layout,
+        Layout::Auto
+    );
+    assert!(Config::parse("layout = \"spiral\"").is_err());
+    assert!(Config::parse("layout = 1").is_err());
+
+    let config = Config::parse("[key_repeat]\ndelay = 250\nrate = 20\n").unwrap();
+    assert_eq!(
+        config.key_repeat,
+        Some(KeyRepeat {
+            delay: Duration::from_millis(250),
+            interval: Duration::from_millis(50),
+        })
+    );
+    assert_eq!(
+        Config::parse("key_repeat = {}").unwrap().key_repeat,
+        Some(KeyRepeat {
+            delay: Duration::from_millis(500),
+            interval: Duration::from_secs(1) / 30,
+        })
+    );
+    assert!(Config::parse("key_repeat = 1").is_err());
+    assert!(Config::parse("[key_repeat]\nrate = 0").is_err());
+}
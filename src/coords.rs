@@ -0,0 +1,167 @@
+//! Coordinate types shared by the parser and the terminal.
+//!
+//! Escape sequences address cells 1-based (`CSI 1;1H` is the top left corner)
+//! while everything inside the emulator is 0-based. Doing the conversion by hand
+//! at each call site is how the off-by-one bugs kept sneaking in, so positions
+//! cross the parser -> terminal boundary as a [`WirePoint`] and are only turned
+//! into a [`ScreenPoint`] once the screen size is known.
+
+/// The size of the screen in cells.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScreenSize {
+    pub cols: usize,
+    pub rows: usize,
+}
+
+impl ScreenSize {
+    /// Used when the pty doesn't know its size yet (it reports 0x0 until the
+    /// first resize).
+    pub const DEFAULT: Self = Self { cols: 80, rows: 24 };
+
+    /// Create a new size, falling back to [`ScreenSize::DEFAULT`] if either
+    /// dimension is zero.
+    pub fn new(cols: usize, rows: usize) -> Self {
+        if cols == 0 || rows == 0 {
+            Self::DEFAULT
+        } else {
+            Self { cols, rows }
+        }
+    }
+}
+
+impl Default for ScreenSize {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl From<nix::pty::Winsize> for ScreenSize {
+    fn from(ws: nix::pty::Winsize) -> Self {
+        Self::new(ws.ws_col as usize, ws.ws_row as usize)
+    }
+}
+
+/// A 0-based cell position on the screen.
+///
+/// The only way to build one is to clamp it to a [`ScreenSize`], so a
+/// `ScreenPoint` is always a valid index into the screen it was created for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ScreenPoint {
+    col: usize,
+    row: usize,
+}
+
+impl ScreenPoint {
+    pub const ORIGIN: Self = Self { col: 0, row: 0 };
+
+    pub fn clamped(col: usize, row: usize, size: ScreenSize) -> Self {
+        Self {
+            col: col.min(size.cols.saturating_sub(1)),
+            row: row.min(size.rows.saturating_sub(1)),
+        }
+    }
+
+    pub fn col(&self) -> usize {
+        self.col
+    }
+
+    pub fn row(&self) -> usize {
+        self.row
+    }
+
+    /// Convert to the 1-based form used when reporting positions back to the
+    /// application (cursor position reports, mouse events).
+    pub fn to_wire(self) -> WirePoint {
+        WirePoint {
+            col: self.col + 1,
+            row: self.row + 1,
+        }
+    }
+}
+
+/// A 1-based cell position as it appears in escape sequences.
+///
+/// A parameter of 0 means the same thing as a missing one (the default of 1),
+/// so both are normalized when the point is created and the fields are never 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WirePoint {
+    col: usize,
+    row: usize,
+}
+
+impl WirePoint {
+    pub fn new(col: usize, row: usize) -> Self {
+        Self {
+            col: col.max(1),
+            row: row.max(1),
+        }
+    }
+
+    pub fn col(&self) -> usize {
+        self.col
+    }
+
+    pub fn row(&self) -> usize {
+        self.row
+    }
+
+    /// Convert to a 0-based point, clamping to the screen.
+    pub fn to_screen(self, size: ScreenSize) -> ScreenPoint {
+        ScreenPoint::clamped(self.col - 1, self.row - 1, size)
+    }
+}
+
+impl Default for WirePoint {
+    fn default() -> Self {
+        Self { col: 1, row: 1 }
+    }
+}
+
+/// A position in the scrollback and screen together, which stays the same as
+/// lines scroll off the screen.
+///
+/// Lines are numbered from the first line the terminal ever had, so numbers
+/// keep counting up when old lines are dropped from the scrollback.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct AbsolutePoint {
+    pub line: usize,
+    pub col: usize,
+}
+
+impl AbsolutePoint {
+    pub fn new(line: usize, col: usize) -> Self {
+        Self { line, col }
+    }
+}
+
+#[test]
+fn test_wire_point_zero_is_default() {
+    assert_eq!(WirePoint::new(0, 0), WirePoint::default());
+    assert_eq!(
+        WirePoint::new(0, 0).to_screen(ScreenSize::DEFAULT),
+        ScreenPoint::ORIGIN
+    );
+}
+
+#[test]
+fn test_wire_point_clamps_to_screen() {
+    let size = ScreenSize::new(80, 24);
+    let point = WirePoint::new(200, 999).to_screen(size);
+    assert_eq!(point.col(), 79);
+    assert_eq!(point.row(), 23);
+}
+
+#[test]
+fn test_screen_point_round_trip() {
+    let size = ScreenSize::new(80, 24);
+    // The first column is reported as column 1, e.g. for a mouse click.
+    let point = ScreenPoint::clamped(0, 4, size);
+    assert_eq!(point.to_wire(), WirePoint::new(1, 5));
+    assert_eq!(point.to_wire().to_screen(size), point);
+}
+
+#[test]
+fn test_screen_size_falls_back_when_empty() {
+    assert_eq!(ScreenSize::new(0, 0), ScreenSize::DEFAULT);
+    assert_eq!(ScreenSize::new(120, 0), ScreenSize::DEFAULT);
+}
@@ -0,0 +1,493 @@
+//! The screen contents, stored as rows of cells.
+use std::ops::Range;
+
+use crate::{
+    coords::{ScreenPoint, ScreenSize},
+    style::Style,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    c: char,
+    /// A combining mark over the character, like the accent in `e` followed by
+    /// U+0301. Only the first one is kept.
+    mark: Option<char>,
+    style: Style,
+    spacer: bool,
+}
+
+impl Cell {
+    pub fn new(c: char) -> Self {
+        Self::styled(c, Style::default())
+    }
+
+    pub fn styled(c: char, style: Style) -> Self {
+        Self {
+            c,
+            mark: None,
+            style,
+            spacer: false,
+        }
+    }
+
+    /// The right half of a wide character, or the cell left empty at the end
+    /// of a row when a wide character didn't fit. Spacers show as blank but
+    /// aren't part of the text.
+    pub fn spacer(style: Style) -> Self {
+        Self {
+            c: ' ',
+            mark: None,
+            style,
+            spacer: true,
+        }
+    }
+
+    pub fn is_spacer(&self) -> bool {
+        self.spacer
+    }
+
+    pub fn char(&self) -> char {
+        self.c
+    }
+
+    pub fn mark(&self) -> Option<char> {
+        self.mark
+    }
+
+    /// Attach a combining mark to the character, unless it already has one.
+    pub fn add_mark(&mut self, mark: char) {
+        self.mark.get_or_insert(mark);
+    }
+
+    /// The character as it should appear in extracted text. Control
+    /// characters can't be printed by the parser, but rows saved before that
+    /// was the case may still hold them, so they become U+FFFD.
+    pub fn text_char(&self) -> char {
+        if self.c.is_control() {
+            char::REPLACEMENT_CHARACTER
+        } else {
+            self.c
+        }
+    }
+
+    pub fn style(&self) -> &Style {
+        &self.style
+    }
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Self::new(' ')
+    }
+}
+
+/// The size of the glyphs on a line, set with DECDWL (`ESC # 6`), DECDHL
+/// (`ESC # 3` / `ESC # 4`) and DECSWL (`ESC # 5`).
+///
+/// Lines that aren't [`LineSize::Normal`] only have room for half as many
+/// characters, since each one takes up two cells.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LineSize {
+    #[default]
+    Normal,
+    DoubleWidth,
+    /// The top half of a double height line. The bottom half is expected on the
+    /// next line, with the same text.
+    DoubleHeightTop,
+    DoubleHeightBottom,
+}
+
+impl LineSize {
+    pub fn is_double_width(&self) -> bool {
+        !matches!(self, LineSize::Normal)
+    }
+}
+
+/// Attributes of a whole line, kept with its row when it scrolls into the
+/// scrollback and honored by the exports, e.g. [`crate::terminal::Terminal::contents`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Hash)]
+pub struct LineFlags(u8);
+
+impl LineFlags {
+    /// The text carries on at the start of the next row, see [`Row::wrapped`].
+    pub const WRAPPED: Self = Self(1);
+    pub const DOUBLE_WIDTH: Self = Self(1 << 1);
+    pub const DOUBLE_HEIGHT_TOP: Self = Self(1 << 2);
+    pub const DOUBLE_HEIGHT_BOTTOM: Self = Self(1 << 3);
+    /// A shell prompt starts on the line (OSC 133 `A`).
+    pub const PROMPT: Self = Self(1 << 4);
+
+    const SIZE: Self =
+        Self(Self::DOUBLE_WIDTH.0 | Self::DOUBLE_HEIGHT_TOP.0 | Self::DOUBLE_HEIGHT_BOTTOM.0);
+
+    pub fn contains(self, flags: Self) -> bool {
+        self.0 & flags.0 == flags.0
+    }
+
+    pub fn set(&mut self, flags: Self, on: bool) {
+        if on {
+            self.0 |= flags.0;
+        } else {
+            self.0 &= !flags.0;
+        }
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    fn size(self) -> LineSize {
+        if self.contains(Self::DOUBLE_WIDTH) {
+            LineSize::DoubleWidth
+        } else if self.contains(Self::DOUBLE_HEIGHT_TOP) {
+            LineSize::DoubleHeightTop
+        } else if self.contains(Self::DOUBLE_HEIGHT_BOTTOM) {
+            LineSize::DoubleHeightBottom
+        } else {
+            LineSize::Normal
+        }
+    }
+
+    fn set_size(&mut self, size: LineSize) {
+        self.set(Self::SIZE, false);
+        match size {
+            LineSize::Normal => {}
+            LineSize::DoubleWidth => self.set(Self::DOUBLE_WIDTH, true),
+            LineSize::DoubleHeightTop => self.set(Self::DOUBLE_HEIGHT_TOP, true),
+            LineSize::DoubleHeightBottom => self.set(Self::DOUBLE_HEIGHT_BOTTOM, true),
+        }
+    }
+}
+
+impl std::ops::BitOr for LineFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// How many cells a character takes up: 2 for East Asian wide and fullwidth
+/// characters (CJK, Hangul, most emoji), 0 for combining marks and other
+/// characters that attach to the one before, 1 for everything else.
+pub fn char_width(c: char) -> usize {
+    match c as u32 {
+        0x0300..=0x036F
+        | 0x1AB0..=0x1AFF
+        | 0x1DC0..=0x1DFF
+        | 0x200B..=0x200F
+        | 0x20D0..=0x20FF
+        | 0xFE00..=0xFE0F
+        | 0xFE20..=0xFE2F => 0,
+        0x1100..=0x115F
+        | 0x2E80..=0x303E
+        | 0x3041..=0x33FF
+        | 0x3400..=0x4DBF
+        | 0x4E00..=0x9FFF
+        | 0xA000..=0xA4CF
+        | 0xAC00..=0xD7A3
+        | 0xF900..=0xFAFF
+        | 0xFE30..=0xFE4F
+        | 0xFF00..=0xFF60
+        | 0xFFE0..=0xFFE6
+        | 0x1F300..=0x1F64F
+        | 0x1F900..=0x1F9FF
+        | 0x20000..=0x2FFFD
+        | 0x30000..=0x3FFFD => 2,
+        _ => 1,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Row {
+    cells: Vec<Cell>,
+    flags: LineFlags,
+}
+
+impl Row {
+    pub fn new(cols: usize) -> Self {
+        Self::filled(cols, Cell::default())
+    }
+
+    /// A row with every cell set to `blank`.
+    pub fn filled(cols: usize, blank: Cell) -> Self {
+        Self {
+            cells: vec![blank; cols],
+            flags: LineFlags::default(),
+        }
+    }
+
+    pub fn cells(&self) -> &[Cell] {
+        &self.cells
+    }
+
+    pub fn cells_mut(&mut self) -> &mut [Cell] {
+        &mut self.cells
+    }
+
+    pub fn flags(&self) -> LineFlags {
+        self.flags
+    }
+
+    pub fn set_flags(&mut self, flags: LineFlags, on: bool) {
+        self.flags.set(flags, on);
+    }
+
+    pub fn size(&self) -> LineSize {
+        self.flags.size()
+    }
+
+    pub fn set_size(&mut self, size: LineSize) {
+        self.flags.set_size(size);
+    }
+
+    /// Whether the text ran past the end of the row and carries on at the
+    /// start of the next one, rather than the line ending here.
+    pub fn wrapped(&self) -> bool {
+        self.flags.contains(LineFlags::WRAPPED)
+    }
+
+    pub fn set_wrapped(&mut self, wrapped: bool) {
+        self.flags.set(LineFlags::WRAPPED, wrapped);
+    }
+
+    /// The number of characters that fit on this line.
+    pub fn cols(&self) -> usize {
+        if self.size().is_double_width() {
+            self.cells.len() / 2
+        } else {
+            self.cells.len()
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.clear_with(Cell::default());
+    }
+
+    /// Clear the row, setting every cell to `blank`.
+    pub fn clear_with(&mut self, blank: Cell) {
+        self.cells.fill(blank);
+        self.flags = LineFlags::default();
+    }
+
+    /// The text of the visible part of the line, with trailing blanks removed.
+    /// Wide characters appear once, their spacers are skipped, and combining
+    /// marks follow the character they're on.
+    pub fn text(&self) -> String {
+        let text = self.chars().map(|(c, _)| c).collect::<String>();
+        text.trim_end().to_owned()
+    }
+
+    /// The characters in the visible part of the line and their styles, as
+    /// they appear in [`Row::text`] but without trimming.
+    pub fn chars(&self) -> impl Iterator<Item = (char, &Style)> {
+        self.cells[..self.cols()]
+            .iter()
+            .filter(|cell| !cell.is_spacer())
+            .flat_map(|cell| {
+                std::iter::once(cell.text_char())
+                    .chain(cell.mark())
+                    .map(|c| (c, cell.style()))
+            })
+    }
+
+    /// The text of the columns in `cols`, like [`Row::text`] but without
+    /// trimming. Columns past the end of the visible part are ignored.
+    pub fn text_in(&self, cols: Range<usize>) -> String {
+        let end = cols.end.min(self.cols());
+        self.cells[cols.start.min(end)..end]
+            .iter()
+            .filter(|cell| !cell.is_spacer())
+            .flat_map(|cell| std::iter::once(cell.text_char()).chain(cell.mark()))
+            .collect()
+    }
+
+    /// The visible part of the line as runs of text that share a style, like
+    /// [`Row::chars`] grouped together.
+    pub fn spans(&self) -> Vec<(String, Style)> {
+        let mut spans: Vec<(String, Style)> = Vec::new();
+        for (c, style) in self.chars() {
+            match spans.last_mut() {
+                Some((text, last)) if last == style => text.push(c),
+                _ => spans.push((c.to_string(), *style)),
+            }
+        }
+        spans
+    }
+
+    fn resize(&mut self, cols: usize) {
+        self.cells.resize(cols, Cell::default());
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Grid {
+    rows: Vec<Row>,
+    size: ScreenSize,
+}
+
+impl Grid {
+    pub fn new(size: ScreenSize) -> Self {
+        Self {
+            rows: vec![Row::new(size.cols); size.rows],
+            size,
+        }
+    }
+
+    pub fn size(&self) -> ScreenSize {
+        self.size
+    }
+
+    pub fn rows(&self) -> &[Row] {
+        &self.rows
+    }
+
+    pub fn row(&self, row: usize) -> &Row {
+        &self.rows[row]
+    }
+
+    pub fn row_mut(&mut self, row: usize) -> &mut Row {
+        &mut self.rows[row]
+    }
+
+    pub fn cell(&self, point: ScreenPoint) -> &Cell {
+        &self.rows[point.row()].cells[point.col()]
+    }
+
+    pub fn cell_mut(&mut self, point: ScreenPoint) -> &mut Cell {
+        &mut self.rows[point.row()].cells[point.col()]
+    }
+
+    /// Resize the grid, keeping the top left of the contents in place.
+    pub fn resize(&mut self, size: ScreenSize) {
+        for row in self.rows.iter_mut() {
+            row.resize(size.cols);
+        }
+        self.rows.resize(size.rows, Row::new(size.cols));
+        self.size = size;
+    }
+
+    /// Move every line up by one, adding a blank line at the bottom. Returns the
+    /// line that was pushed off the top.
+    pub fn scroll_up(&mut self) -> Row {
+        self.scroll_region_up(0..self.size.rows, Cell::default())
+    }
+
+    /// Move the lines in `region` up by one, adding a line of `blank` cells at
+    /// the bottom of it. Returns the line that was pushed off the top of it.
+    pub fn scroll_region_up(&mut self, region: Range<usize>, blank: Cell) -> Row {
+        let top = self.rows.remove(region.start);
+        self.rows
+            .insert(region.end - 1, Row::filled(self.size.cols, blank));
+        top
+    }
+
+    /// Move the lines in `region` down by one, adding a line of `blank` cells
+    /// at the top of it. The line at the bottom of it is dropped.
+    pub fn scroll_region_down(&mut self, region: Range<usize>, blank: Cell) {
+        self.rows.remove(region.end - 1);
+        self.rows
+            .insert(region.start, Row::filled(self.size.cols, blank));
+    }
+
+    pub fn clear(&mut self) {
+        self.clear_with(Cell::default());
+    }
+
+    /// Clear every row, setting every cell to `blank`.
+    pub fn clear_with(&mut self, blank: Cell) {
+        for row in self.rows.iter_mut() {
+            row.clear_with(blank);
+        }
+    }
+}
+
+impl std::fmt::Display for Grid {
+    /// The visible text, one line per row.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, row) in self.rows.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", row.text())?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_grid_resize_keeps_contents() {
+    let mut grid = Grid::new(ScreenSize::new(4, 2));
+    *grid.cell_mut(ScreenPoint::clamped(1, 1, grid.size())) = Cell::new('x');
+    grid.resize(ScreenSize::new(6, 3));
+    assert_eq!(grid.to_string(), "\n x\n");
+    grid.resize(ScreenSize::new(1, 1));
+    assert_eq!(grid.to_string(), "");
+}
+
+#[test]
+fn test_char_width() {
+    assert_eq!(char_width('a'), 1);
+    assert_eq!(char_width('é'), 1);
+    assert_eq!(char_width('中'), 2);
+    assert_eq!(char_width('ア'), 2);
+    assert_eq!(char_width('한'), 2);
+    assert_eq!(char_width('Ａ'), 2);
+    assert_eq!(char_width('🦀'), 2);
+}
+
+#[test]
+fn test_double_width_row_cols() {
+    let mut row = Row::new(80);
+    assert_eq!(row.cols(), 80);
+    row.set_size(LineSize::DoubleHeightTop);
+    assert_eq!(row.cols(), 40);
+}
+
+#[test]
+fn test_row_text() {
+    let mut row = Row::new(8);
+    row.cells_mut()[0] = Cell::new('a');
+    row.cells_mut()[1] = Cell::new('中');
+    row.cells_mut()[2] = Cell::spacer(Style::default());
+    row.cells_mut()[3] = Cell::new('\x07');
+    row.cells_mut()[4] = Cell::new('b');
+    assert_eq!(row.text(), "a中\u{FFFD}b");
+}
+
+#[test]
+fn test_line_flags() {
+    let mut row = Row::new(8);
+    row.set_wrapped(true);
+    row.set_size(LineSize::DoubleHeightTop);
+    row.set_flags(LineFlags::PROMPT, true);
+    assert_eq!(row.size(), LineSize::DoubleHeightTop);
+    row.set_size(LineSize::DoubleWidth);
+    assert_eq!(row.size(), LineSize::DoubleWidth);
+    assert_eq!(
+        row.flags(),
+        LineFlags::WRAPPED | LineFlags::DOUBLE_WIDTH | LineFlags::PROMPT
+    );
+    row.clear();
+    assert!(row.flags().is_empty());
+}
+
+#[test]
+fn test_row_spans() {
+    let bold = Style {
+        bold: true,
+        ..Style::default()
+    };
+    let mut row = Row::new(6);
+    row.cells_mut()[0] = Cell::styled('a', bold);
+    row.cells_mut()[1] = Cell::styled('中', bold);
+    row.cells_mut()[2] = Cell::spacer(bold);
+    row.cells_mut()[3] = Cell::new('b');
+    assert_eq!(
+        row.spans(),
+        [
+            ("a中".to_owned(), bold),
+            ("b  ".to_owned(), Style::default())
+        ]
+    );
+}
@@ -1,7 +1,31 @@
-use std::os::fd::OwnedFd;
+use std::{
+    borrow::Cow,
+    collections::VecDeque,
+    path::PathBuf,
+    rc::Rc,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
-use crate::terminal::Terminal;
-use egui::{self, TextStyle, Vec2};
+use egui::{
+    self,
+    text::{LayoutJob, TextFormat},
+    TextStyle, Vec2,
+};
+use termulus::{
+    clock::{Clock, SystemClock},
+    coords::{AbsolutePoint, ScreenPoint, ScreenSize},
+    grid::{Cell, LineSize, Row},
+    input::{
+        self, Arrow, Key, KeyEvent, KeyRepeat, Modifiers, MouseButton, MouseEvent, MouseEventKind,
+        SyntheticRepeat, WheelDirection,
+    },
+    style::{Color, Palette, Style, UnderlineStyle},
+    terminal::{
+        caret_notation, AccessibleLine, ModeSummary, MouseProtocol, Selection, Terminal,
+        TerminalStats, WriteOrigin,
+    },
+};
 
 pub trait GetCharSize {
     fn get_char_size(&self, style: &TextStyle) -> Vec2;
@@ -11,7 +35,6 @@ impl GetCharSize for egui::Context {
     fn get_char_size(&self, style: &TextStyle) -> Vec2 {
         let font_id = self.style().text_styles[style].clone();
         self.fonts(|fonts| {
-            let height = font_id.size;
             let layout = fonts.layout(
                 "@".to_string(),
                 font_id,
@@ -19,42 +42,1653 @@ impl GetCharSize for egui::Context {
                 f32::INFINITY,
             );
 
-            Vec2::new(layout.mesh_bounds.width(), height)
+            // The advance and line height rather than the glyph bounds, so that
+            // cells line up with the text.
+            layout.rect.size()
         })
     }
 }
 
+/// Measures the time between writing input to the pty and the screen changing
+/// in response, e.g. a key press being echoed.
+pub struct LatencyMeter {
+    /// When the input was written, and the terminal generation at that time.
+    pending: Option<(Instant, u64)>,
+    samples: VecDeque<Duration>,
+    /// How long the terminal takes to answer DSR (`CSI 6n`) with a cursor
+    /// position report, which is the part of every sample that's spent in
+    /// the terminal rather than the pty and the application.
+    calibration: Option<Duration>,
+    clock: Box<dyn Clock>,
+}
+
+impl Default for LatencyMeter {
+    fn default() -> Self {
+        Self::new(SystemClock)
+    }
+}
+
+impl LatencyMeter {
+    const SAMPLES: usize = 10;
+
+    pub fn new(clock: impl Clock + 'static) -> Self {
+        Self {
+            pending: None,
+            samples: VecDeque::new(),
+            calibration: None,
+            clock: Box::new(clock),
+        }
+    }
+
+    /// Time `terminal` making the reply to a DSR, to take off the samples.
+    /// Nothing is sent to the application.
+    pub fn calibrate(&mut self, terminal: &Terminal) {
+        let start = self.clock.now();
+        let reply = terminal.cursor_position_report();
+        let elapsed = self.clock.now().saturating_duration_since(start);
+        // Anything but a cursor position report means the measurement is off.
+        self.calibration =
+            (reply.starts_with(b"\x1b[") && reply.ends_with(b"R")).then_some(elapsed);
+    }
+
+    pub fn calibration(&self) -> Option<Duration> {
+        self.calibration
+    }
+
+    /// Start timing, unless a measurement is already in flight.
+    pub fn input_sent(&mut self, generation: u64) {
+        if self.pending.is_none() {
+            self.pending = Some((self.clock.now(), generation));
+        }
+    }
+
+    /// Finish the pending measurement if the screen has changed since the input was sent.
+    pub fn screen_updated(&mut self, generation: u64) {
+        if let Some((sent, sent_generation)) = self.pending {
+            if generation != sent_generation {
+                self.pending = None;
+                let elapsed = self.clock.now().saturating_duration_since(sent);
+                let calibration = self.calibration.unwrap_or_default();
+                self.push_sample(elapsed.saturating_sub(calibration));
+            }
+        }
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    fn push_sample(&mut self, sample: Duration) {
+        if self.samples.len() == Self::SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    /// The rolling average of the last few samples.
+    pub fn average(&self) -> Option<Duration> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        Some(self.samples.iter().sum::<Duration>() / self.samples.len() as u32)
+    }
+}
+
+/// Options for the debug GUI, from the command line and the config file.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Show throughput, memory, and frame time in the status bar.
+    pub perf: bool,
+    /// Show a description of each escape sequence the application sends.
+    pub inspect: bool,
+    pub layout: Layout,
+    /// Close after this long, for running without anyone watching, e.g. in CI.
+    pub timeout: Option<Duration>,
+    /// Close as soon as the screen contains this text. If it times out first,
+    /// the exit code is 1.
+    pub expect: Option<String>,
+    /// Where to write the JSON snapshot of the screen when closing because of
+    /// `timeout` or `expect`, instead of stdout.
+    pub output: Option<PathBuf>,
+    /// Repeat held keys that the OS doesn't, from the config file.
+    pub key_repeat: Option<KeyRepeat>,
+    /// What the process should exit with once the GUI has closed.
+    pub exit_code: Rc<std::cell::Cell<i32>>,
+}
+
+/// How the panes in a tab are arranged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Layout {
+    /// Side by side, each as big as its screen.
+    #[default]
+    Columns,
+    /// Tiled to fill the window. Each new pane splits the largest one across
+    /// its longer side, at the golden ratio.
+    Auto,
+}
+
+/// Split `area` into `panes` tiles for [`Layout::Auto`], in pane order.
+fn auto_tile(area: egui::Rect, panes: usize) -> Vec<egui::Rect> {
+    const RATIO: f32 = 0.618_034;
+    let mut tiles = vec![area];
+    while tiles.len() < panes {
+        let i = largest_tile(&tiles);
+        let tile = tiles[i];
+        // The old pane keeps the bigger part, and the new one goes after it.
+        let (old, new) = if tile.width() >= tile.height() {
+            tile.split_left_right_at_x(tile.left() + tile.width() * RATIO)
+        } else {
+            tile.split_top_bottom_at_y(tile.top() + tile.height() * RATIO)
+        };
+        tiles[i] = old;
+        tiles.insert(i + 1, new);
+    }
+    tiles
+}
+
+/// The first of the tiles with the largest area, which is the next to be split.
+fn largest_tile(tiles: &[egui::Rect]) -> usize {
+    let mut largest = 0;
+    for (i, tile) in tiles.iter().enumerate() {
+        if tile.area() > tiles[largest].area() {
+            largest = i;
+        }
+    }
+    largest
+}
+
+/// The pty size for a pane shown in `area`: as many whole cells as fit. `None`
+/// if not even one does, e.g. while the window is minimized.
+fn pane_winsize(area: Vec2, char_size: Vec2) -> Option<nix::pty::Winsize> {
+    let cols = (area.x / char_size.x).floor();
+    let rows = (area.y / char_size.y).floor();
+    if cols < 1.0 || rows < 1.0 {
+        return None;
+    }
+    Some(nix::pty::Winsize {
+        ws_row: rows as u16,
+        ws_col: cols as u16,
+        ws_xpixel: (cols * char_size.x) as u16,
+        ws_ypixel: (rows * char_size.y) as u16,
+    })
+}
+
+/// The cell under `offset`, a position relative to the top left of the
+/// screen. Cells on double width rows are twice as wide, and positions off the
+/// screen are clamped, so a drag that ends outside is still released.
+fn cell_at(terminal: &Terminal, offset: Vec2, char_size: Vec2) -> ScreenPoint {
+    let size = terminal.size();
+    let row = ((offset.y / char_size.y).max(0.0) as usize).min(size.rows - 1);
+    let (cell_width, cols) = match terminal.visible_rows().nth(row) {
+        Some(row) if row.size().is_double_width() => (char_size.x * 2.0, row.cols()),
+        _ => (char_size.x, size.cols),
+    };
+    let col = ((offset.x / cell_width).max(0.0) as usize).min(cols - 1);
+    ScreenPoint::clamped(col, row, size)
+}
+
+/// Throughput over the last second, plus the time the last frame took.
+#[derive(Default)]
+pub struct PerfMeter {
+    samples: VecDeque<(Instant, TerminalStats)>,
+    frame_time: Duration,
+}
+
+impl PerfMeter {
+    const WINDOW: Duration = Duration::from_secs(1);
+
+    pub fn record(&mut self, stats: TerminalStats, frame_time: Duration) {
+        let now = Instant::now();
+        self.frame_time = frame_time;
+        self.samples.push_back((now, stats));
+        // Keep one sample older than the window so the rate covers all of it.
+        while self.samples.len() > 2 && now - self.samples[1].0 > Self::WINDOW {
+            self.samples.pop_front();
+        }
+    }
+
+    /// Bytes read and parser outputs per second.
+    pub fn rates(&self) -> (f64, f64) {
+        let (Some((start, first)), Some((end, last))) = (self.samples.front(), self.samples.back())
+        else {
+            return (0.0, 0.0);
+        };
+        let elapsed = (*end - *start).as_secs_f64();
+        if elapsed == 0.0 {
+            return (0.0, 0.0);
+        }
+        (
+            (last.bytes_read - first.bytes_read) as f64 / elapsed,
+            (last.outputs_parsed - first.outputs_parsed) as f64 / elapsed,
+        )
+    }
+}
+
+/// A color as `#rrggbb`, with its name or palette index if it has one.
+fn describe_color(color: Option<Color>, palette: &Palette) -> String {
+    let Some(color) = color else {
+        return "default".to_owned();
+    };
+    let (r, g, b) = palette.rgb(color);
+    let hex = format!("#{:02x}{:02x}{:02x}", r, g, b);
+    match (color.name(), color.index()) {
+        (Some(name), Some(index)) => format!("{} ({}, {})", hex, name, index),
+        (None, Some(index)) => format!("{} (index {})", hex, index),
+        _ => hex,
+    }
+}
+
+/// The hover tooltip for a cell, when inspecting.
+fn cell_tooltip(ui: &mut egui::Ui, cell: &Cell, palette: &Palette) {
+    let style = cell.style();
+    ui.label(format!("U+{:04X} {:?}", cell.char() as u32, cell.char()));
+    if let Some(mark) = cell.mark() {
+        ui.label(format!("with U+{:04X} {:?}", mark as u32, mark));
+    }
+    ui.label(format!("fg: {}", describe_color(style.fg, palette)));
+    ui.label(format!("bg: {}", describe_color(style.bg, palette)));
+    let attributes = style.attribute_names();
+    if attributes.is_empty() {
+        ui.label("attributes: none");
+    } else {
+        ui.label(format!("attributes: {}", attributes.join(", ")));
+    }
+}
+
+/// Short labels for the modes that are on, for the status bar.
+fn mode_badges(modes: ModeSummary) -> Vec<&'static str> {
+    let mut badges = Vec::new();
+    if modes.alternate_screen {
+        badges.push("ALT");
+    }
+    if modes.mouse != MouseProtocol::Off {
+        badges.push("MOUSE");
+    }
+    if modes.bracketed_paste {
+        badges.push("PASTE");
+    }
+    if modes.application_cursor_keys {
+        badges.push("APP CURSOR");
+    }
+    if !modes.cursor_visible {
+        badges.push("HIDDEN CURSOR");
+    }
+    if modes.synchronized_update {
+        badges.push("SYNC");
+    }
+    badges
+}
+
+/// A type of color blindness to simulate with the View menu, so developers can
+/// check that their applications are still readable with it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ColorBlindnessFilter {
+    /// Red-green, without M cones.
+    Deuteranopia,
+    /// Red, without L cones.
+    Protanopia,
+    /// Blue-yellow, without S cones.
+    Tritanopia,
+}
+
+impl ColorBlindnessFilter {
+    const ALL: [Self; 3] = [Self::Deuteranopia, Self::Protanopia, Self::Tritanopia];
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Deuteranopia => "Deuteranopia (red-green)",
+            Self::Protanopia => "Protanopia (red)",
+            Self::Tritanopia => "Tritanopia (blue-yellow)",
+        }
+    }
+
+    /// Simulate how an sRGB color (0 to 1) looks: convert it to the LMS
+    /// (cone response) color space, remove the missing cone's response by
+    /// deriving it from the other two, and convert back.
+    fn transform(self, color: [f32; 3]) -> [f32; 3] {
+        // The matrices are the ones used by Daltonize, from Viénot et al. (1999).
+        const RGB_TO_LMS: [[f32; 3]; 3] = [
+            [17.8824, 43.5161, 4.11935],
+            [3.45565, 27.1554, 3.86714],
+            [0.0299566, 0.184309, 1.46709],
+        ];
+        const LMS_TO_RGB: [[f32; 3]; 3] = [
+            [0.080_944_45, -0.130_504_41, 0.116_721_07],
+            [-0.010_248_534, 0.054_019_33, -0.113_614_71],
+            [-0.000_365_296_94, -0.004_121_615, 0.693_511_4],
+        ];
+        let simulate = match self {
+            Self::Deuteranopia => [[1.0, 0.0, 0.0], [0.494207, 0.0, 1.24827], [0.0, 0.0, 1.0]],
+            Self::Protanopia => [[0.0, 2.02344, -2.52581], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+            Self::Tritanopia => [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [-0.395913, 0.801109, 0.0]],
+        };
+        let multiply = |matrix: [[f32; 3]; 3], v: [f32; 3]| {
+            matrix.map(|row| row[0] * v[0] + row[1] * v[1] + row[2] * v[2])
+        };
+        // The matrices work on linear RGB.
+        let linear = color.map(|c| {
+            if c <= 0.04045 {
+                c / 12.92
+            } else {
+                ((c + 0.055) / 1.055).powf(2.4)
+            }
+        });
+        let lms = multiply(simulate, multiply(RGB_TO_LMS, linear));
+        multiply(LMS_TO_RGB, lms).map(|c| {
+            let c = c.clamp(0.0, 1.0);
+            if c <= 0.003_130_8 {
+                c * 12.92
+            } else {
+                1.055 * c.powf(1.0 / 2.4) - 0.055
+            }
+        })
+    }
+
+    fn apply(self, color: egui::Color32) -> egui::Color32 {
+        let [r, g, b] = self.transform([color.r(), color.g(), color.b()].map(|c| c as f32 / 255.0));
+        let [r, g, b] = [r, g, b].map(|c| (c * 255.0).round() as u8);
+        egui::Color32::from_rgba_unmultiplied(r, g, b, color.a())
+    }
+}
+
+/// Turns cell colors into the colors they are drawn with.
+struct CellColors<'a> {
+    palette: &'a Palette,
+    /// Used for text without a foreground color.
+    default_fg: egui::Color32,
+    /// Used for reversed text without a background color.
+    default_bg: egui::Color32,
+    filter: Option<ColorBlindnessFilter>,
+}
+
+impl CellColors<'_> {
+    fn get(&self, color: Color) -> egui::Color32 {
+        self.rgb(self.palette.rgb(color))
+    }
+
+    fn rgb(&self, (r, g, b): (u8, u8, u8)) -> egui::Color32 {
+        self.filter(egui::Color32::from_rgb(r, g, b))
+    }
+
+    fn filter(&self, color: egui::Color32) -> egui::Color32 {
+        match self.filter {
+            Some(filter) => filter.apply(color),
+            None => color,
+        }
+    }
+
+    /// The color text is drawn in, which is the background color if it's
+    /// reversed.
+    fn fg(&self, style: &Style) -> egui::Color32 {
+        if style.reverse {
+            self.resolve(style.bg, self.palette.background, self.default_bg)
+        } else {
+            self.resolve(style.fg, self.palette.foreground, self.default_fg)
+        }
+    }
+
+    /// The color behind the text, or `None` if it's the terminal's background.
+    fn bg(&self, style: &Style) -> Option<egui::Color32> {
+        if style.reverse {
+            Some(self.resolve(style.fg, self.palette.foreground, self.default_fg))
+        } else {
+            style.bg.map(|color| self.get(color))
+        }
+    }
+
+    fn resolve(
+        &self,
+        color: Option<Color>,
+        default: Option<(u8, u8, u8)>,
+        fallback: egui::Color32,
+    ) -> egui::Color32 {
+        match (color, default) {
+            (Some(color), _) => self.get(color),
+            (None, Some(rgb)) => self.rgb(rgb),
+            (None, None) => self.filter(fallback),
+        }
+    }
+
+    fn cursor(&self) -> egui::Color32 {
+        self.rgb(self.palette.cursor.unwrap_or((0x80, 0x80, 0x80)))
+    }
+}
+
+/// Paint one line of the grid into `rect`, the area covered by the row's cells.
+fn paint_row(
+    painter: &egui::Painter,
+    row: &Row,
+    rect: egui::Rect,
+    font_id: &egui::FontId,
+    colors: &CellColors,
+    char_size: Vec2,
+) {
+    let cells = &row.cells()[..row.cols()];
+    let (cell_width, font_id, letter_spacing, offset) = match row.size() {
+        LineSize::Normal => (char_size.x, font_id.clone(), 0.0, 0.0),
+        // egui can't stretch glyphs horizontally, so spread them out over
+        // both of their cells instead.
+        LineSize::DoubleWidth => (char_size.x * 2.0, font_id.clone(), char_size.x, 0.0),
+        // Paint the text at twice the size and only show the half that
+        // belongs on this row.
+        LineSize::DoubleHeightTop | LineSize::DoubleHeightBottom => {
+            let font_id = egui::FontId::new(font_id.size * 2.0, font_id.family.clone());
+            let offset = if row.size() == LineSize::DoubleHeightBottom {
+                -rect.height()
+            } else {
+                0.0
+            };
+            (char_size.x * 2.0, font_id, 0.0, offset)
+        }
+    };
+
+    let mut start = 0;
+    while start < cells.len() {
+        let bg = colors.bg(cells[start].style());
+        let len = cells[start..]
+            .iter()
+            .take_while(|cell| colors.bg(cell.style()) == bg)
+            .count();
+        if let Some(bg) = bg {
+            let min = rect.min + Vec2::new(start as f32 * cell_width, 0.0);
+            let bg_rect =
+                egui::Rect::from_min_size(min, Vec2::new(len as f32 * cell_width, rect.height()));
+            painter.rect_filled(bg_rect, 0.0, bg);
+        }
+        start += len;
+    }
+    let job = row_job(cells, &font_id, colors, letter_spacing);
+    let galley = painter.fonts(|fonts| fonts.layout_job(job));
+    painter.with_clip_rect(rect).galley(
+        rect.min + Vec2::new(0.0, offset),
+        galley,
+        colors.default_fg,
+    );
+
+    // There's no bold monospace font, so bold text is painted a second time,
+    // a pixel to the right. The other cells are blanked so it lines up.
+    if cells.iter().any(|cell| cell.style().bold) {
+        let bold: Vec<_> = cells
+            .iter()
+            .map(|cell| {
+                if cell.style().bold {
+                    *cell
+                } else {
+                    Cell::default()
+                }
+            })
+            .collect();
+        let job = row_job(&bold, &font_id, colors, letter_spacing);
+        let galley = painter.fonts(|fonts| fonts.layout_job(job));
+        painter.with_clip_rect(rect).galley(
+            rect.min + Vec2::new(1.0, offset),
+            galley,
+            colors.default_fg,
+        );
+    }
+}
+
+/// Keep what's written to `terminal` in `log`, in caret notation, for the
+/// inspector. Only the most recent writes are kept.
+fn tap_outgoing(terminal: &mut Terminal, log: &Arc<Mutex<VecDeque<String>>>) {
+    let log = Arc::clone(log);
+    terminal.set_outgoing_tap(move |origin, bytes| {
+        let mut log = log.lock().unwrap();
+        if log.len() == Terminal::INSPECTOR_LIMIT {
+            log.pop_front();
+        }
+        log.push_back(format!("{:?}: {}", origin, caret_notation(bytes)));
+    });
+}
+
+/// The text of a row's cells, ready for layout.
+///
+/// Some programs change the style before every character, even when it's
+/// the same, so sections that end up with the same format are merged to keep
+/// the layout as cheap as for text that was written in one go.
+fn row_job(
+    cells: &[Cell],
+    font_id: &egui::FontId,
+    colors: &CellColors,
+    letter_spacing: f32,
+) -> LayoutJob {
+    // Trailing blanks don't need laying out.
+    let text_len = cells
+        .iter()
+        .rposition(|cell| cell.char() != ' ')
+        .map_or(0, |i| i + 1);
+    let cells = &cells[..text_len];
+    let mut job = LayoutJob::default();
+    let mut start = 0;
+    while start < cells.len() {
+        let style = cells[start].style();
+        let len = cells[start..]
+            .iter()
+            .take_while(|cell| cell.style() == style)
+            .count();
+        let text = cells[start..start + len]
+            .iter()
+            .filter(|cell| !cell.is_spacer())
+            .flat_map(|cell| std::iter::once(cell.char()).chain(cell.mark()))
+            .collect::<String>();
+        let mut color = colors.fg(style);
+        if style.dim {
+            color = color.gamma_multiply(0.5);
+        }
+        // egui only draws solid lines, so every underline style gets one.
+        let underline = match style.underline {
+            UnderlineStyle::None => egui::Stroke::NONE,
+            _ => egui::Stroke::new(1.0, style.underline_color.map_or(color, |c| colors.get(c))),
+        };
+        let strikethrough = if style.strikethrough {
+            egui::Stroke::new(1.0, color)
+        } else {
+            egui::Stroke::NONE
+        };
+        let format = TextFormat {
+            font_id: font_id.clone(),
+            color,
+            italics: style.italic,
+            underline,
+            strikethrough,
+            extra_letter_spacing: letter_spacing,
+            ..Default::default()
+        };
+        match job.sections.last_mut() {
+            Some(last) if last.format == format => {
+                job.text.push_str(&text);
+                last.byte_range.end = job.text.len();
+            }
+            _ => job.append(&text, 0.0, format),
+        }
+        start += len;
+    }
+    job
+}
+
+/// The dialog for writing an escape sequence to the application by hand,
+/// opened with Ctrl+Shift+Escape.
+#[derive(Default)]
+struct SendDialog {
+    open: bool,
+    text: String,
+    /// Why the text couldn't be sent, shown under it.
+    error: Option<String>,
+}
+
+/// The terminal key for an egui key, if it's one that sends an escape
+/// sequence. Other keys are sent by their text.
+fn terminal_key(key: egui::Key) -> Option<Key> {
+    use egui::Key as K;
+    Some(match key {
+        K::Enter => Key::Enter,
+        K::Tab => Key::Tab,
+        K::Backspace => Key::Backspace,
+        K::Escape => Key::Escape,
+        K::ArrowUp => Key::Arrow(Arrow::Up),
+        K::ArrowDown => Key::Arrow(Arrow::Down),
+        K::ArrowRight => Key::Arrow(Arrow::Right),
+        K::ArrowLeft => Key::Arrow(Arrow::Left),
+        K::Home => Key::Home,
+        K::End => Key::End,
+        K::PageUp => Key::PageUp,
+        K::PageDown => Key::PageDown,
+        K::Insert => Key::Insert,
+        K::Delete => Key::Delete,
+        K::F1 => Key::F(1),
+        K::F2 => Key::F(2),
+        K::F3 => Key::F(3),
+        K::F4 => Key::F(4),
+        K::F5 => Key::F(5),
+        K::F6 => Key::F(6),
+        K::F7 => Key::F(7),
+        K::F8 => Key::F(8),
+        K::F9 => Key::F(9),
+        K::F10 => Key::F(10),
+        K::F11 => Key::F(11),
+        K::F12 => Key::F(12),
+        _ => Key::Char(key_char(key)?),
+    })
+}
+
+/// The character a key types without Shift, so Ctrl can be sent with it.
+fn key_char(key: egui::Key) -> Option<char> {
+    use egui::Key as K;
+    Some(match key {
+        K::Space => ' ',
+        K::OpenBracket => '[',
+        K::CloseBracket => ']',
+        K::Backslash => '\\',
+        K::Backtick => '`',
+        K::Minus => '-',
+        // Letters and digits are named by the character.
+        _ => match key.name().chars().collect::<Vec<_>>()[..] {
+            [c] => c.to_ascii_lowercase(),
+            _ => return None,
+        },
+    })
+}
+
+/// A tab that is being dragged to a new position in the tab bar.
+#[derive(Debug, Clone, Copy)]
+struct TabDrag {
+    index: usize,
+    /// How far the tab has been dragged horizontally.
+    offset: f32,
+}
+
+/// Move the tab at `from` so that it is inserted before the tab that is
+/// currently at `to` (or at the end if `to == tabs.len()`). Returns the new
+/// index of the tab that was at `active`, so the same one stays focused.
+fn move_tab<T>(tabs: &mut Vec<T>, active: usize, from: usize, to: usize) -> usize {
+    // Removing the tab shifts everything after it down by one.
+    let to = if to > from { to - 1 } else { to };
+    let tab = tabs.remove(from);
+    tabs.insert(to, tab);
+    if active == from {
+        to
+    } else {
+        let active = if active > from { active - 1 } else { active };
+        if active >= to {
+            active + 1
+        } else {
+            active
+        }
+    }
+}
+
+/// Where a dragged tab would be inserted, given the rects of all the tabs.
+fn tab_insertion_index(rects: &[egui::Rect], drag: TabDrag) -> usize {
+    let x = rects[drag.index].center().x + drag.offset;
+    rects.iter().filter(|rect| rect.center().x < x).count()
+}
+
+/// The Ctrl+F search bar.
+#[derive(Default)]
+struct SearchBar {
+    open: bool,
+    /// Whether the query field has keyboard focus, in which case typing goes
+    /// to it instead of the terminal.
+    has_focus: bool,
+    query: String,
+    regex: bool,
+    /// Why the query couldn't be searched for, shown instead of the match count.
+    error: Option<String>,
+    matches: Vec<Selection>,
+    /// The selected match, counting back from the newest one.
+    current: usize,
+}
+
+impl SearchBar {
+    /// Search the terminal again, since the query or the output may have changed.
+    fn update(&mut self, terminal: &Terminal) {
+        match terminal.history_search(&self.query, self.regex) {
+            Ok(matches) => {
+                self.matches = matches;
+                self.error = None;
+            }
+            Err(e) => {
+                self.matches.clear();
+                self.error = Some(e.to_string());
+            }
+        }
+        self.current = self.current.min(self.matches.len().saturating_sub(1));
+    }
+
+    /// Select the next older (or newer, with `back`) match and scroll to it.
+    fn step(&mut self, terminal: &mut Terminal, back: bool) {
+        let len = self.matches.len();
+        if len == 0 {
+            return;
+        }
+        self.current = if back {
+            (self.current + len - 1) % len
+        } else {
+            (self.current + 1) % len
+        };
+        self.select(terminal);
+    }
+
+    fn select(&self, terminal: &mut Terminal) {
+        let Some(found) = self.matches.iter().rev().nth(self.current) else {
+            return;
+        };
+        terminal.set_selection(Some(*found));
+        terminal.scroll_to_line(found.start().line);
+    }
+}
+
+/// Highlight the part of a row covered by the selection.
+fn paint_selection(
+    painter: &egui::Painter,
+    row: &Row,
+    line: usize,
+    selection: Selection,
+    rect: egui::Rect,
+    color: egui::Color32,
+    char_size: Vec2,
+) {
+    if line < selection.start().line || line > selection.end().line {
+        return;
+    }
+    let start = if line == selection.start().line {
+        selection.start().col
+    } else {
+        0
+    };
+    let end = if line == selection.end().line {
+        selection.end().col + 1
+    } else {
+        row.cols()
+    };
+    let cell_width = if row.size().is_double_width() {
+        char_size.x * 2.0
+    } else {
+        char_size.x
+    };
+    painter.rect_filled(
+        egui::Rect::from_x_y_ranges(
+            rect.left() + start as f32 * cell_width..=rect.left() + end as f32 * cell_width,
+            rect.y_range(),
+        ),
+        0.0,
+        color,
+    );
+}
+
+/// The screen's text for the accessibility tree, kept up to date with the
+/// terminal's damage rather than rebuilt every frame.
+#[derive(Default)]
+struct AccessibleScreen {
+    /// The tab and pane the lines are for.
+    pane: (usize, usize),
+    lines: Vec<AccessibleLine>,
+}
+
+impl AccessibleScreen {
+    fn update(&mut self, terminal: &mut Terminal, pane: (usize, usize)) {
+        let damage = terminal.take_damage();
+        if damage.full || pane != self.pane || self.lines.len() != terminal.size().rows {
+            self.pane = pane;
+            self.lines = terminal.accessible_lines();
+        } else {
+            for row in damage.rows {
+                self.lines[row] = terminal.accessible_line(row);
+            }
+        }
+    }
+
+    /// Rebuild everything on the next update, e.g. because the pane was closed.
+    fn invalidate(&mut self) {
+        self.lines.clear();
+    }
+}
+
+/// The AccessKit node id egui uses for `id`, which egui doesn't expose. Ids
+/// are already hashes, so hashing one with egui's id hasher gives back its value.
+fn accesskit_id(id: egui::Id) -> egui::accesskit::NodeId {
+    use std::hash::BuildHasher;
+    egui::accesskit::NodeId(egui::IdMap::<()>::default().hasher().hash_one(id))
+}
+
+/// Add the screen to the accessibility tree: a terminal node with a text box
+/// per row, and the caret at the cursor.
+fn publish_accessibility(
+    ctx: &egui::Context,
+    id: egui::Id,
+    rect: egui::Rect,
+    char_size: Vec2,
+    lines: &[AccessibleLine],
+    cursor: Option<(usize, usize)>,
+) {
+    use egui::accesskit::{Rect, Role, TextDirection, TextPosition, TextSelection};
+
+    fn bounds(rect: egui::Rect) -> Rect {
+        Rect {
+            x0: rect.min.x.into(),
+            y0: rect.min.y.into(),
+            x1: rect.max.x.into(),
+            y1: rect.max.y.into(),
+        }
+    }
+
+    let enabled = ctx
+        .accesskit_node_builder(id, |node| {
+            node.set_role(Role::Terminal);
+            node.set_bounds(bounds(rect));
+        })
+        .is_some();
+    if !enabled {
+        return;
+    }
+    ctx.with_accessibility_parent(id, || {
+        for (i, line) in lines.iter().enumerate() {
+            let row_rect = egui::Rect::from_min_size(
+                rect.min + Vec2::new(0.0, i as f32 * char_size.y),
+                Vec2::new(rect.width(), char_size.y),
+            );
+            ctx.accesskit_node_builder(id.with(i), |node| {
+                node.set_role(Role::InlineTextBox);
+                node.set_bounds(bounds(row_rect));
+                node.set_text_direction(TextDirection::LeftToRight);
+                node.set_value(line.text.as_str());
+                let chars = line.text.char_indices();
+                node.set_character_lengths(
+                    chars
+                        .clone()
+                        .map(|(_, c)| c.len_utf8() as u8)
+                        .collect::<Vec<_>>(),
+                );
+                node.set_character_positions(
+                    chars
+                        .map(|(offset, _)| line.offset_to_column(offset) as f32 * char_size.x)
+                        .collect::<Vec<_>>(),
+                );
+                node.set_character_widths(vec![char_size.x; line.text.chars().count()]);
+            });
+        }
+    });
+    if let Some((row, col)) = cursor {
+        let line = &lines[row];
+        let offset = line.column_to_offset(col);
+        let caret = TextPosition {
+            node: accesskit_id(id.with(row)),
+            character_index: line.text[..offset].chars().count(),
+        };
+        ctx.accesskit_node_builder(id, |node| {
+            node.set_text_selection(TextSelection {
+                anchor: caret,
+                focus: caret,
+            });
+        });
+    }
+}
+
+/// A tab with one or more terminals in it, shown side by side.
+struct Tab<'a> {
+    panes: Vec<Terminal<'a>>,
+    /// The pane that gets input.
+    focused: usize,
+}
+
+impl<'a> Tab<'a> {
+    fn new(terminal: Terminal<'a>) -> Self {
+        Self {
+            panes: vec![terminal],
+            focused: 0,
+        }
+    }
+
+    fn terminal(&self) -> &Terminal<'a> {
+        &self.panes[self.focused]
+    }
+
+    fn terminal_mut(&mut self) -> &mut Terminal<'a> {
+        &mut self.panes[self.focused]
+    }
+
+    /// Read from every pane, closing the ones whose shell has exited. Returns
+    /// whether any were closed.
+    fn read(&mut self) -> bool {
+        let mut closed = false;
+        let mut i = 0;
+        while i < self.panes.len() {
+            let bytes_read = self.panes[i].stats().bytes_read;
+            if self.panes[i].read().is_ok() {
+                let pane = &mut self.panes[i];
+                // New output brings a pane that's scrolled back down to the screen.
+                if pane.stats().bytes_read != bytes_read {
+                    pane.scroll_display(-(pane.display_offset() as isize));
+                }
+                // Nothing reacts to events yet (the status bar reads the
+                // modes every frame), but they shouldn't pile up.
+                pane.events().for_each(drop);
+                i += 1;
+                continue;
+            }
+            self.panes.remove(i);
+            closed = true;
+            if self.focused > i || self.focused == self.panes.len() {
+                self.focused = self.focused.saturating_sub(1);
+            }
+        }
+        closed
+    }
+}
+
+/// Paint a terminal's screen into the next space in `ui`, which is sized to
+/// fit the grid. The cursor is hollow unless the terminal is `focused`.
+fn show_terminal(
+    ui: &mut egui::Ui,
+    terminal: &Terminal,
+    char_size: Vec2,
+    focused: bool,
+    inspect: bool,
+    accessible: Option<&[AccessibleLine]>,
+    filter: Option<ColorBlindnessFilter>,
+) -> egui::Response {
+    let grid = terminal.grid();
+    let size = grid.size();
+    let (rect, response) = ui.allocate_exact_size(
+        Vec2::new(size.cols as f32, size.rows as f32) * char_size,
+        egui::Sense::click(),
+    );
+    let painter = ui.painter_at(rect);
+    let font_id = ui.style().text_styles[&TextStyle::Monospace].clone();
+    let colors = CellColors {
+        palette: terminal.palette(),
+        default_fg: ui.visuals().text_color(),
+        default_bg: ui.visuals().panel_fill,
+        filter,
+    };
+    if let Some(background) = terminal.palette().background {
+        painter.rect_filled(rect, 0.0, colors.rgb(background));
+    }
+    let selection_color = ui.visuals().selection.bg_fill;
+    for (i, row) in terminal.visible_rows().enumerate() {
+        let row_rect = egui::Rect::from_min_size(
+            rect.min + Vec2::new(0.0, i as f32 * char_size.y),
+            Vec2::new(rect.width(), char_size.y),
+        );
+        if let Some(selection) = terminal.selection() {
+            let line = terminal.visible_to_absolute(i);
+            paint_selection(
+                &painter,
+                row,
+                line,
+                selection,
+                row_rect,
+                selection_color,
+                char_size,
+            );
+        }
+        paint_row(&painter, row, row_rect, &font_id, &colors, char_size);
+    }
+
+    if let Some(lines) = accessible {
+        publish_accessibility(
+            ui.ctx(),
+            response.id,
+            rect,
+            char_size,
+            lines,
+            terminal.visible_cursor(),
+        );
+    }
+
+    if inspect {
+        let hovered = response.hover_pos().and_then(|pos| {
+            let pos = pos - rect.min;
+            let row = terminal
+                .visible_rows()
+                .nth((pos.y / char_size.y) as usize)?;
+            let cell_width = if row.size().is_double_width() {
+                char_size.x * 2.0
+            } else {
+                char_size.x
+            };
+            row.cells()[..row.cols()].get((pos.x / cell_width) as usize)
+        });
+        if let Some(cell) = hovered {
+            response.clone().on_hover_ui_at_pointer(|ui| {
+                cell_tooltip(ui, cell, terminal.palette());
+            });
+        }
+    }
+
+    let cursor = terminal.cursor_pos();
+    let cell_width = if grid.row(cursor.y()).size().is_double_width() {
+        char_size.x * 2.0
+    } else {
+        char_size.x
+    };
+    let cursor_rect = egui::Rect::from_min_size(
+        rect.min
+            + Vec2::new(
+                cursor.x() as f32 * cell_width,
+                cursor.y() as f32 * char_size.y,
+            ),
+        Vec2::new(cell_width, char_size.y),
+    );
+    // Applications hide it with DECTCEM (`CSI ? 25 l`).
+    if terminal.modes().cursor_visible {
+        if focused {
+            painter.rect_filled(cursor_rect, 0.0, colors.cursor());
+        } else {
+            painter.rect_stroke(cursor_rect, 0.0, (1.0, colors.cursor()));
+        }
+    }
+    response
+}
+
+/// The colors for new terminals, matching the egui theme so that applications
+/// asking for the default colors get the ones that are shown.
+fn theme(visuals: &egui::Visuals) -> Palette {
+    let rgb = |color: egui::Color32| Some((color.r(), color.g(), color.b()));
+    let mut palette = Palette::default();
+    palette.foreground = rgb(visuals.text_color());
+    palette.background = rgb(visuals.panel_fill);
+    palette.cursor = rgb(egui::Color32::GRAY);
+    palette
+}
+
 pub struct TermGui<'a> {
-    terminal: Terminal<'a>,
+    /// The tabs, in the order they are shown.
+    tabs: Vec<Tab<'a>>,
+    active: usize,
+    tab_drag: Option<TabDrag>,
+    search: SearchBar,
+    send: SendDialog,
+    accessible: AccessibleScreen,
+    /// Set from the View menu.
+    color_filter: Option<ColorBlindnessFilter>,
     char_size: Option<Vec2>,
+    latency: LatencyMeter,
+    /// Wheel motion that hasn't added up to a whole line yet.
+    scroll_remainder: f32,
+    /// Where the mouse was pressed to select text, while it's held.
+    select_from: Option<AbsolutePoint>,
+    options: Options,
+    theme: Palette,
+    perf: PerfMeter,
+    /// When the GUI started, for [`Options::timeout`].
+    started: Instant,
+    key_repeat: SyntheticRepeat,
+    /// What was written to the applications, for the inspector.
+    outgoing: Arc<Mutex<VecDeque<String>>>,
 }
 
 impl<'a> TermGui<'a> {
-    pub fn new(cc: &eframe::CreationContext<'_>, fd: OwnedFd) -> Self {
+    pub fn new(
+        cc: &eframe::CreationContext<'_>,
+        mut terminal: Terminal<'a>,
+        options: Options,
+    ) -> Self {
         cc.egui_ctx.style_mut(|style| {
             style.override_text_style = Some(TextStyle::Monospace);
         });
+        let theme = theme(&cc.egui_ctx.style().visuals);
+        let outgoing = Arc::default();
+        terminal.set_inspect(options.inspect);
+        terminal.set_theme(theme.clone());
+        if options.inspect {
+            tap_outgoing(&mut terminal, &outgoing);
+        }
         Self {
-            terminal: Terminal::new(fd),
+            tabs: vec![Tab::new(terminal)],
+            active: 0,
+            tab_drag: None,
+            search: SearchBar::default(),
+            send: SendDialog::default(),
+            accessible: AccessibleScreen::default(),
+            color_filter: None,
             char_size: None,
+            latency: LatencyMeter::default(),
+            scroll_remainder: 0.0,
+            select_from: None,
+            options,
+            theme,
+            perf: PerfMeter::default(),
+            started: Instant::now(),
+            key_repeat: SyntheticRepeat::default(),
+            outgoing,
+        }
+    }
+
+    fn terminal(&self) -> &Terminal<'a> {
+        self.tabs[self.active].terminal()
+    }
+
+    fn terminal_mut(&mut self) -> &mut Terminal<'a> {
+        self.tabs[self.active].terminal_mut()
+    }
+
+    fn new_tab(&mut self) {
+        match crate::shell().spawn() {
+            Ok(mut terminal) => {
+                terminal.set_inspect(self.options.inspect);
+                terminal.set_theme(self.theme.clone());
+                if self.options.inspect {
+                    tap_outgoing(&mut terminal, &self.outgoing);
+                }
+                self.tabs.push(Tab::new(terminal));
+                self.active = self.tabs.len() - 1;
+            }
+            Err(e) => eprintln!("failed to open a new tab: {}", e),
+        }
+    }
+
+    /// Add a pane to the active tab and focus it. With [`Layout::Columns`] it
+    /// goes next to the focused pane, with [`Layout::Auto`] next to the pane
+    /// that is split to make room for it.
+    fn new_pane(&mut self, mut terminal: Terminal<'a>) {
+        if self.options.inspect {
+            tap_outgoing(&mut terminal, &self.outgoing);
+        }
+        let tab = &mut self.tabs[self.active];
+        let index = match self.options.layout {
+            Layout::Columns => tab.focused + 1,
+            // Which tile is split doesn't depend on the size of the window.
+            Layout::Auto => {
+                let tiles = auto_tile(
+                    egui::Rect::from_min_size(egui::Pos2::ZERO, Vec2::splat(1.0)),
+                    tab.panes.len(),
+                );
+                largest_tile(&tiles) + 1
+            }
+        };
+        tab.panes.insert(index, terminal);
+        tab.focused = index;
+    }
+
+    /// Open a pane with a new shell, starting from the same screen as the
+    /// focused one, so the two can go different ways from there.
+    fn branch(&mut self) {
+        let frozen = self.terminal().freeze();
+        match frozen.thaw(crate::shell()) {
+            Ok(terminal) => self.new_pane(terminal),
+            Err(e) => eprintln!("failed to branch the terminal: {}", e),
+        }
+    }
+
+    fn tab_bar(&mut self, ui: &mut egui::Ui) {
+        let mut rects = Vec::with_capacity(self.tabs.len());
+        let mut dropped = None;
+        ui.horizontal(|ui| {
+            ui.menu_button("View", |ui| {
+                ui.menu_button("Simulate Color Blindness", |ui| {
+                    ui.radio_value(&mut self.color_filter, None, "Off");
+                    for filter in ColorBlindnessFilter::ALL {
+                        ui.radio_value(&mut self.color_filter, Some(filter), filter.name());
+                    }
+                });
+            });
+            ui.separator();
+            for (i, tab) in self.tabs.iter().enumerate() {
+                let title = match tab.terminal().title() {
+                    "" => format!("Tab {}", i + 1),
+                    title => title.to_owned(),
+                };
+                let response = ui
+                    .add(egui::SelectableLabel::new(i == self.active, title))
+                    .interact(egui::Sense::drag());
+                if response.clicked() {
+                    self.active = i;
+                }
+                if response.drag_started() {
+                    self.tab_drag = Some(TabDrag {
+                        index: i,
+                        offset: 0.0,
+                    });
+                }
+                if let Some(drag) = self.tab_drag.as_mut().filter(|drag| drag.index == i) {
+                    drag.offset += response.drag_delta().x;
+                    if response.drag_released() {
+                        dropped = Some(*drag);
+                    }
+                }
+                rects.push(response.rect);
+            }
+            if ui.button("+").clicked() {
+                self.new_tab();
+            }
+        });
+
+        if let Some(drag) = dropped {
+            self.tab_drag = None;
+            let to = tab_insertion_index(&rects, drag);
+            self.active = move_tab(&mut self.tabs, self.active, drag.index, to);
+        } else if let Some(drag) = self.tab_drag {
+            // Show where the tab would go if it was dropped now.
+            let to = tab_insertion_index(&rects, drag);
+            let x = match to {
+                0 => rects[0].left(),
+                to => rects[to - 1].right(),
+            };
+            let y = rects[drag.index].y_range();
+            ui.painter().vline(x, y, ui.visuals().selection.stroke);
+        }
+    }
+
+    fn search_bar(&mut self, ui: &mut egui::Ui, focus: bool) {
+        let mut changed = false;
+        ui.horizontal(|ui| {
+            let response = ui.text_edit_singleline(&mut self.search.query);
+            if focus {
+                response.request_focus();
+            }
+            changed |= response.changed();
+            if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                let back = ui.input(|i| i.modifiers.shift);
+                self.search.update(self.tabs[self.active].terminal());
+                self.search
+                    .step(self.tabs[self.active].terminal_mut(), back);
+                response.request_focus();
+            }
+            self.search.has_focus = response.has_focus();
+
+            #[cfg(feature = "regex")]
+            if ui
+                .selectable_label(self.search.regex, ".*")
+                .on_hover_text("Regex")
+                .clicked()
+            {
+                self.search.regex = !self.search.regex;
+                changed = true;
+            }
+
+            match &self.search.error {
+                Some(error) => {
+                    ui.colored_label(ui.visuals().error_fg_color, error);
+                }
+                None if self.search.matches.is_empty() => {
+                    ui.label("No matches");
+                }
+                None => {
+                    ui.label(format!(
+                        "{}/{}",
+                        self.search.current + 1,
+                        self.search.matches.len()
+                    ));
+                }
+            }
+
+            if ui.button("x").clicked() || ui.input(|i| i.key_pressed(egui::Key::Escape)) {
+                self.search.open = false;
+                self.search.has_focus = false;
+                self.terminal_mut().set_selection(None);
+            }
+        });
+        // Keep up with new output, as well as the query.
+        self.search.update(self.tabs[self.active].terminal());
+        if changed {
+            self.search.current = 0;
+            self.search.select(self.tabs[self.active].terminal_mut());
+        }
+    }
+
+    fn send_dialog(&mut self, ctx: &egui::Context, focus: bool) {
+        egui::Window::new("Send escape sequence")
+            .title_bar(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, Vec2::ZERO)
+            .show(ctx, |ui| {
+                ui.label("Send escape sequence");
+                let response = ui.text_edit_singleline(&mut self.send.text);
+                if focus {
+                    response.request_focus();
+                }
+                if response.changed() {
+                    self.send.error = None;
+                }
+                ui.weak("\\e for ESC, \\n for a newline, \\xHH for any byte");
+                if let Some(error) = &self.send.error {
+                    ui.colored_label(ui.visuals().error_fg_color, error);
+                }
+                if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                    match input::unescape(&self.send.text) {
+                        Ok(bytes) => {
+                            let terminal = self.terminal_mut();
+                            if terminal.write_from(WriteOrigin::Injected, &bytes).is_ok() {
+                                self.latency.input_sent(self.terminal().generation());
+                            }
+                            self.send.open = false;
+                        }
+                        Err(e) => {
+                            self.send.error = Some(e.to_string());
+                            response.request_focus();
+                        }
+                    }
+                }
+                if ui.input(|i| i.key_pressed(egui::Key::Escape)) {
+                    self.send.open = false;
+                }
+            });
+    }
+
+    /// The exit code to close with, if [`Options::expect`] was found or
+    /// [`Options::timeout`] has passed.
+    fn finished(&self) -> Option<i32> {
+        if let Some(expect) = &self.options.expect {
+            if self.terminal().grid().to_string().contains(expect.as_str()) {
+                return Some(0);
+            }
+        }
+        let timeout = self.options.timeout?;
+        let code = if self.options.expect.is_some() { 1 } else { 0 };
+        (self.started.elapsed() >= timeout).then_some(code)
+    }
+
+    /// Write the snapshot of the screen and close.
+    fn finish(&mut self, ctx: &egui::Context, mut code: i32) {
+        let snapshot = self.terminal().snapshot_json();
+        match &self.options.output {
+            Some(path) => {
+                if let Err(e) = std::fs::write(path, snapshot) {
+                    eprintln!("failed to write {}: {}", path.display(), e);
+                    code = 1;
+                }
+            }
+            None => print!("{}", snapshot),
+        }
+        self.options.exit_code.set(code);
+        ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+    }
+
+    /// How many lines a wheel event scrolls by, keeping track of partial lines.
+    fn wheel_lines(
+        &mut self,
+        unit: egui::MouseWheelUnit,
+        delta: Vec2,
+    ) -> Option<(WheelDirection, usize)> {
+        let char_size = self.char_size?;
+        self.scroll_remainder += match unit {
+            egui::MouseWheelUnit::Point => delta.y / char_size.y,
+            egui::MouseWheelUnit::Line => delta.y,
+            egui::MouseWheelUnit::Page => delta.y * self.terminal().size().rows as f32,
+        };
+        let lines = self.scroll_remainder.trunc();
+        self.scroll_remainder -= lines;
+        if lines == 0.0 {
+            return None;
+        }
+        let direction = if lines > 0.0 {
+            WheelDirection::Up
+        } else {
+            WheelDirection::Down
+        };
+        Some((direction, lines.abs() as usize))
+    }
+
+    /// Send the mouse to the focused pane, which is at `rect`, if the
+    /// application wants it.
+    fn mouse_input(&mut self, ui: &egui::Ui, rect: egui::Rect) {
+        let char_size = *self.char_size.as_ref().expect("char size to have been set");
+        let mut positions = Vec::new();
+        let mut wheel = Vec::new();
+        ui.input(|state| {
+            let modifiers = Modifiers {
+                shift: state.modifiers.shift,
+                alt: state.modifiers.alt,
+                ctrl: state.modifiers.ctrl,
+            };
+            for event in state.events.iter() {
+                let (kind, pos) = match *event {
+                    egui::Event::PointerButton {
+                        pos,
+                        button,
+                        pressed,
+                        ..
+                    } => {
+                        let button = match button {
+                            egui::PointerButton::Primary => MouseButton::Left,
+                            egui::PointerButton::Middle => MouseButton::Middle,
+                            egui::PointerButton::Secondary => MouseButton::Right,
+                            _ => continue,
+                        };
+                        if !pressed {
+                            (MouseEventKind::Release(button), pos)
+                        } else if rect.contains(pos) {
+                            (MouseEventKind::Press(button), pos)
+                        } else {
+                            continue;
+                        }
+                    }
+                    egui::Event::PointerMoved(pos)
+                        if rect.contains(pos) || state.pointer.any_down() =>
+                    {
+                        (MouseEventKind::Motion, pos)
+                    }
+                    egui::Event::MouseWheel { unit, delta, .. } => {
+                        let Some(pos) = state.pointer.hover_pos().filter(|pos| rect.contains(*pos))
+                        else {
+                            continue;
+                        };
+                        if let Some((direction, lines)) = self.wheel_lines(unit, delta) {
+                            wheel.push((direction, lines, pos, modifiers));
+                        }
+                        continue;
+                    }
+                    _ => continue,
+                };
+                positions.push((kind, pos, modifiers));
+            }
+        });
+
+        // Cells are looked up once the input is read, as that borrows self.
+        let point = |pos: egui::Pos2| cell_at(self.terminal(), pos - rect.min, char_size);
+        let mut events: Vec<_> = positions
+            .into_iter()
+            .map(|(kind, pos, modifiers)| MouseEvent {
+                kind,
+                point: point(pos),
+                modifiers,
+            })
+            .collect();
+        let wheel: Vec<_> = wheel
+            .into_iter()
+            .map(|(direction, lines, pos, modifiers)| (direction, lines, point(pos), modifiers))
+            .collect();
+
+        for (direction, lines, point, modifiers) in wheel {
+            if self.terminal().modes().mouse == MouseProtocol::Off {
+                match input::encode_wheel(direction, lines, self.terminal().modes()) {
+                    Some(bytes) => {
+                        // The tab is closed on the next read if this fails.
+                        if self.terminal_mut().write(&bytes).is_ok() {
+                            self.latency.input_sent(self.terminal().generation());
+                        }
+                    }
+                    // Otherwise the wheel moves through the scrollback.
+                    None => self.terminal_mut().scroll_display(match direction {
+                        WheelDirection::Up => lines as isize,
+                        WheelDirection::Down => -(lines as isize),
+                    }),
+                }
+                continue;
+            }
+            events.extend((0..lines).map(|_| MouseEvent {
+                kind: MouseEventKind::Wheel(direction),
+                point,
+                modifiers,
+            }));
+        }
+        for event in events {
+            match self.terminal_mut().mouse_input(event) {
+                Ok(true) => self.latency.input_sent(self.terminal().generation()),
+                Ok(false)
+                    if event.kind == MouseEventKind::Press(MouseButton::Left)
+                        && event.modifiers == Modifiers::default() =>
+                {
+                    // Clicking in the command being typed moves the cursor there.
+                    let point = event.point;
+                    // Dragging from here selects text.
+                    let line = self.terminal().visible_to_absolute(point.row());
+                    self.select_from = Some(AbsolutePoint::new(line, point.col()));
+                    self.terminal_mut().set_selection(None);
+                    if let Some(bytes) = self
+                        .terminal()
+                        .arrows_to_move_cursor_to(point.col(), point.row())
+                    {
+                        if self.terminal_mut().write(&bytes).is_ok() {
+                            self.latency.input_sent(self.terminal().generation());
+                        }
+                    }
+                }
+                Ok(false) if event.kind == MouseEventKind::Motion => {
+                    let Some(from) = self.select_from else {
+                        continue;
+                    };
+                    let line = self.terminal().visible_to_absolute(event.point.row());
+                    let to = AbsolutePoint::new(line, event.point.col());
+                    // A click without a drag doesn't select anything.
+                    if to != from || self.terminal().selection().is_some() {
+                        let selection = Selection::new(from, to);
+                        self.terminal_mut().set_selection(Some(selection));
+                    }
+                }
+                Ok(false) if event.kind == MouseEventKind::Release(MouseButton::Left) => {
+                    self.select_from = None;
+                }
+                _ => {}
+            }
         }
     }
 
     fn init(&mut self, ctx: &egui::Context) {
         self.char_size = Some(ctx.get_char_size(&TextStyle::Monospace));
+        self.latency.calibrate(self.tabs[self.active].terminal());
     }
 }
 
 impl<'a> eframe::App for TermGui<'a> {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        if let None = self.char_size {
+        let frame_start = Instant::now();
+        if self.char_size.is_none() {
             self.init(ctx);
             println!("proportions: {:?}\n", self.char_size);
         }
-        let Ok(()) = self.terminal.read() else {
+        // Keep reading from the tabs in the background too, so their output
+        // doesn't back up. A pane whose shell has exited is closed, and so is a
+        // tab without any panes left.
+        let mut i = 0;
+        while i < self.tabs.len() {
+            if self.tabs[i].read() {
+                self.accessible.invalidate();
+            }
+            if !self.tabs[i].panes.is_empty() {
+                i += 1;
+                continue;
+            }
+            self.tabs.remove(i);
+            if self.active > i || self.active == self.tabs.len() {
+                self.active = self.active.saturating_sub(1);
+            }
+            self.tab_drag = None;
+        }
+        if self.tabs.is_empty() {
             ctx.send_viewport_cmd(egui::ViewportCommand::Close);
             return;
-        };
+        }
+        if let Some(code) = self.finished() {
+            self.finish(ctx, code);
+            return;
+        }
+        if let Some(timeout) = self.options.timeout {
+            ctx.request_repaint_after(timeout.saturating_sub(self.started.elapsed()));
+        }
+        if self.options.expect.is_some() {
+            // Nothing else reads the pty while the window is left alone.
+            ctx.request_repaint_after(Duration::from_millis(50));
+        }
+        self.latency.screen_updated(self.terminal().generation());
+        if let Some(deadline) = self
+            .tabs
+            .iter()
+            .flat_map(|tab| &tab.panes)
+            .filter_map(Terminal::next_deadline)
+            .min()
+        {
+            // Make sure a stuck protocol is noticed even if nothing else happens.
+            ctx.request_repaint_after(deadline.saturating_duration_since(Instant::now()));
+        }
+        if self.latency.is_pending() {
+            // Keep polling the pty until the response to the input shows up, otherwise
+            // nothing is read until the next input event.
+            ctx.request_repaint();
+        }
+
+        egui::TopBottomPanel::top("tab_bar").show(ctx, |ui| self.tab_bar(ui));
+        let open_search = ctx.input_mut(|i| i.consume_key(egui::Modifiers::COMMAND, egui::Key::F));
+        self.search.open |= open_search;
+        if ctx.input_mut(|i| {
+            i.consume_key(
+                egui::Modifiers::COMMAND | egui::Modifiers::SHIFT,
+                egui::Key::B,
+            )
+        }) {
+            self.branch();
+        }
+        if self.search.open {
+            egui::TopBottomPanel::top("search_bar")
+                .show(ctx, |ui| self.search_bar(ui, open_search));
+        }
+        let open_send = ctx.input_mut(|i| {
+            i.consume_key(
+                egui::Modifiers::COMMAND | egui::Modifiers::SHIFT,
+                egui::Key::Escape,
+            )
+        });
+        if open_send {
+            self.send.open = true;
+            self.send.error = None;
+        }
+        if self.send.open {
+            self.send_dialog(ctx, open_send);
+        }
+
+        egui::TopBottomPanel::bottom("status_bar").show(ctx, |ui| {
+            let latency = match self.latency.average() {
+                Some(latency) => format!("Latency: {:.1}ms", latency.as_secs_f64() * 1000.0),
+                None => "Latency: --".to_owned(),
+            };
+            let calibration = match self.latency.calibration() {
+                Some(dsr) => format!(
+                    "Less the {:.3}ms the terminal takes to answer DSR",
+                    dsr.as_secs_f64() * 1000.0
+                ),
+                None => "Not calibrated".to_owned(),
+            };
+            ui.horizontal(|ui| {
+                ui.label(latency).on_hover_text(calibration);
+                for badge in mode_badges(self.terminal().mode_summary()) {
+                    ui.separator();
+                    ui.label(egui::RichText::new(badge).strong());
+                }
+                if self.options.perf {
+                    let (bytes, outputs) = self.perf.rates();
+                    ui.separator();
+                    ui.label(format!("{:.1} KiB/s", bytes / 1024.0));
+                    ui.separator();
+                    ui.label(format!("{:.0} events/s", outputs));
+                    ui.separator();
+                    ui.label(format!(
+                        "Memory: {:.1} KiB",
+                        self.terminal().memory_usage() as f64 / 1024.0
+                    ));
+                    ui.separator();
+                    ui.label(format!(
+                        "Frame: {:.1}ms",
+                        self.perf.frame_time.as_secs_f64() * 1000.0
+                    ));
+                }
+            });
+        });
+        if self.options.inspect {
+            egui::SidePanel::right("inspector").show(ctx, |ui| {
+                let half = ui.available_height() / 2.0;
+                ui.strong("From the application");
+                egui::ScrollArea::vertical()
+                    .id_source("incoming")
+                    .max_height(half)
+                    .stick_to_bottom(true)
+                    .show(ui, |ui| {
+                        for description in self.terminal().inspected() {
+                            ui.label(description);
+                        }
+                    });
+                ui.separator();
+                ui.strong("To the application");
+                egui::ScrollArea::vertical()
+                    .id_source("outgoing")
+                    .stick_to_bottom(true)
+                    .show(ui, |ui| {
+                        for line in self.outgoing.lock().unwrap().iter() {
+                            ui.label(line);
+                        }
+                    });
+            });
+        }
         // let size = nix::pty::Winsize {
         //     ws_row: 24,
         //     ws_col: 80,
@@ -64,41 +1698,627 @@ impl<'a> eframe::App for TermGui<'a> {
         // self.terminal.set_window_size(&size).ok();
 
         egui::CentralPanel::default().show(ctx, |ui| {
+            let typing = self.search.has_focus || self.send.open;
+            let mut copy = false;
             ui.input(|state| {
+                if !state.focused || typing {
+                    // The release of a held key could go somewhere else.
+                    self.key_repeat.stop();
+                }
+                // Set when a repeat is dropped for DECARM, so that the text
+                // the repeat would have typed is dropped too.
+                let mut drop_text = false;
                 for event in state.events.iter() {
-                    let bytes = match event {
+                    let bytes: Cow<[u8]> = match event {
+                        _ if typing => continue,
                         egui::Event::Key {
-                            key: egui::Key::Enter,
-                            pressed: true,
+                            key,
+                            pressed,
+                            repeat,
+                            modifiers,
                             ..
-                        } => b"\n".as_slice(),
-                        egui::Event::Text(text) => text.as_bytes(),
-                        _ => b"".as_slice(),
+                        } => {
+                            drop_text = *pressed && *repeat && !self.terminal().modes().auto_repeat;
+                            let Some(key) = terminal_key(*key) else {
+                                continue;
+                            };
+                            if !*pressed {
+                                self.key_repeat.release(key);
+                                continue;
+                            }
+                            // Without Ctrl these type text, which comes as a
+                            // text event.
+                            if matches!(key, Key::Char(_)) && !modifiers.ctrl {
+                                continue;
+                            }
+                            let event = KeyEvent {
+                                key,
+                                modifiers: Modifiers {
+                                    shift: modifiers.shift,
+                                    alt: modifiers.alt,
+                                    ctrl: modifiers.ctrl,
+                                },
+                                repeat: *repeat,
+                            };
+                            if let Some(settings) = &self.options.key_repeat {
+                                self.key_repeat.press(event, settings, Instant::now());
+                            }
+                            match input::encode_key(event, self.terminal().modes()) {
+                                Some(bytes) => bytes.into(),
+                                None => continue,
+                            }
+                        }
+                        egui::Event::Text(text) => {
+                            if std::mem::take(&mut drop_text) {
+                                continue;
+                            }
+                            let modifiers = Modifiers {
+                                alt: state.modifiers.alt,
+                                ..Modifiers::default()
+                            };
+                            input::encode_text(text, modifiers).into()
+                        }
+                        // egui turns Ctrl-C, Ctrl-X and Ctrl-V into clipboard
+                        // commands, but in a terminal they're control codes.
+                        // With Shift (or Cmd on macOS) they're left alone.
+                        egui::Event::Copy | egui::Event::Cut | egui::Event::Paste(_)
+                            if state.modifiers.ctrl && !state.modifiers.shift =>
+                        {
+                            let c = match event {
+                                egui::Event::Copy => 'c',
+                                egui::Event::Cut => 'x',
+                                _ => 'v',
+                            };
+                            let event = KeyEvent {
+                                key: Key::Char(c),
+                                modifiers: Modifiers {
+                                    shift: false,
+                                    alt: state.modifiers.alt,
+                                    ctrl: true,
+                                },
+                                repeat: false,
+                            };
+                            match input::encode_key(event, self.terminal().modes()) {
+                                Some(bytes) => bytes.into(),
+                                None => continue,
+                            }
+                        }
+                        // Ctrl-Shift-C, or the platform's copy key.
+                        egui::Event::Copy => {
+                            copy = true;
+                            continue;
+                        }
+                        _ => continue,
                     };
-                    let Ok(_) = self.terminal.write(bytes) else {
-                        ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+                    let Ok(_) = self.terminal_mut().write(&bytes) else {
+                        // The tab is closed on the next read.
                         continue;
                     };
+                    self.latency.input_sent(self.terminal().generation());
                 }
             });
+            if copy {
+                if let Some(text) = self.terminal().selection_text() {
+                    ctx.output_mut(|output| output.copied_text = text);
+                }
+            }
+            if let Some(settings) = self.options.key_repeat {
+                for event in self.key_repeat.due(&settings, Instant::now()) {
+                    let Some(bytes) = input::encode_key(event, self.terminal().modes()) else {
+                        continue;
+                    };
+                    if self.terminal_mut().write(&bytes).is_ok() {
+                        self.latency.input_sent(self.terminal().generation());
+                    }
+                }
+                if let Some(deadline) = self.key_repeat.deadline() {
+                    ctx.request_repaint_after(deadline.saturating_duration_since(Instant::now()));
+                }
+            }
 
-            let res = ui.label(self.terminal.buffer());
-
-            let bottom = res.rect.bottom();
-            let left = res.rect.left();
-            let painter = ui.painter();
             let char_size = *self.char_size.as_ref().expect("char size to have been set");
-            let cursor_cell_offset = self.terminal.char_to_cursor_offset();
-            let cursor_offset = cursor_cell_offset * char_size;
+            let tab = &mut self.tabs[self.active];
 
-            painter.rect_filled(
-                egui::Rect::from_min_size(
-                    egui::Pos2::new(left + cursor_offset.x, bottom + cursor_offset.y),
-                    char_size.clone().into(),
-                ),
-                0.0,
-                egui::Color32::GRAY,
-            );
+            // Each pane's process is told the size of the space it's shown in,
+            // and gets SIGWINCH when that changes.
+            let area = ui.available_rect_before_wrap();
+            let areas = match self.options.layout {
+                Layout::Columns => {
+                    let panes = tab.panes.len() as f32;
+                    // Roughly what each separator takes, with the spacing around it.
+                    let gap = ui.spacing().item_spacing.x * 2.0 + 6.0;
+                    let width = (area.width() - gap * (panes - 1.0)) / panes;
+                    vec![Vec2::new(width, area.height()); tab.panes.len()]
+                }
+                Layout::Auto => auto_tile(area, tab.panes.len())
+                    .iter()
+                    .map(|tile| tile.size())
+                    .collect(),
+            };
+            for (terminal, area) in tab.panes.iter_mut().zip(areas) {
+                let Some(winsize) = pane_winsize(area, char_size) else {
+                    continue;
+                };
+                if terminal.size() != ScreenSize::from(winsize) {
+                    if let Err(e) = terminal.set_window_size(&winsize) {
+                        eprintln!("failed to resize the terminal: {}", e);
+                    }
+                }
+            }
+
+            let pane = (self.active, tab.focused);
+            self.accessible.update(tab.terminal_mut(), pane);
+            let lines = self.accessible.lines.as_slice();
+            let inspect = self.options.inspect;
+            let color_filter = self.color_filter;
+            let focused = tab.focused;
+            let show = |ui: &mut egui::Ui, i: usize, terminal: &Terminal| {
+                let accessible = (i == focused).then_some(lines);
+                show_terminal(
+                    ui,
+                    terminal,
+                    char_size,
+                    i == focused,
+                    inspect,
+                    accessible,
+                    color_filter,
+                )
+            };
+            // The part of each pane that's visible, and its response.
+            let mut shown = Vec::with_capacity(tab.panes.len());
+            match self.options.layout {
+                Layout::Columns => {
+                    ui.horizontal_top(|ui| {
+                        for (i, terminal) in tab.panes.iter().enumerate() {
+                            if i > 0 {
+                                ui.separator();
+                            }
+                            let response = show(ui, i, terminal);
+                            shown.push((response.rect, response));
+                        }
+                    });
+                }
+                Layout::Auto => {
+                    let tiles = auto_tile(ui.available_rect_before_wrap(), tab.panes.len());
+                    for (i, (terminal, tile)) in tab.panes.iter().zip(tiles).enumerate() {
+                        let response = ui
+                            .allocate_ui_at_rect(tile, |ui| {
+                                ui.set_clip_rect(tile.intersect(ui.clip_rect()));
+                                show(ui, i, terminal)
+                            })
+                            .inner;
+                        ui.painter().rect_stroke(
+                            tile,
+                            0.0,
+                            ui.visuals().widgets.noninteractive.bg_stroke,
+                        );
+                        shown.push((response.rect.intersect(tile), response));
+                    }
+                }
+            }
+            let mut focused_rect = None;
+            for (i, (rect, response)) in shown.into_iter().enumerate() {
+                if i == tab.focused {
+                    focused_rect = Some(rect);
+                }
+                if response.clicked() {
+                    tab.focused = i;
+                }
+            }
+            if let Some(rect) = focused_rect {
+                self.mouse_input(ui, rect);
+            }
         });
+
+        if self.options.perf {
+            self.perf
+                .record(self.terminal().stats(), frame_start.elapsed());
+            // The rates are only meaningful if frames keep coming.
+            ctx.request_repaint_after(Duration::from_millis(250));
+        }
+    }
+}
+
+#[test]
+fn test_latency_meter_rolling_average() {
+    let mut meter = LatencyMeter::default();
+    assert_eq!(meter.average(), None);
+
+    // Nothing is recorded until the screen actually changes.
+    meter.input_sent(0);
+    meter.screen_updated(0);
+    assert!(meter.is_pending());
+    meter.screen_updated(1);
+    assert!(!meter.is_pending());
+    assert_eq!(meter.samples.len(), 1);
+
+    for ms in 0..20 {
+        meter.push_sample(Duration::from_millis(ms));
+    }
+    // Only the last 10 samples (10..20ms) count.
+    assert_eq!(meter.average(), Some(Duration::from_micros(14_500)));
+}
+
+#[test]
+fn test_latency_meter_calibration() {
+    use termulus::{clock::FakeClock, terminal::test_terminal};
+
+    /// Moves on by 300µs every time it's read.
+    struct Ticking(FakeClock);
+    impl Clock for Ticking {
+        fn now(&self) -> Instant {
+            self.0.advance(Duration::from_micros(300));
+            self.0.now()
+        }
+    }
+
+    let clock = FakeClock::new();
+    let mut meter = LatencyMeter::new(Ticking(clock.clone()));
+    let (term, _app) = test_terminal();
+    meter.calibrate(&term);
+    assert_eq!(meter.calibration(), Some(Duration::from_micros(300)));
+
+    // The terminal's share of the time is taken off each sample.
+    meter.input_sent(0);
+    clock.advance(Duration::from_millis(2));
+    meter.screen_updated(1);
+    assert_eq!(meter.average(), Some(Duration::from_millis(2)));
+}
+
+#[test]
+fn test_move_tab() {
+    use termulus::terminal::{feed, test_terminal};
+
+    let mut terminals = Vec::new();
+    let mut apps = Vec::new();
+    for title in ["a", "b", "c"] {
+        let (mut term, mut app) = test_terminal();
+        feed(
+            &mut term,
+            &mut app,
+            format!("\x1b]2;{}\x07", title).as_bytes(),
+        );
+        terminals.push(term);
+        apps.push(app);
     }
+    let titles = |terminals: &[Terminal]| {
+        terminals
+            .iter()
+            .map(|t| t.title().to_owned())
+            .collect::<Vec<_>>()
+    };
+
+    // Drag "c" to the front while "b" is focused.
+    let active = move_tab(&mut terminals, 1, 2, 0);
+    assert_eq!(terminals[0].title(), "c");
+    assert_eq!(titles(&terminals), ["c", "a", "b"]);
+    assert_eq!(terminals[active].title(), "b");
+
+    // Drag the focused tab to the end.
+    let active = move_tab(&mut terminals, active, active, 3);
+    assert_eq!(titles(&terminals), ["c", "a", "b"]);
+    assert_eq!(active, 2);
+    let active = move_tab(&mut terminals, 0, 0, 3);
+    assert_eq!(terminals[0].title(), "a");
+    assert_eq!(terminals[active].title(), "c");
+
+    // The ptys are untouched, so each terminal still reads from its own.
+    feed(&mut terminals[2], &mut apps[2], b"\x1b]2;still c\x07");
+    assert_eq!(terminals[2].title(), "still c");
+}
+
+#[cfg(feature = "regex")]
+#[test]
+fn test_search_bar_invalid_regex() {
+    use termulus::terminal::{feed, test_terminal};
+
+    let (mut term, mut app) = test_terminal();
+    feed(&mut term, &mut app, b"555-1234\r\n555-9876");
+    let mut search = SearchBar {
+        query: r"\d{3}-\d{4}".to_owned(),
+        regex: true,
+        ..Default::default()
+    };
+    search.update(&term);
+    assert_eq!(search.matches.len(), 2);
+
+    // Enter steps back from the newest match.
+    search.select(&mut term);
+    assert_eq!(term.selection().unwrap().start().line, 1);
+    search.step(&mut term, false);
+    assert_eq!(term.selection().unwrap().start().line, 0);
+
+    search.query = "[unclosed".to_owned();
+    search.update(&term);
+    assert_eq!(search.error.as_deref(), Some("Invalid regex"));
+    assert!(search.matches.is_empty());
+}
+
+#[test]
+fn test_describe_color() {
+    let palette = Palette::default();
+    assert_eq!(describe_color(None, &palette), "default");
+    assert_eq!(
+        describe_color(Some(Color::BrightRed), &palette),
+        "#ff0000 (bright red, 9)"
+    );
+    assert_eq!(
+        describe_color(Some(Color::Indexed(208)), &palette),
+        "#ff8700 (index 208)"
+    );
+    assert_eq!(
+        describe_color(Some(Color::Rgb(255, 100, 0)), &palette),
+        "#ff6400"
+    );
+}
+
+#[test]
+fn test_accessible_screen_follows_damage() {
+    use termulus::terminal::{feed, test_terminal};
+
+    let (mut term, mut app) = test_terminal();
+    let mut screen = AccessibleScreen::default();
+    feed(&mut term, &mut app, b"one\r\ntwo");
+    screen.update(&mut term, (0, 0));
+    assert_eq!(screen.lines[0].text, "one");
+    assert_eq!(screen.lines[1].text, "two");
+
+    // Only the damaged row is rebuilt.
+    screen.lines[0].text = "stale".to_owned();
+    feed(&mut term, &mut app, b"!");
+    screen.update(&mut term, (0, 0));
+    assert_eq!(screen.lines[0].text, "stale");
+    assert_eq!(screen.lines[1].text, "two!");
+
+    // Switching panes rebuilds everything.
+    screen.update(&mut term, (0, 1));
+    assert_eq!(screen.lines[0].text, "one");
+}
+
+#[test]
+fn test_auto_tile() {
+    let area = egui::Rect::from_min_size(egui::Pos2::ZERO, Vec2::new(1600.0, 900.0));
+    assert_eq!(auto_tile(area, 1), [area]);
+
+    // The first splits are across the width, then the largest pane is tall
+    // enough to be split across its height.
+    let tiles = auto_tile(area, 3);
+    let x = 1600.0 * 0.618_034;
+    assert_eq!(
+        tiles[0],
+        egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(x * 0.618_034, 900.0))
+    );
+    assert_eq!(
+        tiles[1],
+        egui::Rect::from_min_max(egui::pos2(x * 0.618_034, 0.0), egui::pos2(x, 900.0))
+    );
+    assert_eq!(
+        tiles[2],
+        egui::Rect::from_min_max(egui::pos2(x, 0.0), egui::pos2(1600.0, 900.0))
+    );
+    let tiles = auto_tile(area, 4);
+    assert_eq!(tiles[0].max.y, 900.0 * 0.618_034);
+    assert_eq!(tiles[1].min.y, 900.0 * 0.618_034);
+
+    for panes in 1..=8 {
+        let tiles = auto_tile(area, panes);
+        assert_eq!(tiles.len(), panes);
+        // Always the same layout, covering the whole area without overlaps.
+        assert_eq!(tiles, auto_tile(area, panes));
+        let total: f32 = tiles.iter().map(|tile| tile.area()).sum();
+        assert!((total - area.area()).abs() < 1.0);
+        for (i, a) in tiles.iter().enumerate() {
+            assert!(area.contains_rect(*a));
+            for b in &tiles[i + 1..] {
+                assert!(a.intersect(*b).area() <= 0.0);
+            }
+            // No pane ends up as a sliver.
+            let aspect = a.width().max(a.height()) / a.width().min(a.height());
+            assert!(aspect < 3.0, "{} panes: {:?}", panes, tiles);
+        }
+        // Each layout only splits one pane of the one before it.
+        if panes > 1 {
+            let before = auto_tile(area, panes - 1);
+            let split = largest_tile(&before);
+            assert_eq!(tiles[..split], before[..split]);
+            assert_eq!(tiles[split + 2..], before[split + 1..]);
+            assert_eq!(tiles[split].union(tiles[split + 1]), before[split]);
+        }
+    }
+}
+
+#[test]
+fn test_color_blindness_filter() {
+    let distance = |a: [f32; 3], b: [f32; 3]| {
+        a.iter()
+            .zip(b)
+            .map(|(a, b)| (a - b).powi(2))
+            .sum::<f32>()
+            .sqrt()
+    };
+    let red = [1.0, 0.0, 0.0];
+    let green = [0.0, 0.6, 0.0];
+    let blue = [0.0, 0.0, 1.0];
+    let yellow = [1.0, 1.0, 0.0];
+    for filter in ColorBlindnessFilter::ALL {
+        // Greys look the same to everyone.
+        for grey in [0.0, 0.5, 1.0] {
+            let out = filter.transform([grey; 3]);
+            assert!(distance(out, [grey; 3]) < 0.01, "{:?}: {:?}", filter, out);
+        }
+    }
+    // Red and green are much harder to tell apart without L or M cones, and
+    // blue and yellow without S cones.
+    for filter in [
+        ColorBlindnessFilter::Deuteranopia,
+        ColorBlindnessFilter::Protanopia,
+    ] {
+        let (r, g) = (filter.transform(red), filter.transform(green));
+        assert!(distance(r, g) < distance(red, green) / 2.0, "{:?}", filter);
+    }
+    let filter = ColorBlindnessFilter::Tritanopia;
+    let (b, y) = (filter.transform(blue), filter.transform(yellow));
+    assert!(distance(b, y) < distance(blue, yellow));
+    assert_eq!(filter.apply(egui::Color32::WHITE), egui::Color32::WHITE);
+}
+
+#[test]
+fn test_sgr_churn() {
+    use std::time::Instant;
+    use termulus::terminal::test_terminal;
+
+    // Like some Java command line tools, which set the style again before
+    // every character, in a different way each time.
+    let line = "the quick brown fox jumps over the lazy dog, again and again and again";
+    let clean = format!("\x1b[32m{}\x1b[0m\r\n", line).repeat(24);
+    let churn = line
+        .chars()
+        .enumerate()
+        .map(|(i, c)| match i % 3 {
+            0 => format!("\x1b[0m\x1b[32m{}", c),
+            1 => format!("\x1b[38;5;2m\x1b[1m\x1b[22m{}", c),
+            _ => format!("\x1b[0;32m{}", c),
+        })
+        .collect::<String>();
+    let churn = format!("{}\x1b[0m\r\n", churn).repeat(24);
+
+    let palette = Palette::default();
+    let colors = CellColors {
+        palette: &palette,
+        default_fg: egui::Color32::WHITE,
+        default_bg: egui::Color32::BLACK,
+        filter: None,
+    };
+    let font_id = egui::FontId::monospace(14.0);
+    // Build the layout jobs for a frame, returning them and the time it took.
+    let frame = |input: &str| {
+        let (mut term, _app) = test_terminal();
+        term.inject_bytes(input.as_bytes());
+        let start = Instant::now();
+        let mut jobs = Vec::new();
+        for _ in 0..50 {
+            jobs = term
+                .grid()
+                .rows()
+                .iter()
+                .map(|row| row_job(row.cells(), &font_id, &colors, 0.0))
+                .collect::<Vec<_>>();
+        }
+        (jobs, start.elapsed())
+    };
+    let (clean_jobs, clean_time) = frame(&clean);
+    let (churn_jobs, churn_time) = frame(&churn);
+    assert_eq!(churn_jobs[0].sections.len(), 1);
+    assert_eq!(churn_jobs, clean_jobs);
+    assert!(
+        churn_time < clean_time * 3 + std::time::Duration::from_millis(20),
+        "{:?} with churn, {:?} without",
+        churn_time,
+        clean_time
+    );
+}
+
+#[test]
+fn test_pane_winsize() {
+    let char_size = Vec2::new(8.0, 16.0);
+    let winsize = pane_winsize(Vec2::new(645.0, 390.0), char_size).unwrap();
+    assert_eq!((winsize.ws_col, winsize.ws_row), (80, 24));
+    assert_eq!((winsize.ws_xpixel, winsize.ws_ypixel), (640, 384));
+    assert!(pane_winsize(Vec2::new(645.0, 10.0), char_size).is_none());
+    assert!(pane_winsize(Vec2::ZERO, char_size).is_none());
+}
+
+#[test]
+fn test_cell_at_double_width() {
+    use termulus::terminal::{feed, test_terminal};
+
+    let (mut term, mut app) = test_terminal();
+    feed(&mut term, &mut app, b"\x1b#6");
+    let char_size = Vec2::new(8.0, 16.0);
+    let cell = |x, y| {
+        let point = cell_at(&term, Vec2::new(x, y), char_size);
+        (point.col(), point.row())
+    };
+    assert_eq!(cell(28.0, 4.0), (1, 0));
+    assert_eq!(cell(28.0, 20.0), (3, 1));
+    // Off the end of the row, and off the screen.
+    assert_eq!(cell(700.0, 4.0), (39, 0));
+    assert_eq!(cell(700.0, 20.0), (79, 1));
+    assert_eq!(cell(-5.0, 1000.0), (0, 23));
+}
+
+#[test]
+fn test_row_job_attributes() {
+    use termulus::terminal::test_terminal;
+
+    let (mut term, _app) = test_terminal();
+    term.inject_bytes(b"\x1b[3;4;9;31mA\x1b[0;4;58;5;4mB\x1b[0;2mC");
+    let palette = Palette::default();
+    let colors = CellColors {
+        palette: &palette,
+        default_fg: egui::Color32::WHITE,
+        default_bg: egui::Color32::BLACK,
+        filter: None,
+    };
+    let font_id = egui::FontId::monospace(14.0);
+    let job = row_job(term.grid().row(0).cells(), &font_id, &colors, 0.0);
+    let formats: Vec<_> = job.sections.iter().map(|s| &s.format).collect();
+    let red = colors.get(Color::Red);
+    assert!(formats[0].italics);
+    assert_eq!(formats[0].underline, egui::Stroke::new(1.0, red));
+    assert_eq!(formats[0].strikethrough, egui::Stroke::new(1.0, red));
+    // The underline color is separate from the text color.
+    assert_eq!(formats[1].color, egui::Color32::WHITE);
+    assert_eq!(formats[1].underline.color, colors.get(Color::Blue));
+    assert_eq!(formats[2].color, egui::Color32::WHITE.gamma_multiply(0.5));
+    assert!(!formats[2].italics);
+}
+
+#[test]
+fn test_output_ends_scrolling_back() {
+    use std::io::Write;
+    use termulus::terminal::{feed, test_terminal};
+
+    let (mut term, mut app) = test_terminal();
+    feed(&mut term, &mut app, &b"line\r\n".repeat(40));
+    let mut tab = Tab::new(term);
+    tab.panes[0].scroll_display(5);
+    // Reading nothing leaves the view where it is.
+    tab.read();
+    assert_eq!(tab.panes[0].display_offset(), 5);
+    app.write_all(b"more").unwrap();
+    tab.read();
+    assert_eq!(tab.panes[0].display_offset(), 0);
+}
+
+#[test]
+fn test_reverse_colors() {
+    use termulus::terminal::test_terminal;
+
+    let (mut term, _app) = test_terminal();
+    term.inject_bytes(b"\x1b[7mA\x1b[31;44mB\x1b[27mC");
+    let palette = Palette::default();
+    let colors = CellColors {
+        palette: &palette,
+        default_fg: egui::Color32::WHITE,
+        default_bg: egui::Color32::BLACK,
+        filter: None,
+    };
+    let style = |col: usize| *term.grid().row(0).cells()[col].style();
+    assert_eq!(colors.fg(&style(0)), egui::Color32::BLACK);
+    assert_eq!(colors.bg(&style(0)), Some(egui::Color32::WHITE));
+    assert_eq!(colors.fg(&style(1)), colors.get(Color::Blue));
+    assert_eq!(colors.bg(&style(1)), Some(colors.get(Color::Red)));
+    assert_eq!(colors.fg(&style(2)), colors.get(Color::Red));
+    assert_eq!(colors.bg(&style(2)), Some(colors.get(Color::Blue)));
+}
+
+#[test]
+fn test_terminal_key() {
+    use egui::Key as K;
+    assert_eq!(terminal_key(K::C), Some(Key::Char('c')));
+    assert_eq!(terminal_key(K::Num2), Some(Key::Char('2')));
+    assert_eq!(terminal_key(K::OpenBracket), Some(Key::Char('[')));
+    assert_eq!(terminal_key(K::F5), Some(Key::F(5)));
+    assert_eq!(terminal_key(K::Copy), None);
 }
@@ -0,0 +1,691 @@
+//! Encoding user input into the bytes applications expect to read.
+//!
+//! These don't write anything themselves so that they can be tested without a
+//! pty, the embedder passes the result to [`crate::terminal::Terminal::write`].
+use std::{
+    collections::HashSet,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    coords::ScreenPoint,
+    terminal::{Modes, MouseEncoding, MouseProtocol},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WheelDirection {
+    Up,
+    Down,
+}
+
+/// Encode `lines` steps of the mouse wheel.
+///
+/// Returns `None` if the wheel isn't meant for the application, in which case the
+/// embedder should scroll its own view instead.
+pub fn encode_wheel(direction: WheelDirection, lines: usize, modes: &Modes) -> Option<Vec<u8>> {
+    // With alternate scroll, programs on the alternate screen (less, man) get
+    // arrow keys, since there is no scrollback there for the wheel to move through.
+    if modes.alternate_screen && modes.alternate_scroll {
+        let arrow = match direction {
+            WheelDirection::Up => Arrow::Up,
+            WheelDirection::Down => Arrow::Down,
+        };
+        return Some(encode_arrows(arrow, lines, modes));
+    }
+    None
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Arrow {
+    Up,
+    Down,
+    Right,
+    Left,
+}
+
+/// Encode `count` presses of an arrow key.
+pub fn encode_arrows(arrow: Arrow, count: usize, modes: &Modes) -> Vec<u8> {
+    let key = match arrow {
+        Arrow::Up => b'A',
+        Arrow::Down => b'B',
+        Arrow::Right => b'C',
+        Arrow::Left => b'D',
+    };
+    let prefix: &[u8] = if modes.application_cursor_keys {
+        b"\x1bO"
+    } else {
+        b"\x1b["
+    };
+    let mut bytes = Vec::with_capacity(count * 3);
+    for _ in 0..count {
+        bytes.extend_from_slice(prefix);
+        bytes.push(key);
+    }
+    bytes
+}
+
+/// Keys that send escape sequences rather than text. Text is written as it is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Key {
+    Enter,
+    Tab,
+    Backspace,
+    Escape,
+    Arrow(Arrow),
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Insert,
+    Delete,
+    /// F1 to F12.
+    F(u8),
+    /// A key that types a character, for when Ctrl turns it into a control
+    /// code. Otherwise the text it types is written with [`encode_text`].
+    Char(char),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub key: Key,
+    pub modifiers: Modifiers,
+    /// Whether the press comes from the key being held down rather than
+    /// pressed again, so it can be dropped when the application resets DECARM.
+    pub repeat: bool,
+}
+
+/// Encode a key press as xterm does.
+///
+/// Returns `None` for repeats while auto-repeat (DECARM) is off, in which
+/// case the embedder should drop any text from the same press too.
+pub fn encode_key(event: KeyEvent, modes: &Modes) -> Option<Vec<u8>> {
+    if event.repeat && !modes.auto_repeat {
+        return None;
+    }
+    let modifiers = event.modifiers;
+    // The modifier parameter: 1, plus 1 for shift, 2 for alt, and 4 for ctrl.
+    let param = 1 + modifiers.shift as u8 + 2 * modifiers.alt as u8 + 4 * modifiers.ctrl as u8;
+    // Keys with a final byte, like the arrows: `CSI 1 ; m X` with modifiers,
+    // otherwise `SS3 X` in application mode or `CSI X`.
+    let letter = |key: u8, application: bool| match param {
+        1 if application => vec![0x1b, b'O', key],
+        1 => vec![0x1b, b'[', key],
+        _ => format!("\x1b[1;{}{}", param, key as char).into_bytes(),
+    };
+    // Keys with a number, like Delete: `CSI n ; m ~`.
+    let tilde = |n: u8| match param {
+        1 => format!("\x1b[{}~", n).into_bytes(),
+        _ => format!("\x1b[{};{}~", n, param).into_bytes(),
+    };
+    let cursor_keys = modes.application_cursor_keys;
+    let bytes = match event.key {
+        Key::Enter => b"\n".to_vec(),
+        Key::Tab if modifiers.shift => b"\x1b[Z".to_vec(),
+        Key::Tab => b"\t".to_vec(),
+        Key::Backspace => vec![0x7f],
+        Key::Escape => vec![0x1b],
+        Key::Arrow(arrow) => {
+            let key = match arrow {
+                Arrow::Up => b'A',
+                Arrow::Down => b'B',
+                Arrow::Right => b'C',
+                Arrow::Left => b'D',
+            };
+            letter(key, cursor_keys)
+        }
+        Key::Home => letter(b'H', cursor_keys),
+        Key::End => letter(b'F', cursor_keys),
+        Key::Insert => tilde(2),
+        Key::Delete => tilde(3),
+        Key::PageUp => tilde(5),
+        Key::PageDown => tilde(6),
+        Key::F(n @ 1..=4) => letter(b'P' + n - 1, true),
+        Key::F(n @ 5..=12) => tilde([15, 17, 18, 19, 20, 21, 23, 24][n as usize - 5]),
+        Key::F(_) => return None,
+        Key::Char(c) if modifiers.ctrl => vec![control_code(c)?],
+        Key::Char(c) => c.to_string().into_bytes(),
+    };
+    // Alt sends ESC before keys that are a single byte, like xterm's
+    // metaSendsEscape, rather than a modifier parameter.
+    let plain = match event.key {
+        Key::Tab => !modifiers.shift,
+        Key::Enter | Key::Backspace | Key::Escape | Key::Char(_) => true,
+        _ => false,
+    };
+    if modifiers.alt && plain {
+        return Some([b"\x1b", &bytes[..]].concat());
+    }
+    Some(bytes)
+}
+
+/// Encode typed text, with ESC before it if Alt was held.
+pub fn encode_text(text: &str, modifiers: Modifiers) -> Vec<u8> {
+    if modifiers.alt {
+        [b"\x1b", text.as_bytes()].concat()
+    } else {
+        text.as_bytes().to_vec()
+    }
+}
+
+/// The control code that Ctrl and `c` send, for the letters and the keys that
+/// xterm gives one to.
+fn control_code(c: char) -> Option<u8> {
+    Some(match c.to_ascii_lowercase() {
+        c @ 'a'..='z' => c as u8 - b'a' + 1,
+        '@' | ' ' | '`' | '2' => 0,
+        '[' | '3' => 0x1b,
+        '\\' | '4' => 0x1c,
+        ']' | '5' => 0x1d,
+        '^' | '~' | '6' => 0x1e,
+        '_' | '/' | '-' | '7' => 0x1f,
+        '?' | '8' => 0x7f,
+        _ => return None,
+    })
+}
+
+/// How held keys are repeated when the OS doesn't repeat them itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyRepeat {
+    /// How long a key is held before it starts repeating.
+    pub delay: Duration,
+    /// The time between repeats.
+    pub interval: Duration,
+}
+
+/// Repeats held keys for [`KeyRepeat`]. Keys that the OS has been seen
+/// repeating are left to it from then on, so they don't repeat twice as fast.
+#[derive(Debug, Clone, Default)]
+pub struct SyntheticRepeat {
+    held: Option<(KeyEvent, Instant)>,
+    repeated_by_os: HashSet<Key>,
+}
+
+impl SyntheticRepeat {
+    pub fn press(&mut self, event: KeyEvent, settings: &KeyRepeat, now: Instant) {
+        if event.repeat {
+            self.repeated_by_os.insert(event.key);
+        }
+        if self.repeated_by_os.contains(&event.key) {
+            self.held = None;
+        } else {
+            self.held = Some((event, now + settings.delay));
+        }
+    }
+
+    pub fn release(&mut self, key: Key) {
+        if self.held.is_some_and(|(event, _)| event.key == key) {
+            self.held = None;
+        }
+    }
+
+    /// Stop repeating, e.g. because the window lost focus and the release
+    /// won't be seen.
+    pub fn stop(&mut self) {
+        self.held = None;
+    }
+
+    /// The repeats that are due by `now`, to be encoded like any other press.
+    pub fn due(&mut self, settings: &KeyRepeat, now: Instant) -> Vec<KeyEvent> {
+        let mut events = Vec::new();
+        if let Some((event, next)) = &mut self.held {
+            while *next <= now {
+                events.push(KeyEvent {
+                    repeat: true,
+                    ..*event
+                });
+                *next += settings.interval.max(Duration::from_millis(1));
+            }
+        }
+        events
+    }
+
+    /// When the next repeat is due, if a key is held.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.held.map(|(_, next)| next)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseButton {
+    Left,
+    Middle,
+    Right,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseEventKind {
+    Press(MouseButton),
+    Release(MouseButton),
+    Motion,
+    Wheel(WheelDirection),
+}
+
+/// The modifier keys held down during a mouse or key event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub alt: bool,
+    pub ctrl: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MouseEvent {
+    pub kind: MouseEventKind,
+    /// The cell under the pointer. Drags that leave the screen should be
+    /// clamped to it rather than dropped, so the release isn't lost.
+    pub point: ScreenPoint,
+    pub modifiers: Modifiers,
+}
+
+/// Encodes mouse events as xterm does, keeping track of the button that is
+/// held down between a press and its release.
+#[derive(Debug, Clone, Default)]
+pub struct MouseInput {
+    held: Option<MouseButton>,
+    /// Where the last event was reported, so motion is only reported when the
+    /// pointer moves to a different cell.
+    last_point: Option<ScreenPoint>,
+}
+
+impl MouseInput {
+    /// Encode `event`, or return `None` if the application didn't ask for it.
+    pub fn encode(&mut self, event: MouseEvent, modes: &Modes) -> Option<Vec<u8>> {
+        let held = self.held;
+        match event.kind {
+            MouseEventKind::Press(button) => self.held = Some(button),
+            MouseEventKind::Release(button) if held == Some(button) => self.held = None,
+            _ => {}
+        }
+
+        let report = match event.kind {
+            MouseEventKind::Press(_) | MouseEventKind::Wheel(_) => {
+                modes.mouse != MouseProtocol::Off
+            }
+            MouseEventKind::Release(_) => {
+                !matches!(modes.mouse, MouseProtocol::Off | MouseProtocol::X10)
+            }
+            MouseEventKind::Motion => {
+                self.last_point != Some(event.point)
+                    && match modes.mouse {
+                        MouseProtocol::ButtonEvent => held.is_some(),
+                        MouseProtocol::AnyEvent => true,
+                        _ => false,
+                    }
+            }
+        };
+        if !report {
+            return None;
+        }
+        self.last_point = Some(event.point);
+
+        let sgr = modes.mouse_encoding == MouseEncoding::Sgr;
+        let mut button = match event.kind {
+            MouseEventKind::Press(button) => button_code(button),
+            // Only SGR says which button was released.
+            MouseEventKind::Release(button) if sgr => button_code(button),
+            MouseEventKind::Release(_) => 3,
+            MouseEventKind::Motion => 32 + held.map_or(3, button_code),
+            MouseEventKind::Wheel(WheelDirection::Up) => 64,
+            MouseEventKind::Wheel(WheelDirection::Down) => 65,
+        };
+        // X10 mode predates modifiers.
+        if modes.mouse != MouseProtocol::X10 {
+            let modifiers = event.modifiers;
+            button |= (modifiers.shift as usize) << 2
+                | (modifiers.alt as usize) << 3
+                | (modifiers.ctrl as usize) << 4;
+        }
+
+        let point = event.point.to_wire();
+        let (col, row) = (point.col(), point.row());
+        match modes.mouse_encoding {
+            MouseEncoding::Default => {
+                let mut bytes = b"\x1b[M".to_vec();
+                for value in [button, col, row] {
+                    bytes.push(u8::try_from(value + 32).ok()?);
+                }
+                Some(bytes)
+            }
+            MouseEncoding::Utf8 => {
+                let mut bytes = b"\x1b[M".to_vec();
+                for value in [button, col, row] {
+                    let c = char::from_u32(value as u32 + 32).filter(|c| c.len_utf8() <= 2)?;
+                    bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                Some(bytes)
+            }
+            MouseEncoding::Sgr => {
+                let end = match event.kind {
+                    MouseEventKind::Release(_) => 'm',
+                    _ => 'M',
+                };
+                Some(format!("\x1b[<{};{};{}{}", button, col, row, end).into_bytes())
+            }
+            MouseEncoding::Urxvt => {
+                Some(format!("\x1b[{};{};{}M", button + 32, col, row).into_bytes())
+            }
+        }
+    }
+}
+
+fn button_code(button: MouseButton) -> usize {
+    match button {
+        MouseButton::Left => 0,
+        MouseButton::Middle => 1,
+        MouseButton::Right => 2,
+    }
+}
+
+/// Turn a sequence typed with backslash escapes, like `\e[?1049h`, into the
+/// bytes it stands for. Understands `\e` (ESC), `\n`, `\r`, `\t`, `\a`,
+/// `\b`, `\\`, and `\xHH` for any other byte.
+pub fn unescape(text: &str) -> anyhow::Result<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        let byte = match chars.next() {
+            Some('e' | 'E') => 0x1B,
+            Some('n') => b'\n',
+            Some('r') => b'\r',
+            Some('t') => b'\t',
+            Some('a') => 0x07,
+            Some('b') => 0x08,
+            Some('\\') => b'\\',
+            Some('x') => {
+                let hex = chars.by_ref().take(2).collect::<String>();
+                if hex.len() != 2 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                    anyhow::bail!("\\x needs two hex digits, not {:?}", hex);
+                }
+                u8::from_str_radix(&hex, 16)?
+            }
+            Some(c) => anyhow::bail!("unknown escape \\{}", c),
+            None => anyhow::bail!("trailing backslash"),
+        };
+        bytes.push(byte);
+    }
+    Ok(bytes)
+}
+
+#[test]
+fn test_alternate_scroll() {
+    let mut modes = Modes::default();
+    modes.set_private(1007, true);
+    // Not on the alternate screen, so the wheel is ours.
+    assert_eq!(encode_wheel(WheelDirection::Up, 1, &modes), None);
+
+    modes.set_private(1049, true);
+    assert_eq!(
+        encode_wheel(WheelDirection::Up, 1, &modes),
+        Some(b"\x1b[A".to_vec())
+    );
+    assert_eq!(
+        encode_wheel(WheelDirection::Down, 2, &modes),
+        Some(b"\x1b[B\x1b[B".to_vec())
+    );
+
+    modes.set_private(1, true);
+    assert_eq!(
+        encode_wheel(WheelDirection::Up, 1, &modes),
+        Some(b"\x1bOA".to_vec())
+    );
+
+    modes.set_private(1007, false);
+    assert_eq!(encode_wheel(WheelDirection::Up, 1, &modes), None);
+}
+
+#[test]
+fn test_mouse_encoding() {
+    use crate::coords::ScreenSize;
+    use MouseEventKind::*;
+
+    let size = ScreenSize::new(400, 50);
+    let event = |kind, col, row| MouseEvent {
+        kind,
+        point: ScreenPoint::clamped(col, row, size),
+        modifiers: Modifiers::default(),
+    };
+    let with_modifiers = |event: MouseEvent, shift, alt, ctrl| MouseEvent {
+        modifiers: Modifiers { shift, alt, ctrl },
+        ..event
+    };
+    // The modes to set, the events, and what xterm sends for them. Each case
+    // starts with no buttons held.
+    type Case = (&'static [usize], Vec<MouseEvent>, &'static [&'static [u8]]);
+    #[rustfmt::skip]
+    let cases: &[Case] = &[
+        (&[1000], vec![event(Press(MouseButton::Left), 0, 0), event(Release(MouseButton::Left), 0, 0)], &[b"\x1b[M !!", b"\x1b[M#!!"]),
+        (&[1000], vec![event(Wheel(WheelDirection::Up), 0, 0)], &[b"\x1b[M`!!"]),
+        (&[1000], vec![with_modifiers(event(Press(MouseButton::Right), 9, 4), true, false, true)], &[b"\x1b[M6*%"]),
+        // Too far right for the default encoding.
+        (&[1000], vec![event(Press(MouseButton::Left), 299, 0)], &[]),
+        (&[1000, 1005], vec![event(Press(MouseButton::Left), 299, 0)], &[b"\x1b[M \xc5\x8c!"]),
+        (&[9], vec![with_modifiers(event(Press(MouseButton::Left), 0, 0), true, true, true), event(Release(MouseButton::Left), 0, 0)], &[b"\x1b[M !!"]),
+        (&[1000, 1006], vec![event(Press(MouseButton::Left), 0, 0), event(Release(MouseButton::Left), 0, 0)], &[b"\x1b[<0;1;1M", b"\x1b[<0;1;1m"]),
+        (&[1000, 1006], vec![event(Wheel(WheelDirection::Down), 0, 0)], &[b"\x1b[<65;1;1M"]),
+        (&[1000, 1006], vec![with_modifiers(event(Press(MouseButton::Middle), 4, 9), false, true, false)], &[b"\x1b[<9;5;10M"]),
+        // Motion isn't reported without 1002 or 1003.
+        (&[1000, 1006], vec![event(Press(MouseButton::Left), 0, 0), event(Motion, 3, 2)], &[b"\x1b[<0;1;1M"]),
+        // A drag is released where it ends, not where it started.
+        (&[1002, 1006], vec![event(Motion, 1, 0), event(Press(MouseButton::Left), 0, 0), event(Motion, 0, 0), event(Motion, 3, 2), event(Release(MouseButton::Left), 5, 2), event(Motion, 6, 2)],
+            &[b"\x1b[<0;1;1M", b"\x1b[<32;4;3M", b"\x1b[<0;6;3m"]),
+        (&[1002], vec![event(Press(MouseButton::Right), 0, 0), event(Motion, 1, 0), event(Release(MouseButton::Right), 1, 0)], &[b"\x1b[M\"!!", b"\x1b[MB\"!", b"\x1b[M#\"!"]),
+        (&[1003, 1006], vec![event(Motion, 1, 0), event(Motion, 1, 0), with_modifiers(event(Motion, 2, 0), true, false, false)], &[b"\x1b[<35;2;1M", b"\x1b[<39;3;1M"]),
+        (&[1000, 1015], vec![event(Press(MouseButton::Left), 0, 0), event(Release(MouseButton::Left), 0, 0)], &[b"\x1b[32;1;1M", b"\x1b[35;1;1M"]),
+    ];
+    for (modes_set, events, expected) in cases {
+        let mut modes = Modes::default();
+        for mode in *modes_set {
+            modes.set_private(*mode, true);
+        }
+        let mut input = MouseInput::default();
+        let sent = events
+            .iter()
+            .filter_map(|event| input.encode(*event, &modes))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sent, *expected,
+            "modes {:?}, events {:?}",
+            modes_set, events
+        );
+    }
+}
+
+#[test]
+fn test_mouse_encoding_matrix() {
+    let buttons = [MouseButton::Left, MouseButton::Middle, MouseButton::Right];
+    let point = ScreenPoint::ORIGIN;
+    for protocol in [None, Some(9), Some(1000), Some(1002), Some(1003)] {
+        for encoding in [None, Some(1005), Some(1006), Some(1015)] {
+            let mut modes = Modes::default();
+            for mode in protocol.into_iter().chain(encoding) {
+                modes.set_private(mode, true);
+            }
+            for button in buttons {
+                for modifiers in 0..8 {
+                    let modifiers = Modifiers {
+                        shift: modifiers & 1 != 0,
+                        alt: modifiers & 2 != 0,
+                        ctrl: modifiers & 4 != 0,
+                    };
+                    let mut input = MouseInput::default();
+                    let mut send = |kind| {
+                        input.encode(
+                            MouseEvent {
+                                kind,
+                                point,
+                                modifiers,
+                            },
+                            &modes,
+                        )
+                    };
+                    let press = send(MouseEventKind::Press(button));
+                    let wheel = send(MouseEventKind::Wheel(WheelDirection::Up));
+                    let release = send(MouseEventKind::Release(button));
+                    assert_eq!(press.is_some(), protocol.is_some());
+                    assert_eq!(wheel.is_some(), protocol.is_some());
+                    assert_eq!(release.is_some(), protocol.is_some_and(|mode| mode != 9));
+                    let Some(release) = release else {
+                        continue;
+                    };
+                    // Only SGR releases say which button it was.
+                    let sgr = encoding == Some(1006);
+                    assert_eq!(release.ends_with(b"m"), sgr);
+                    if sgr {
+                        let press = press.unwrap();
+                        assert_eq!(release[..release.len() - 1], press[..press.len() - 1]);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_unescape() {
+    assert_eq!(unescape("\\e[?1049h").unwrap(), b"\x1b[?1049h");
+    assert_eq!(unescape("a\\nb\\r\\t\\\\").unwrap(), b"a\nb\r\t\\");
+    assert_eq!(unescape("\\x1b]2;é\\a").unwrap(), "\x1b]2;é\x07".as_bytes());
+    assert!(unescape("\\q").is_err());
+    assert!(unescape("\\x1").is_err());
+    assert!(unescape("\\xzz").is_err());
+    assert!(unescape("\\x+f").is_err());
+    assert!(unescape("abc\\").is_err());
+}
+
+#[test]
+fn test_encode_key() {
+    let press = |key, modifiers| KeyEvent {
+        key,
+        modifiers,
+        repeat: false,
+    };
+    let none = Modifiers::default();
+    let mut modes = Modes::default();
+    let up = Key::Arrow(Arrow::Up);
+    assert_eq!(encode_key(press(up, none), &modes).unwrap(), b"\x1b[A");
+    let ctrl = Modifiers { ctrl: true, ..none };
+    assert_eq!(encode_key(press(up, ctrl), &modes).unwrap(), b"\x1b[1;5A");
+    let shift = Modifiers {
+        shift: true,
+        ..none
+    };
+    assert_eq!(
+        encode_key(press(Key::Tab, shift), &modes).unwrap(),
+        b"\x1b[Z"
+    );
+    assert_eq!(
+        encode_key(press(Key::Delete, none), &modes).unwrap(),
+        b"\x1b[3~"
+    );
+    assert_eq!(
+        encode_key(press(Key::Delete, shift), &modes).unwrap(),
+        b"\x1b[3;2~"
+    );
+    assert_eq!(
+        encode_key(press(Key::F(1), none), &modes).unwrap(),
+        b"\x1bOP"
+    );
+    assert_eq!(
+        encode_key(press(Key::F(12), none), &modes).unwrap(),
+        b"\x1b[24~"
+    );
+    modes.set_private(1, true);
+    assert_eq!(encode_key(press(up, none), &modes).unwrap(), b"\x1bOA");
+    assert_eq!(
+        encode_key(press(Key::Home, none), &modes).unwrap(),
+        b"\x1bOH"
+    );
+
+    // Repeats are only dropped while DECARM is reset.
+    let repeat = KeyEvent {
+        repeat: true,
+        ..press(up, none)
+    };
+    assert!(encode_key(repeat, &modes).is_some());
+    modes.set_private(8, false);
+    assert!(encode_key(repeat, &modes).is_none());
+    assert!(encode_key(press(up, none), &modes).is_some());
+}
+
+#[test]
+fn test_ctrl_and_alt() {
+    let press = |key, modifiers| KeyEvent {
+        key,
+        modifiers,
+        repeat: false,
+    };
+    let modes = Modes::default();
+    let none = Modifiers::default();
+    let ctrl = Modifiers { ctrl: true, ..none };
+    let alt = Modifiers { alt: true, ..none };
+    let encode = |c, modifiers| encode_key(press(Key::Char(c), modifiers), &modes);
+    assert_eq!(encode('c', ctrl).unwrap(), b"\x03");
+    assert_eq!(encode('a', ctrl).unwrap(), b"\x01");
+    assert_eq!(encode('Z', ctrl).unwrap(), b"\x1a");
+    assert_eq!(encode('[', ctrl).unwrap(), b"\x1b");
+    assert_eq!(encode('\\', ctrl).unwrap(), b"\x1c");
+    assert_eq!(encode(' ', ctrl).unwrap(), b"\0");
+    assert_eq!(encode('1', ctrl), None);
+    assert_eq!(
+        encode('d', Modifiers { alt: true, ..ctrl }).unwrap(),
+        b"\x1b\x04"
+    );
+    assert_eq!(encode('x', alt).unwrap(), b"\x1bx");
+
+    // Alt prefixes keys that are one byte, and is a parameter on the rest.
+    assert_eq!(
+        encode_key(press(Key::Backspace, alt), &modes).unwrap(),
+        b"\x1b\x7f"
+    );
+    assert_eq!(
+        encode_key(press(Key::Arrow(Arrow::Left), alt), &modes).unwrap(),
+        b"\x1b[1;3D"
+    );
+
+    assert_eq!(encode_text("b", alt), b"\x1bb");
+    assert_eq!(encode_text("é", none), "é".as_bytes());
+}
+
+#[test]
+fn test_synthetic_repeat() {
+    let settings = KeyRepeat {
+        delay: Duration::from_millis(500),
+        interval: Duration::from_millis(100),
+    };
+    let press = |key, repeat| KeyEvent {
+        key,
+        modifiers: Modifiers::default(),
+        repeat,
+    };
+    let start = Instant::now();
+    let at = |ms| start + Duration::from_millis(ms);
+    let left = Key::Arrow(Arrow::Left);
+    let mut synthetic = SyntheticRepeat::default();
+    synthetic.press(press(left, false), &settings, start);
+    assert_eq!(synthetic.deadline(), Some(at(500)));
+    assert!(synthetic.due(&settings, at(499)).is_empty());
+    assert_eq!(
+        synthetic.due(&settings, at(720)),
+        vec![press(left, true); 3]
+    );
+    assert_eq!(synthetic.deadline(), Some(at(800)));
+    synthetic.release(Key::Backspace);
+    assert!(synthetic.deadline().is_some());
+    synthetic.release(left);
+    assert!(synthetic.due(&settings, at(2000)).is_empty());
+
+    // Once the OS repeats a key, it's left to the OS.
+    synthetic.press(press(Key::Backspace, false), &settings, start);
+    synthetic.press(press(Key::Backspace, true), &settings, at(500));
+    assert!(synthetic.due(&settings, at(2000)).is_empty());
+    synthetic.press(press(Key::Backspace, false), &settings, at(3000));
+    assert_eq!(synthetic.deadline(), None);
+    synthetic.press(press(left, false), &settings, at(3000));
+    synthetic.stop();
+    assert_eq!(synthetic.deadline(), None);
+}
@@ -0,0 +1,12 @@
+//! The terminal emulator backend for Sesh: a parser for the output of
+//! applications, and a [`terminal::Terminal`] that keeps the screen, the
+//! scrollback, and the modes they set. It doesn't depend on any GUI, so that
+//! sesh can use it to multiplex terminal sessions.
+
+pub mod clock;
+pub mod coords;
+pub mod grid;
+pub mod input;
+pub mod parser;
+pub mod style;
+pub mod terminal;
@@ -1,52 +1,94 @@
 //! This is a debug GUI for the terminal emulator backend I am building for
-//! Sesh. The terminal emulator will be included in this crate, but the GUI
+//! Sesh. The terminal emulator is the library in this crate, but the GUI
 //! stuff is all temporary and for debugging only. The library will be used
 //! in sesh to multiplex terminal sessions and allow multiple applications to
 //! run in the same terminal window. Currently sesh works by piping the output directly
 //! from the server to the client which is very limiting, but this will allow for scrollback,
 //! multiple panes, and proper keymappings.
-use std::ffi::CStr;
+use std::{path::PathBuf, time::Duration};
 
-use anyhow::Result;
-use eframe;
-use gui::TermGui;
-use nix::pty::ForkptyResult;
+use anyhow::{Context, Result};
+use config::Config;
+use gui::{Options, TermGui};
+use termulus::terminal::TerminalBuilder;
 
+mod config;
 mod gui;
-mod parser;
-mod terminal;
+mod screenshot;
+
+/// The shell that tabs and panes start with.
+///
+/// Temporary: sesh already contains the logic for handling process creation
+/// and management. This is just for testing the terminal emulator.
+pub fn shell() -> TerminalBuilder {
+    TerminalBuilder::new()
+        .command("ash")
+        .args(&["--noprofile", "--norc"])
+}
 
 fn main() -> Result<()> {
-    // Temporary: sesh already contains the logic for handling process creation
-    // and management. This is just for testing the terminal emulator.
-    let ForkptyResult {
-        master,
-        fork_result,
-    } = unsafe { nix::pty::forkpty(None, None).unwrap() };
-    let fd = match fork_result {
-        nix::unistd::ForkResult::Parent { .. } => master,
-        nix::unistd::ForkResult::Child => {
-            nix::unistd::execvp::<&CStr>(
-                CStr::from_bytes_with_nul(b"ash\0")?,
-                &[
-                    CStr::from_bytes_with_nul(b"ash\0")?,
-                    CStr::from_bytes_with_nul(b"--noprofile\0").unwrap(),
-                    CStr::from_bytes_with_nul(b"--norc\0").unwrap(),
-                ],
-            )
-            .unwrap();
-            return Ok(());
-        }
+    let config = Config::load()?;
+    let mut options = Options {
+        layout: config.layout,
+        key_repeat: config.key_repeat,
+        ..Options::default()
     };
+    let mut screenshot_test = None;
+    let mut tolerance = 0;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--perf" => options.perf = true,
+            "--inspect" => options.inspect = true,
+            "--screenshot-test" => {
+                let (Some(expected), Some(actual)) = (args.next(), args.next()) else {
+                    anyhow::bail!("usage: --screenshot-test expected.png actual.png");
+                };
+                screenshot_test = Some((PathBuf::from(expected), PathBuf::from(actual)));
+            }
+            "--timeout" => {
+                let seconds: f64 = args
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .filter(|n: &f64| n.is_finite() && *n >= 0.0)
+                    .context("--timeout needs a number of seconds")?;
+                options.timeout = Some(Duration::from_secs_f64(seconds));
+            }
+            "--expect" => {
+                options.expect = Some(args.next().context("--expect needs some text")?);
+            }
+            "--output" => {
+                options.output = Some(args.next().context("--output needs a path")?.into());
+            }
+            "--tolerance" => {
+                tolerance = args
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .context("--tolerance needs a number from 0 to 255")?;
+            }
+            _ => anyhow::bail!("unknown argument: {}", arg),
+        }
+    }
+    if let Some((expected, actual)) = screenshot_test {
+        let matched = screenshot::run_test(&expected, &actual, tolerance)?;
+        std::process::exit(if matched { 0 } else { 1 });
+    }
+
+    let terminal = shell().spawn()?;
 
+    let exit_code = options.exit_code.clone();
     let native_options = eframe::NativeOptions::default();
     eframe::run_native(
         "Debug GUI",
         native_options,
         Box::new(|cc| {
-            let app = TermGui::new(cc, fd);
+            let app = TermGui::new(cc, terminal, options);
             Box::new(app)
         }),
     )
-    .map_err(|e| anyhow::anyhow!("{}", e))
+    .map_err(|e| anyhow::anyhow!("{}", e))?;
+    if exit_code.get() != 0 {
+        std::process::exit(exit_code.get());
+    }
+    Ok(())
 }
@@ -1,20 +1,21 @@
-use std::borrow::Cow;
+use std::{borrow::Cow, collections::VecDeque};
+
+use crate::{
+    coords::WirePoint,
+    grid::LineSize,
+    style::{Color, ColorSlot, UnderlineStyle},
+};
 
 pub trait IsTerminator {
     fn is_csi_terminator(&self) -> bool;
 }
 
 impl IsTerminator for u8 {
+    /// Any byte from `@` to `~` ends a CSI sequence, whether it's one we
+    /// support or not, so that unsupported ones don't swallow the text after
+    /// them.
     fn is_csi_terminator(&self) -> bool {
-        match self {
-            b'A'..=b'H' => true, // Cursor position
-            b'J' | b'K' => true, // Erase display/line
-            b'S' | b'T' => true, // Scroll up/down
-            b'f' => true,        // Horizontal vertical position (?)
-            b'm' => true,        // Select Graphic Rendition (SGR)
-            b's' | b'u' => true, // Save/restore cursor position
-            _ => false,
-        }
+        matches!(self, 0x40..=0x7E)
     }
 }
 
@@ -22,14 +23,721 @@ impl IsTerminator for u8 {
 pub enum TerminalOutput<'a> {
     Ansi(Cow<'a, [u8]>),
     Text(Cow<'a, [u8]>),
-    SetCursorPos { x: usize, y: usize },
+    SetCursorPos(WirePoint),
+    /// CUU (`CSI Ps A`).
+    MoveCursorUp {
+        n: usize,
+    },
+    /// CUD (`CSI Ps B`).
+    MoveCursorDown {
+        n: usize,
+    },
+    /// CUF (`CSI Ps C`).
+    MoveCursorForward {
+        n: usize,
+    },
+    /// CUB (`CSI Ps D`).
+    MoveCursorBack {
+        n: usize,
+    },
+    /// CNL (`CSI Ps E`), down `n` rows to the start of the line.
+    MoveCursorNextLine {
+        n: usize,
+    },
+    /// CPL (`CSI Ps F`), up `n` rows to the start of the line.
+    MoveCursorPrevLine {
+        n: usize,
+    },
+    /// CHA (`CSI Ps G`) or HPA (``CSI Ps ` ``), to column `n` of the same
+    /// row, counting from 1.
+    MoveCursorToColumn {
+        n: usize,
+    },
+    /// VPA (`CSI Ps d`), to row `n` in the same column, counting from 1.
+    MoveCursorToRow {
+        n: usize,
+    },
+    /// DECSTBM (`CSI Ps ; Ps r`), the rows that scroll, counting from 1.
+    /// `None` for the bottom of the screen.
+    SetScrollRegion {
+        top: usize,
+        bottom: Option<usize>,
+    },
+    /// SU (`CSI Ps S`), scroll the scroll region up by `n` lines.
+    ScrollUp {
+        n: usize,
+    },
+    /// SD (`CSI Ps T`), scroll the scroll region down by `n` lines.
+    ScrollDown {
+        n: usize,
+    },
+    /// ICH (`CSI Ps @`), insert `n` blanks at the cursor, shifting the rest of
+    /// the line right.
+    InsertChars {
+        n: usize,
+    },
+    /// DCH (`CSI Ps P`), delete `n` characters at the cursor, shifting the rest
+    /// of the line left.
+    DeleteChars {
+        n: usize,
+    },
+    /// ECH (`CSI Ps X`), blank `n` characters from the cursor, without
+    /// shifting the rest of the line.
+    EraseChars(usize),
+    /// REP (`CSI Ps b`), print the last printed character `n` more times.
+    RepeatChar(usize),
+    /// IL (`CSI Ps L`), insert `n` blank lines at the cursor, pushing the
+    /// lines below it down within the scroll region.
+    InsertLines {
+        n: usize,
+    },
+    /// DL (`CSI Ps M`), delete `n` lines at the cursor, pulling the lines
+    /// below it up within the scroll region.
+    DeleteLines {
+        n: usize,
+    },
+    /// HTS (`ESC H`), set a tab stop at the cursor's column.
+    SetTabStop,
+    /// TBC (`CSI g` or `CSI 3 g`), clear the tab stop at the cursor's column,
+    /// or every tab stop when `all`.
+    ClearTabStop {
+        all: bool,
+    },
+    /// CHT (`CSI Ps I`), move the cursor forward `n` tab stops.
+    TabForward(usize),
+    /// CBT (`CSI Ps Z`), move the cursor back `n` tab stops.
+    TabBackward(usize),
+    /// `CSI Pm h` / `CSI Pm l`, or `CSI ? Pm h` / `CSI ? Pm l` for DEC private modes.
+    SetMode {
+        private: bool,
+        params: Vec<usize>,
+        enable: bool,
+    },
+    /// SGR (`CSI Pm m`), changes the style of the text that follows.
+    Sgr(Vec<SgrAttribute>),
+    /// NEL (`ESC E`, or 0x85 with 8-bit controls), a carriage return and a line feed.
+    NextLine,
+    /// RI (`ESC M`), up a line, scrolling down at the top of the scroll region.
+    ReverseIndex,
+    /// CR (`\r`), back to the start of the line.
+    CarriageReturn,
+    /// LF (`\n`), down a line, scrolling at the bottom of the scroll region.
+    LineFeed,
+    /// HT (`\t`), forward to the next tab stop.
+    Tab,
+    /// BEL (0x07) outside of a string.
+    Bell,
     ClearForwards,
     ClearBackwards,
     ClearAll,
+    /// `CSI 3 J`, clears the scrollback but not the screen.
+    ClearScrollback,
+    /// EL (`CSI 0 K`), from the cursor to the end of its line.
+    ClearLineForwards,
+    /// EL (`CSI 1 K`), from the start of the cursor's line to the cursor.
+    ClearLineBackwards,
+    /// EL (`CSI 2 K`), the whole of the cursor's line.
+    ClearLineAll,
     RestoreCursorPos,
     SaveCursorPos,
-    // I don't have scrollback yet
-    // ClearAllAndScrollback
+    /// DECDWL, DECDHL, and DECSWL (`ESC # 3` to `ESC # 6`), for the cursor's line.
+    SetLineSize(LineSize),
+    /// DECALN (`ESC # 8`), fills the screen with `E`s.
+    AlignmentTest,
+    /// DECRQM (`CSI Ps $ p`, or `CSI ? Ps $ p` for a DEC private mode), asks
+    /// whether a mode is set.
+    RequestMode {
+        private: bool,
+        mode: usize,
+    },
+    /// DSR (`CSI Ps n`), asks for the terminal's status (5) or the cursor
+    /// position (6).
+    DeviceStatusReport(usize),
+    /// DA1 (`CSI c`), or DA2 (`CSI > c`) when `secondary`, asks what kind of
+    /// terminal this is.
+    DeviceAttributes {
+        secondary: bool,
+    },
+    /// XTSAVE (`CSI ? Pm s`), saves the given DEC private modes.
+    SaveModes(Vec<usize>),
+    /// XTRESTORE (`CSI ? Pm r`), restores the given DEC private modes.
+    RestoreModes(Vec<usize>),
+    /// Semantic prompt marks (OSC 133).
+    PromptMark(PromptMarkKind),
+    /// OSC 0 or OSC 2, sets the window title.
+    SetTitle(String),
+    /// OSC 52 with a `?` payload, asking for the contents of a selection.
+    ClipboardQuery {
+        selection: String,
+    },
+    /// OSC 4, 10 to 12, 104, or 110 to 112, which can each change or ask for
+    /// several colors.
+    Colors(Vec<ColorRequest>),
+}
+
+impl TerminalOutput<'_> {
+    /// Copy any borrowed data, so the output can be kept after the input is gone.
+    pub fn into_owned(self) -> TerminalOutput<'static> {
+        match self {
+            TerminalOutput::Ansi(seq) => TerminalOutput::Ansi(Cow::Owned(seq.into_owned())),
+            TerminalOutput::Text(text) => TerminalOutput::Text(Cow::Owned(text.into_owned())),
+            TerminalOutput::SetCursorPos(pos) => TerminalOutput::SetCursorPos(pos),
+            TerminalOutput::MoveCursorUp { n } => TerminalOutput::MoveCursorUp { n },
+            TerminalOutput::MoveCursorDown { n } => TerminalOutput::MoveCursorDown { n },
+            TerminalOutput::MoveCursorForward { n } => TerminalOutput::MoveCursorForward { n },
+            TerminalOutput::MoveCursorBack { n } => TerminalOutput::MoveCursorBack { n },
+            TerminalOutput::MoveCursorNextLine { n } => TerminalOutput::MoveCursorNextLine { n },
+            TerminalOutput::MoveCursorPrevLine { n } => TerminalOutput::MoveCursorPrevLine { n },
+            TerminalOutput::MoveCursorToColumn { n } => TerminalOutput::MoveCursorToColumn { n },
+            TerminalOutput::MoveCursorToRow { n } => TerminalOutput::MoveCursorToRow { n },
+            TerminalOutput::SetScrollRegion { top, bottom } => {
+                TerminalOutput::SetScrollRegion { top, bottom }
+            }
+            TerminalOutput::ScrollUp { n } => TerminalOutput::ScrollUp { n },
+            TerminalOutput::ScrollDown { n } => TerminalOutput::ScrollDown { n },
+            TerminalOutput::InsertChars { n } => TerminalOutput::InsertChars { n },
+            TerminalOutput::DeleteChars { n } => TerminalOutput::DeleteChars { n },
+            TerminalOutput::EraseChars(n) => TerminalOutput::EraseChars(n),
+            TerminalOutput::RepeatChar(n) => TerminalOutput::RepeatChar(n),
+            TerminalOutput::InsertLines { n } => TerminalOutput::InsertLines { n },
+            TerminalOutput::DeleteLines { n } => TerminalOutput::DeleteLines { n },
+            TerminalOutput::SetTabStop => TerminalOutput::SetTabStop,
+            TerminalOutput::ClearTabStop { all } => TerminalOutput::ClearTabStop { all },
+            TerminalOutput::TabForward(n) => TerminalOutput::TabForward(n),
+            TerminalOutput::TabBackward(n) => TerminalOutput::TabBackward(n),
+            TerminalOutput::SetMode {
+                private,
+                params,
+                enable,
+            } => TerminalOutput::SetMode {
+                private,
+                params,
+                enable,
+            },
+            TerminalOutput::Sgr(attributes) => TerminalOutput::Sgr(attributes),
+            TerminalOutput::NextLine => TerminalOutput::NextLine,
+            TerminalOutput::ReverseIndex => TerminalOutput::ReverseIndex,
+            TerminalOutput::CarriageReturn => TerminalOutput::CarriageReturn,
+            TerminalOutput::LineFeed => TerminalOutput::LineFeed,
+            TerminalOutput::Tab => TerminalOutput::Tab,
+            TerminalOutput::Bell => TerminalOutput::Bell,
+            TerminalOutput::ClearForwards => TerminalOutput::ClearForwards,
+            TerminalOutput::ClearBackwards => TerminalOutput::ClearBackwards,
+            TerminalOutput::ClearAll => TerminalOutput::ClearAll,
+            TerminalOutput::ClearScrollback => TerminalOutput::ClearScrollback,
+            TerminalOutput::ClearLineForwards => TerminalOutput::ClearLineForwards,
+            TerminalOutput::ClearLineBackwards => TerminalOutput::ClearLineBackwards,
+            TerminalOutput::ClearLineAll => TerminalOutput::ClearLineAll,
+            TerminalOutput::RestoreCursorPos => TerminalOutput::RestoreCursorPos,
+            TerminalOutput::SaveCursorPos => TerminalOutput::SaveCursorPos,
+            TerminalOutput::SetLineSize(size) => TerminalOutput::SetLineSize(size),
+            TerminalOutput::AlignmentTest => TerminalOutput::AlignmentTest,
+            TerminalOutput::RequestMode { private, mode } => {
+                TerminalOutput::RequestMode { private, mode }
+            }
+            TerminalOutput::DeviceStatusReport(n) => TerminalOutput::DeviceStatusReport(n),
+            TerminalOutput::DeviceAttributes { secondary } => {
+                TerminalOutput::DeviceAttributes { secondary }
+            }
+            TerminalOutput::SaveModes(modes) => TerminalOutput::SaveModes(modes),
+            TerminalOutput::RestoreModes(modes) => TerminalOutput::RestoreModes(modes),
+            TerminalOutput::PromptMark(kind) => TerminalOutput::PromptMark(kind),
+            TerminalOutput::SetTitle(title) => TerminalOutput::SetTitle(title),
+            TerminalOutput::ClipboardQuery { selection } => {
+                TerminalOutput::ClipboardQuery { selection }
+            }
+            TerminalOutput::Colors(requests) => TerminalOutput::Colors(requests),
+        }
+    }
+
+    /// Whether the output only asks for something, like DSR or DA, and leaves
+    /// the screen alone.
+    pub fn is_query(&self) -> bool {
+        match self {
+            TerminalOutput::RequestMode { .. }
+            | TerminalOutput::DeviceStatusReport(_)
+            | TerminalOutput::DeviceAttributes { .. }
+            | TerminalOutput::ClipboardQuery { .. } => true,
+            TerminalOutput::Colors(requests) => requests
+                .iter()
+                .all(|request| matches!(request, ColorRequest::Query(_))),
+            _ => false,
+        }
+    }
+
+    /// A human readable description of the output, e.g. `CSI 5;10H — move
+    /// cursor to row 5, col 10`, for inspecting what a program is sending.
+    ///
+    /// The sequence is rebuilt from the parsed output rather than copied from
+    /// the input, so default parameters are spelled out.
+    pub fn describe(&self) -> String {
+        fn join(params: &[usize]) -> String {
+            params
+                .iter()
+                .map(usize::to_string)
+                .collect::<Vec<_>>()
+                .join(";")
+        }
+        fn modes(private: bool, params: &[usize]) -> String {
+            let kind = if private { "DEC private mode" } else { "mode" };
+            let plural = if params.len() == 1 { "" } else { "s" };
+            let params = params
+                .iter()
+                .map(usize::to_string)
+                .collect::<Vec<_>>()
+                .join(", ");
+            format!("{kind}{plural} {params}")
+        }
+
+        match self {
+            TerminalOutput::Ansi(_) => "unhandled escape sequence".to_owned(),
+            TerminalOutput::Text(text) => format!("text {:?}", String::from_utf8_lossy(text)),
+            TerminalOutput::SetCursorPos(pos) => format!(
+                "CSI {row};{col}H — move cursor to row {row}, col {col}",
+                row = pos.row(),
+                col = pos.col()
+            ),
+            TerminalOutput::MoveCursorUp { n } => format!("CSI {n}A — move cursor up {n}"),
+            TerminalOutput::MoveCursorDown { n } => format!("CSI {n}B — move cursor down {n}"),
+            TerminalOutput::MoveCursorForward { n } => {
+                format!("CSI {n}C — move cursor right {n}")
+            }
+            TerminalOutput::MoveCursorBack { n } => format!("CSI {n}D — move cursor left {n}"),
+            TerminalOutput::MoveCursorNextLine { n } => {
+                format!("CSI {n}E — move cursor to the start of the line {n} down")
+            }
+            TerminalOutput::MoveCursorPrevLine { n } => {
+                format!("CSI {n}F — move cursor to the start of the line {n} up")
+            }
+            TerminalOutput::MoveCursorToColumn { n } => {
+                format!("CSI {n}G — move cursor to col {n}")
+            }
+            TerminalOutput::MoveCursorToRow { n } => format!("CSI {n}d — move cursor to row {n}"),
+            TerminalOutput::SetScrollRegion { top, bottom: None } => {
+                format!("CSI {top}r — scroll rows {top} to the bottom")
+            }
+            TerminalOutput::SetScrollRegion {
+                top,
+                bottom: Some(bottom),
+            } => format!("CSI {top};{bottom}r — scroll rows {top} to {bottom}"),
+            TerminalOutput::ScrollUp { n } => format!("CSI {n}S — scroll up {n}"),
+            TerminalOutput::ScrollDown { n } => format!("CSI {n}T — scroll down {n}"),
+            TerminalOutput::InsertChars { n } => format!("CSI {n}@ — insert {n} blanks"),
+            TerminalOutput::DeleteChars { n } => format!("CSI {n}P — delete {n} chars"),
+            TerminalOutput::EraseChars(n) => format!("CSI {n}X — erase {n} chars"),
+            TerminalOutput::RepeatChar(n) => format!("CSI {n}b — repeat the last char {n} times"),
+            TerminalOutput::InsertLines { n } => format!("CSI {n}L — insert {n} lines"),
+            TerminalOutput::DeleteLines { n } => format!("CSI {n}M — delete {n} lines"),
+            TerminalOutput::SetTabStop => "ESC H — set a tab stop".to_owned(),
+            TerminalOutput::ClearTabStop { all: false } => "CSI g — clear the tab stop".to_owned(),
+            TerminalOutput::ClearTabStop { all: true } => "CSI 3g — clear all tab stops".to_owned(),
+            TerminalOutput::TabForward(n) => format!("CSI {n}I — forward {n} tab stops"),
+            TerminalOutput::TabBackward(n) => format!("CSI {n}Z — back {n} tab stops"),
+            TerminalOutput::SetMode {
+                private,
+                params,
+                enable,
+            } => format!(
+                "CSI {}{}{} — {} {}",
+                if *private { "?" } else { "" },
+                join(params),
+                if *enable { 'h' } else { 'l' },
+                if *enable { "set" } else { "reset" },
+                modes(*private, params)
+            ),
+            TerminalOutput::Sgr(attributes) => {
+                let params = attributes
+                    .iter()
+                    .flat_map(SgrAttribute::params)
+                    .collect::<Vec<_>>();
+                let attributes = attributes
+                    .iter()
+                    .map(|attribute| format!("{:?}", attribute))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                format!("CSI {}m — set text style: {}", join(&params), attributes)
+            }
+            TerminalOutput::NextLine => "ESC E — move to the start of the next line".to_owned(),
+            TerminalOutput::ReverseIndex => "ESC M — move up a line".to_owned(),
+            TerminalOutput::CarriageReturn => "CR — move to the start of the line".to_owned(),
+            TerminalOutput::LineFeed => "LF — move down a line".to_owned(),
+            TerminalOutput::Tab => "HT — move to the next tab stop".to_owned(),
+            TerminalOutput::Bell => "BEL — ring the bell".to_owned(),
+            TerminalOutput::ClearForwards => {
+                "CSI 0J — clear from the cursor to the end of the screen".to_owned()
+            }
+            TerminalOutput::ClearBackwards => {
+                "CSI 1J — clear from the start of the screen to the cursor".to_owned()
+            }
+            TerminalOutput::ClearAll => "CSI 2J — clear the screen".to_owned(),
+            TerminalOutput::ClearScrollback => "CSI 3J — clear the scrollback".to_owned(),
+            TerminalOutput::ClearLineForwards => {
+                "CSI 0K — clear from the cursor to the end of the line".to_owned()
+            }
+            TerminalOutput::ClearLineBackwards => {
+                "CSI 1K — clear from the start of the line to the cursor".to_owned()
+            }
+            TerminalOutput::ClearLineAll => "CSI 2K — clear the line".to_owned(),
+            TerminalOutput::RestoreCursorPos => {
+                "CSI u — restore the cursor position and style".to_owned()
+            }
+            TerminalOutput::SaveCursorPos => {
+                "CSI s — save the cursor position and style".to_owned()
+            }
+            TerminalOutput::SetLineSize(size) => match size {
+                LineSize::DoubleHeightTop => "ESC #3 — double height line, top half",
+                LineSize::DoubleHeightBottom => "ESC #4 — double height line, bottom half",
+                LineSize::Normal => "ESC #5 — single width line",
+                LineSize::DoubleWidth => "ESC #6 — double width line",
+            }
+            .to_owned(),
+            TerminalOutput::AlignmentTest => "ESC #8 — fill the screen with Es".to_owned(),
+            TerminalOutput::RequestMode { private, mode } => format!(
+                "CSI {}{}$p — ask whether {} is set",
+                if *private { "?" } else { "" },
+                mode,
+                modes(*private, &[*mode])
+            ),
+            TerminalOutput::DeviceStatusReport(5) => "CSI 5n — ask for the status".to_owned(),
+            TerminalOutput::DeviceStatusReport(6) => {
+                "CSI 6n — ask for the cursor position".to_owned()
+            }
+            TerminalOutput::DeviceStatusReport(n) => format!("CSI {n}n — ask for status {n}"),
+            TerminalOutput::DeviceAttributes { secondary: false } => {
+                "CSI c — ask for the primary device attributes".to_owned()
+            }
+            TerminalOutput::DeviceAttributes { secondary: true } => {
+                "CSI >c — ask for the secondary device attributes".to_owned()
+            }
+            TerminalOutput::SaveModes(params) => {
+                format!("CSI ?{}s — save {}", join(params), modes(true, params))
+            }
+            TerminalOutput::RestoreModes(params) => {
+                format!("CSI ?{}r — restore {}", join(params), modes(true, params))
+            }
+            TerminalOutput::PromptMark(kind) => match kind {
+                PromptMarkKind::PromptStart => "OSC 133;A — start of the prompt",
+                PromptMarkKind::CommandStart => "OSC 133;B — start of the command",
+                PromptMarkKind::OutputStart => "OSC 133;C — start of the command output",
+                PromptMarkKind::CommandFinished => "OSC 133;D — the command finished",
+            }
+            .to_owned(),
+            TerminalOutput::SetTitle(title) => {
+                format!("OSC 2;{title} — set the title to {title:?}")
+            }
+            TerminalOutput::ClipboardQuery { selection } => {
+                format!("OSC 52;{selection};? — ask for the contents of selection {selection:?}")
+            }
+            TerminalOutput::Colors(requests) => {
+                let (sequences, actions): (Vec<_>, Vec<_>) =
+                    requests.iter().map(ColorRequest::describe).unzip();
+                format!("OSC {} — {}", sequences.join(" OSC "), actions.join(", "))
+            }
+        }
+    }
+}
+
+/// One color to change or ask for, see [`TerminalOutput::Colors`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorRequest {
+    Set(ColorSlot, (u8, u8, u8)),
+    /// A `?` instead of a color, which is answered with the current color.
+    Query(ColorSlot),
+    /// OSC 104 with an index, or OSC 110 to 112.
+    Reset(ColorSlot),
+    /// OSC 104 without an index resets all of the indexed colors.
+    ResetPalette,
+}
+
+impl ColorRequest {
+    /// The sequence for the request, without the `OSC`, and what it does.
+    fn describe(&self) -> (String, String) {
+        fn name(slot: ColorSlot) -> String {
+            match slot {
+                ColorSlot::Indexed(index) => format!("color {}", index),
+                ColorSlot::Foreground => "the foreground color".to_owned(),
+                ColorSlot::Background => "the background color".to_owned(),
+                ColorSlot::Cursor => "the cursor color".to_owned(),
+            }
+        }
+        fn code(slot: ColorSlot) -> String {
+            match slot {
+                ColorSlot::Indexed(index) => format!("4;{}", index),
+                ColorSlot::Foreground => "10".to_owned(),
+                ColorSlot::Background => "11".to_owned(),
+                ColorSlot::Cursor => "12".to_owned(),
+            }
+        }
+        match *self {
+            ColorRequest::Set(slot, (r, g, b)) => (
+                format!("{};rgb:{:02x}/{:02x}/{:02x}", code(slot), r, g, b),
+                format!("set {} to #{:02x}{:02x}{:02x}", name(slot), r, g, b),
+            ),
+            ColorRequest::Query(slot) => (
+                format!("{};?", code(slot)),
+                format!("ask for {}", name(slot)),
+            ),
+            ColorRequest::Reset(slot @ ColorSlot::Indexed(index)) => {
+                (format!("104;{}", index), format!("reset {}", name(slot)))
+            }
+            ColorRequest::Reset(slot) => {
+                (format!("1{}", code(slot)), format!("reset {}", name(slot)))
+            }
+            ColorRequest::ResetPalette => ("104".to_owned(), "reset the palette".to_owned()),
+        }
+    }
+}
+
+/// Parse an X11 color spec as used by OSC 4 and 10 to 12: `rgb:r/g/b` with 1
+/// to 4 hex digits per component, or `#rgb` with 1 to 4 digits per component.
+/// Color names aren't supported.
+fn parse_color_spec(spec: &[u8]) -> Option<(u8, u8, u8)> {
+    let spec = std::str::from_utf8(spec).ok()?;
+    let [r, g, b] = if let Some(rgb) = spec.strip_prefix("rgb:") {
+        let mut parts = rgb.split('/');
+        let components = [parts.next()?, parts.next()?, parts.next()?].map(|part| {
+            if !(1..=4).contains(&part.len()) {
+                return None;
+            }
+            // Scaled, so `f` and `ffff` are both full intensity.
+            let max = 16u32.pow(part.len() as u32) - 1;
+            let value = u32::from_str_radix(part, 16).ok()?;
+            Some((value * 255 + max / 2) / max)
+        });
+        if parts.next().is_some() {
+            return None;
+        }
+        components
+    } else {
+        let hex = spec.strip_prefix('#')?;
+        let len = hex.len() / 3;
+        if hex.len() % 3 != 0 || !(1..=4).contains(&len) {
+            return None;
+        }
+        // Only the most significant digits count, so `#f00` is `#f00000`.
+        [0, 1, 2].map(|i| {
+            let part = hex.get(i * len..(i + 1) * len)?;
+            let value = u32::from_str_radix(part, 16).ok()?;
+            Some(match len {
+                1 => value << 4,
+                len => value >> (4 * (len - 2)),
+            })
+        })
+    };
+    Some((r? as u8, g? as u8, b? as u8))
+}
+
+/// One of the parameters of an SGR sequence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SgrAttribute {
+    /// 0, or no parameters at all.
+    Reset,
+    Bold,
+    Dim,
+    Italic,
+    /// 4 for a single underline, 21 for a double one, or `4:n` for any style.
+    Underline(UnderlineStyle),
+    Reverse,
+    /// 9
+    Strikethrough,
+    /// 53
+    Overline,
+    /// 22, neither bold nor dim.
+    NormalIntensity,
+    NotItalic,
+    NotUnderlined,
+    NotReversed,
+    /// 29
+    NotStrikethrough,
+    /// 55
+    NotOverlined,
+    /// 30-37, 90-97, or 38 followed by an extended color.
+    Foreground(Color),
+    /// 40-47, 100-107, or 48 followed by an extended color.
+    Background(Color),
+    /// 39
+    DefaultForeground,
+    /// 49
+    DefaultBackground,
+    /// 58 followed by an extended color.
+    UnderlineColor(Color),
+    /// 59
+    DefaultUnderlineColor,
+    /// A parameter that isn't supported, kept so it shows up when inspecting.
+    Unknown(u16),
+}
+
+impl SgrAttribute {
+    /// Decode the parameters of an SGR sequence.
+    pub fn parse(params: &[usize]) -> Vec<SgrAttribute> {
+        Self::parse_with_subparams(params, &[])
+    }
+
+    /// Like [`SgrAttribute::parse`], with the colon separated sub-parameters
+    /// of each parameter, so `38:5:196` works as well as `38;5;196`.
+    pub fn parse_with_subparams(
+        params: &[usize],
+        subparams: &[Vec<Option<usize>>],
+    ) -> Vec<SgrAttribute> {
+        if params.is_empty() {
+            return vec![SgrAttribute::Reset];
+        }
+        let mut attributes = Vec::new();
+        let mut params = params.iter().copied().enumerate();
+        while let Some((i, param)) = params.next() {
+            let subparams = subparams.get(i).map_or(&[][..], Vec::as_slice);
+            let attribute = match param {
+                // `4:0` turns underlining off, the other styles (curly,
+                // dotted...) are drawn as a plain underline.
+                4 if !subparams.is_empty() => {
+                    match subparams[0].and_then(UnderlineStyle::from_subparam) {
+                        Some(UnderlineStyle::None) => SgrAttribute::NotUnderlined,
+                        Some(style) => SgrAttribute::Underline(style),
+                        None => SgrAttribute::Unknown(param as u16),
+                    }
+                }
+                38 | 48 | 58 if !subparams.is_empty() => match subparam_color(subparams) {
+                    Some(color) if param == 38 => SgrAttribute::Foreground(color),
+                    Some(color) if param == 48 => SgrAttribute::Background(color),
+                    Some(color) => SgrAttribute::UnderlineColor(color),
+                    None => SgrAttribute::Unknown(param as u16),
+                },
+                0 => SgrAttribute::Reset,
+                1 => SgrAttribute::Bold,
+                2 => SgrAttribute::Dim,
+                3 => SgrAttribute::Italic,
+                4 => SgrAttribute::Underline(UnderlineStyle::Single),
+                7 => SgrAttribute::Reverse,
+                9 => SgrAttribute::Strikethrough,
+                21 => SgrAttribute::Underline(UnderlineStyle::Double),
+                22 => SgrAttribute::NormalIntensity,
+                23 => SgrAttribute::NotItalic,
+                24 => SgrAttribute::NotUnderlined,
+                27 => SgrAttribute::NotReversed,
+                29 => SgrAttribute::NotStrikethrough,
+                30..=37 => SgrAttribute::Foreground(named(param - 30)),
+                39 => SgrAttribute::DefaultForeground,
+                40..=47 => SgrAttribute::Background(named(param - 40)),
+                49 => SgrAttribute::DefaultBackground,
+                90..=97 => SgrAttribute::Foreground(named(param - 90 + 8)),
+                100..=107 => SgrAttribute::Background(named(param - 100 + 8)),
+                38 | 48 | 58 => {
+                    match extended_color(&mut params.by_ref().map(|(_, param)| param)) {
+                        Some(color) if param == 38 => SgrAttribute::Foreground(color),
+                        Some(color) if param == 48 => SgrAttribute::Background(color),
+                        Some(color) => SgrAttribute::UnderlineColor(color),
+                        None => SgrAttribute::Unknown(param as u16),
+                    }
+                }
+                53 => SgrAttribute::Overline,
+                55 => SgrAttribute::NotOverlined,
+                59 => SgrAttribute::DefaultUnderlineColor,
+                _ => SgrAttribute::Unknown(param.min(u16::MAX as usize) as u16),
+            };
+            attributes.push(attribute);
+        }
+        attributes
+    }
+
+    /// The parameters that produce this attribute, the reverse of [`SgrAttribute::parse`].
+    pub fn params(&self) -> Vec<usize> {
+        fn color(base: usize, color: Color) -> Vec<usize> {
+            match color {
+                Color::Indexed(index) => vec![base + 8, 5, index as usize],
+                Color::Rgb(r, g, b) => vec![base + 8, 2, r as usize, g as usize, b as usize],
+                named => {
+                    let index = named.index().unwrap_or_default() as usize;
+                    if index < 8 {
+                        vec![base + index]
+                    } else {
+                        // The bright colors start at 90 and 100.
+                        vec![base + 60 + index - 8]
+                    }
+                }
+            }
+        }
+        match self {
+            SgrAttribute::Reset => vec![0],
+            SgrAttribute::Bold => vec![1],
+            SgrAttribute::Dim => vec![2],
+            SgrAttribute::Italic => vec![3],
+            SgrAttribute::Underline(UnderlineStyle::Double) => vec![21],
+            // The other styles need a sub-parameter, which can't be expressed
+            // here, but the description still names them.
+            SgrAttribute::Underline(_) => vec![4],
+            SgrAttribute::Reverse => vec![7],
+            SgrAttribute::Strikethrough => vec![9],
+            SgrAttribute::Overline => vec![53],
+            SgrAttribute::NotOverlined => vec![55],
+            SgrAttribute::NormalIntensity => vec![22],
+            SgrAttribute::NotItalic => vec![23],
+            SgrAttribute::NotUnderlined => vec![24],
+            SgrAttribute::NotReversed => vec![27],
+            SgrAttribute::NotStrikethrough => vec![29],
+            SgrAttribute::Foreground(c) => color(30, *c),
+            SgrAttribute::Background(c) => color(40, *c),
+            SgrAttribute::DefaultForeground => vec![39],
+            SgrAttribute::DefaultBackground => vec![49],
+            SgrAttribute::UnderlineColor(Color::Rgb(r, g, b)) => {
+                vec![58, 2, *r as usize, *g as usize, *b as usize]
+            }
+            SgrAttribute::UnderlineColor(c) => vec![58, 5, c.index().unwrap_or_default() as usize],
+            SgrAttribute::DefaultUnderlineColor => vec![59],
+            SgrAttribute::Unknown(param) => vec![*param as usize],
+        }
+    }
+}
+
+fn named(index: usize) -> Color {
+    Color::named(index as u8).expect("index to be one of the 16 named colors")
+}
+
+/// The color after a 38 or 48: `5;n` for an indexed color or `2;r;g;b` for
+/// RGB. Missing RGB components are 0, like in xterm.
+fn extended_color(params: &mut impl Iterator<Item = usize>) -> Option<Color> {
+    match params.next()? {
+        5 => match params.next()? {
+            index @ 0..=255 => Some(Color::Indexed(index as u8)),
+            _ => None,
+        },
+        2 => {
+            let mut component = || params.next().unwrap_or(0).min(255) as u8;
+            Some(Color::Rgb(component(), component(), component()))
+        }
+        _ => None,
+    }
+}
+
+/// An extended color from colon separated sub-parameters: `5:n`, or `2:r:g:b`
+/// with or without the color space id that ITU T.416 puts before the
+/// components, as in `2::r:g:b`. Missing components are 0.
+fn subparam_color(subparams: &[Option<usize>]) -> Option<Color> {
+    let get = |i: usize| subparams.get(i).copied().flatten();
+    match get(0)? {
+        5 => match get(1)? {
+            index @ 0..=255 => Some(Color::Indexed(index as u8)),
+            _ => None,
+        },
+        2 => {
+            let first = if subparams.len() > 4 { 2 } else { 1 };
+            let component = |i: usize| get(first + i).unwrap_or(0).min(255) as u8;
+            Some(Color::Rgb(component(0), component(1), component(2)))
+        }
+        _ => None,
+    }
+}
+
+/// The kinds of marks shells emit with OSC 133 to delimit prompts and commands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PromptMarkKind {
+    /// `A`: the prompt is about to be drawn.
+    PromptStart,
+    /// `B`: the prompt is done and the user is typing a command.
+    CommandStart,
+    /// `C`: the command was submitted and its output follows.
+    OutputStart,
+    /// `D`: the command finished.
+    CommandFinished,
 }
 
 /// Push a byte into a Cow<'a, [u8]>
@@ -46,26 +754,20 @@ pub enum TerminalOutput<'a> {
 /// - `&slice[slice.len()] <= &input[input.len()]` (the slice  is within the input)
 unsafe fn push_byte(slice: &mut Cow<'_, [u8]>, byte: &u8) {
     match slice {
-        Cow::Borrowed(slice) => {
-            assert!(byte as *const u8 >= *slice as *const [u8] as *const u8);
-            // // These assertions cannot be made at the moment because we do not have the original
-            // // input in the Csi parser, and the original input is not necessarily a slice or contiguous
-            // // in memory.
-            // assert!(byte >= &input[0] && byte < &input[input.len()]);
-            // assert!(&slice[0] >= &input[0] && &slice[0] <= &input[input.len()]);
-            // assert!(&slice[slice.len()] <= &input[input.len()]);
-            let len = slice.len();
-            if len > 0 {
-                // If the slice is borrowed and non-empty, the byte should *always*
-                // be located directly after the end of the slice.
-                assert_eq!(
-                    byte as *const u8 as usize,
-                    *slice as *const [u8] as *const u8 as usize + len
-                );
-                let start = *slice as *const [u8] as *const u8;
-                *slice = unsafe { std::slice::from_raw_parts(start, len + 1) };
+        Cow::Borrowed(borrowed) => {
+            let len = borrowed.len();
+            let start = borrowed.as_ptr();
+            if len == 0 {
+                *slice = Cow::Borrowed(unsafe { std::slice::from_raw_parts(byte, 1) });
+            } else if std::ptr::eq(byte, start.wrapping_add(len)) {
+                *slice = Cow::Borrowed(unsafe { std::slice::from_raw_parts(start, len + 1) });
             } else {
-                *slice = unsafe { std::slice::from_raw_parts(byte, 1) };
+                // Bytes in between were skipped, so the slice can't be grown
+                // over them. Malformed input shouldn't bring the host down, so
+                // the bytes are copied instead.
+                let mut vec = borrowed.to_vec();
+                vec.push(*byte);
+                *slice = Cow::Owned(vec);
             }
         }
         Cow::Owned(vec) => {
@@ -83,33 +785,62 @@ pub enum CsiState<'a> {
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct CsiParser<'a> {
     state: CsiState<'a>,
+    /// The private marker (`<`, `=`, `>`, or `?`) if the sequence starts with one.
+    private: Option<u8>,
+    /// The intermediate byte (`0x20..=0x2F`) before the final byte, like the
+    /// `$` in `CSI ? Ps $ p`.
+    intermediate: Option<u8>,
     args: Vec<usize>,
+    /// The sub-parameters of each argument: the values after it separated by
+    /// colons, like the `2::255:0:0` in `38:2::255:0:0`. Empty ones are `None`.
+    subparams: Vec<Vec<Option<usize>>>,
+    /// Whether the argument being accumulated is a sub-parameter.
+    in_subparams: bool,
+}
+
+impl Default for CsiParser<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl<'a> CsiParser<'a> {
     pub fn new() -> Self {
         Self {
             state: CsiState::Argument(Cow::Borrowed(&[])),
+            private: None,
+            intermediate: None,
             args: Vec::new(),
+            subparams: Vec::new(),
+            in_subparams: false,
         }
     }
 
+    fn push_arg(&mut self, arg: usize) {
+        self.args.push(arg);
+        self.subparams.push(Vec::new());
+    }
+
+    fn push_subparam(&mut self, subparam: Option<usize>) {
+        self.subparams
+            .last_mut()
+            .expect("a sub-parameter to follow an argument")
+            .push(subparam);
+    }
+
     pub fn has_incomplete_output(&self) -> bool {
         match &self.state {
-            CsiState::Argument(slice) => slice.len() > 0,
+            CsiState::Argument(slice) => !slice.is_empty(),
             CsiState::Finished(_) => false,
         }
     }
 
     pub fn take_incomplete(&mut self) {
         // Take ownership of any incomplete data.
-        match &mut self.state {
-            CsiState::Argument(arg @ Cow::Borrowed(_)) => {
-                if arg.len() > 0 {
-                    *arg = Cow::Owned(arg.to_vec());
-                }
+        if let CsiState::Argument(arg @ Cow::Borrowed(_)) = &mut self.state {
+            if !arg.is_empty() {
+                *arg = Cow::Owned(arg.to_vec());
             }
-            _ => {}
         }
     }
 
@@ -118,13 +849,14 @@ impl<'a> CsiParser<'a> {
             panic!("attempted to push byte into finished CSI sequence");
         }
 
-        fn accumulate(slice: &Cow<'_, [u8]>) -> Option<usize> {
-            if slice.len() > 0 {
-                let str = unsafe {
-                    // Safety: we know that the slice contains only ascii digits
-                    std::str::from_utf8_unchecked(slice)
-                };
-                Some(usize::from_str_radix(str, 10).expect("to have already validated the input"))
+        /// The value of a parameter, which only ever holds ASCII digits. Huge
+        /// ones are capped, so that the arithmetic done with them can't overflow.
+        fn accumulate(slice: &[u8]) -> Option<usize> {
+            const MAX: usize = u16::MAX as usize;
+            if !slice.is_empty() {
+                Some(slice.iter().fold(0, |value: usize, digit| {
+                    (value * 10 + (digit - b'0') as usize).min(MAX)
+                }))
             } else {
                 None
             }
@@ -132,36 +864,61 @@ impl<'a> CsiParser<'a> {
 
         match &mut self.state {
             CsiState::Argument(slice) => match byte {
+                b'<'..=b'?'
+                    if slice.is_empty() && self.args.is_empty() && self.private.is_none() =>
+                {
+                    self.private = Some(*byte);
+                }
+                b' '..=b'/' if self.intermediate.is_none() => {
+                    self.intermediate = Some(*byte);
+                }
                 byte if byte.is_csi_terminator() => {
-                    if let Some(arg) = accumulate(slice) {
-                        self.args.push(arg);
+                    let arg = accumulate(slice);
+                    if std::mem::take(&mut self.in_subparams) {
+                        self.push_subparam(arg);
+                    } else {
+                        match arg {
+                            Some(arg) => self.push_arg(arg),
+                            // Only `CSI m` and the like have no parameters at
+                            // all, a missing one after a `;` is a 0.
+                            None if !self.args.is_empty() => self.push_arg(0),
+                            None => {}
+                        }
                     }
                     self.state = CsiState::Finished(*byte);
                 }
                 b';' => {
-                    if let Some(arg) = accumulate(slice) {
-                        self.args.push(arg);
+                    let arg = accumulate(slice);
+                    if std::mem::take(&mut self.in_subparams) {
+                        self.push_subparam(arg);
+                    } else {
+                        // An empty parameter, as in `CSI ;5H`, is a 0, which
+                        // means the default.
+                        self.push_arg(arg.unwrap_or(0));
+                    }
+                    self.state = CsiState::Argument(Cow::Borrowed(&[]));
+                }
+                b':' => {
+                    let arg = accumulate(slice);
+                    if self.in_subparams {
+                        self.push_subparam(arg);
+                    } else {
+                        self.push_arg(arg.unwrap_or(0));
+                        self.in_subparams = true;
                     }
                     self.state = CsiState::Argument(Cow::Borrowed(&[]));
                 }
                 byte if byte.is_ascii_digit() => unsafe {
                     push_byte(slice, byte);
                 },
-                byte => {
+                _ => {
                     //NOTE: temporary
                     // We need to take ownership of the slice when we encounted invalid data
                     // because the valid data is no longer contiguous in memory as it is separated
                     // by invalid data.
-                    match slice {
-                        Cow::Borrowed(ref s) => {
-                            *slice = Cow::Owned(s.to_vec());
-                        }
-                        Cow::Owned(_) => {}
-                    };
-                    println!(
-                        "invalid byte in CSI sequence: {} ('{}')",
-                        byte, *byte as char
-                    );
+                    if let Cow::Borrowed(s) = slice {
+                        *slice = Cow::Owned(s.to_vec());
+                    }
                 }
             },
             CsiState::Finished(_) => unreachable!(),
@@ -169,11 +926,150 @@ impl<'a> CsiParser<'a> {
     }
 }
 
+/// Accumulates an operating system command (`ESC ] Ps ; Pt`) until it is
+/// terminated by BEL or ST.
+///
+/// OSC strings are short and rare compared to text and CSI sequences, so the
+/// data is always owned rather than borrowed from the input. This also means
+/// a string split across reads doesn't need any special handling.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct OscParser {
+    data: Vec<u8>,
+}
+
+impl OscParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, byte: &u8) {
+        self.data.push(*byte);
+    }
+
+    pub fn finish(self) -> TerminalOutput<'static> {
+        let mut parts = self.data.splitn(2, |b| *b == b';');
+        let code = parts.next().unwrap_or_default();
+        let rest = parts.next().unwrap_or_default();
+        match code {
+            // OSC 1 only sets the icon name, which there's nowhere to show.
+            b"0" | b"2" => {
+                return TerminalOutput::SetTitle(String::from_utf8_lossy(rest).into_owned());
+            }
+            b"52" => {
+                let mut parts = rest.splitn(2, |b| *b == b';');
+                let selection = parts.next().unwrap_or_default();
+                let data = parts.next().unwrap_or_default();
+                if data == b"?" {
+                    return TerminalOutput::ClipboardQuery {
+                        selection: String::from_utf8_lossy(selection).into_owned(),
+                    };
+                }
+            }
+            b"4" => {
+                let mut requests = Vec::new();
+                let mut parts = rest.split(|b| *b == b';');
+                while let (Some(index), Some(spec)) = (parts.next(), parts.next()) {
+                    let Some(index) = std::str::from_utf8(index)
+                        .ok()
+                        .and_then(|index| index.parse().ok())
+                    else {
+                        continue;
+                    };
+                    let slot = ColorSlot::Indexed(index);
+                    if spec == b"?" {
+                        requests.push(ColorRequest::Query(slot));
+                    } else if let Some(rgb) = parse_color_spec(spec) {
+                        requests.push(ColorRequest::Set(slot, rgb));
+                    }
+                }
+                if !requests.is_empty() {
+                    return TerminalOutput::Colors(requests);
+                }
+            }
+            b"10" | b"11" | b"12" => {
+                // Each color after the first applies to the next slot, so
+                // `OSC 10;?;?` asks for both the foreground and background.
+                let slots = [
+                    ColorSlot::Foreground,
+                    ColorSlot::Background,
+                    ColorSlot::Cursor,
+                ];
+                let first = (code[1] - b'0') as usize;
+                let requests = slots[first..]
+                    .iter()
+                    .zip(rest.split(|b| *b == b';'))
+                    .filter_map(|(slot, spec)| match spec {
+                        b"?" => Some(ColorRequest::Query(*slot)),
+                        spec => Some(ColorRequest::Set(*slot, parse_color_spec(spec)?)),
+                    })
+                    .collect::<Vec<_>>();
+                if !requests.is_empty() {
+                    return TerminalOutput::Colors(requests);
+                }
+            }
+            b"104" if rest.is_empty() => {
+                return TerminalOutput::Colors(vec![ColorRequest::ResetPalette]);
+            }
+            b"104" => {
+                let requests = rest
+                    .split(|b| *b == b';')
+                    .filter_map(|index| std::str::from_utf8(index).ok()?.parse().ok())
+                    .map(|index| ColorRequest::Reset(ColorSlot::Indexed(index)))
+                    .collect::<Vec<_>>();
+                if !requests.is_empty() {
+                    return TerminalOutput::Colors(requests);
+                }
+            }
+            b"110" => {
+                return TerminalOutput::Colors(vec![ColorRequest::Reset(ColorSlot::Foreground)])
+            }
+            b"111" => {
+                return TerminalOutput::Colors(vec![ColorRequest::Reset(ColorSlot::Background)])
+            }
+            b"112" => return TerminalOutput::Colors(vec![ColorRequest::Reset(ColorSlot::Cursor)]),
+            b"133" => {
+                // Anything after the kind is an option (e.g. the exit status for D),
+                // which isn't used yet.
+                let kind = match rest.first() {
+                    Some(b'A') => Some(PromptMarkKind::PromptStart),
+                    Some(b'B') => Some(PromptMarkKind::CommandStart),
+                    Some(b'C') => Some(PromptMarkKind::OutputStart),
+                    Some(b'D') => Some(PromptMarkKind::CommandFinished),
+                    _ => None,
+                };
+                if let Some(kind) = kind {
+                    return TerminalOutput::PromptMark(kind);
+                }
+            }
+            _ => {}
+        }
+        // TODO: temporary
+        TerminalOutput::Ansi(Cow::Borrowed(&[]))
+    }
+}
+
+/// Control strings that are collected until the string terminator, but not
+/// interpreted yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StringKind {
+    /// `ESC P`, device control strings (DECRQSS, sixel, tmux passthrough).
+    Dcs,
+    /// `ESC _`, application program commands (kitty graphics).
+    Apc,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum AnsiBuilder<'a> {
     Empty,
     Esc,
+    /// An escape sequence with an intermediate byte (`0x20..=0x2F`), e.g. the
+    /// `#` in `ESC # 6`. The next byte is the final byte.
+    EscIntermediate(u8),
     Csi(CsiParser<'a>),
+    Osc(OscParser),
+    /// A DCS or APC string, terminated by ST. None are supported, so the
+    /// contents are skipped rather than kept.
+    ControlString(StringKind),
 }
 
 pub struct OutputParser<'a> {
@@ -184,16 +1080,113 @@ pub struct OutputParser<'a> {
     /// and only return complete ones, and then attempt to
     /// resume parsing on the next input.
     partial: Cow<'a, [u8]>,
+    /// How many control strings have been started, so that a string that is
+    /// still open can be told apart from a new one.
+    strings_started: u64,
+    /// Whether raw C1 control bytes (0x80..=0x9F) are controls rather than
+    /// part of the text. They can't be told apart from UTF-8 continuation
+    /// bytes, so this is off unless the application is known to send them.
+    eight_bit_controls: bool,
+    /// Kept between calls to [`OutputParser::feed`] so that its allocation
+    /// can be reused. Always empty in between.
+    ready: VecDeque<TerminalOutput<'a>>,
+}
+
+/// The outputs parsed from one input, from [`OutputParser::feed`].
+pub struct Outputs<'p, 'a> {
+    parser: &'p mut OutputParser<'a>,
+    bytes: &'p [u8],
+    /// How many of `bytes` have been parsed.
+    pos: usize,
+    /// Outputs that have been parsed but not returned yet. One byte can
+    /// finish a run of text and be a control itself.
+    ready: VecDeque<TerminalOutput<'a>>,
+    /// Whether the end of the input has been handled.
+    finished: bool,
+}
+
+impl<'p, 'a> Iterator for Outputs<'p, 'a> {
+    type Item = TerminalOutput<'p>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(output) = self.ready.pop_front() {
+                return Some(output);
+            }
+            if let Some(byte) = self.bytes.get(self.pos) {
+                self.pos += 1;
+                self.parser.step(self.bytes, byte, &mut self.ready);
+            } else if !self.finished {
+                self.finished = true;
+                if let Some(text) = self.parser.partial_take() {
+                    return Some(TerminalOutput::Text(text));
+                }
+            } else {
+                return None;
+            }
+        }
+    }
+}
+
+impl Drop for Outputs<'_, '_> {
+    fn drop(&mut self) {
+        // The partial buffer may borrow from the input, so the end of it has
+        // to be reached before the input goes away.
+        self.for_each(drop);
+        self.parser.ready = std::mem::take(&mut self.ready);
+    }
 }
 
 pub const ESC: u8 = 0x1B; // ESCAPE
 pub const CSI: u8 = 0x5B; // '['
+pub const OSC: u8 = 0x5D; // ']'
+pub const BEL: u8 = 0x07; // BELL, also terminates OSC strings
+pub const ST: u8 = 0x5C; // the final byte of the `ESC \` string terminator
+
+impl Default for OutputParser<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl<'a> OutputParser<'a> {
     pub fn new() -> Self {
         Self {
             state: AnsiBuilder::Empty,
             partial: Cow::Borrowed(&[]),
+            eight_bit_controls: false,
+            strings_started: 0,
+            ready: VecDeque::new(),
+        }
+    }
+
+    /// The kind of control string being collected, if one has been started but
+    /// not terminated, along with a number that identifies it.
+    pub fn open_string(&self) -> Option<(StringKind, u64)> {
+        match self.state {
+            AnsiBuilder::ControlString(kind) => Some((kind, self.strings_started)),
+            _ => None,
+        }
+    }
+
+    /// Throw away an unterminated control string, going back to text.
+    pub fn abort_string(&mut self) {
+        if let AnsiBuilder::ControlString(..) = self.state {
+            self.state = AnsiBuilder::Empty;
+        }
+    }
+
+    pub fn set_eight_bit_controls(&mut self, enable: bool) {
+        self.eight_bit_controls = enable;
+    }
+
+    /// Output any text that has been accumulated so far.
+    fn flush_text(&mut self, output: &mut VecDeque<TerminalOutput<'a>>) {
+        if !self.partial.is_empty() {
+            output.push_back(TerminalOutput::Text(std::mem::replace(
+                &mut self.partial,
+                Cow::Borrowed(&[]),
+            )));
         }
     }
 
@@ -220,7 +1213,7 @@ impl<'a> OutputParser<'a> {
             AnsiBuilder::Empty => {
                 // Since we are at the end of the input and the input state is text, we can
                 // send the text buffer as a segment.
-                if self.partial.len() > 0 {
+                if !self.partial.is_empty() {
                     Some(std::mem::replace(&mut self.partial, Cow::Borrowed(&[])))
                 } else {
                     None
@@ -232,6 +1225,10 @@ impl<'a> OutputParser<'a> {
                 }
                 None
             }
+            // OSC data is always owned, so there's nothing to preserve.
+            AnsiBuilder::Osc(_) => None,
+            AnsiBuilder::ControlString(..) => None,
+            AnsiBuilder::EscIntermediate(_) => None,
             AnsiBuilder::Esc => match &self.partial {
                 // If the partial buffer is borrowed and we have incomplete escape
                 // sequences, we need to preserve the buffer for the next parsing
@@ -242,7 +1239,7 @@ impl<'a> OutputParser<'a> {
                 // input and we need to preserve the partial buffer across multiple
                 // reads.
                 Cow::Borrowed(slice) => {
-                    if slice.len() > 0 {
+                    if !slice.is_empty() {
                         let vec = slice.to_vec();
                         self.partial = Cow::Owned(vec);
                     }
@@ -254,99 +1251,334 @@ impl<'a> OutputParser<'a> {
         }
     }
 
-    pub fn parse(&mut self, bytes: &[u8]) -> Vec<TerminalOutput> {
-        if self.partial.len() == 0 {
+    /// Parse `bytes`, collecting the outputs. See [`OutputParser::feed`].
+    pub fn parse<'p>(&'p mut self, bytes: &'p [u8]) -> Vec<TerminalOutput<'p>> {
+        self.feed(bytes).collect()
+    }
+
+    /// Parse `bytes`, producing outputs as they're asked for. An escape
+    /// sequence that isn't finished by the end of `bytes` is kept for the next
+    /// call, the same as with [`OutputParser::parse`].
+    ///
+    /// Dropping the iterator early still parses the rest of `bytes`, throwing
+    /// the outputs away, so that the parser is ready for the next input.
+    pub fn feed<'p>(&'p mut self, bytes: &'p [u8]) -> Outputs<'p, 'a> {
+        if self.partial.is_empty() {
             self.partial = Cow::Borrowed(unsafe {
                 std::slice::from_raw_parts(bytes as *const [u8] as *const u8, 0)
             });
         }
-        let mut output: Vec<TerminalOutput> = Vec::new();
-        for byte in bytes {
-            match self.state {
-                AnsiBuilder::Empty => match byte {
-                    &ESC => {
-                        if self.partial.len() > 0 {
-                            let segment = TerminalOutput::Text(std::mem::replace(
-                                &mut self.partial,
-                                Cow::Borrowed(unsafe {
-                                    std::slice::from_raw_parts(bytes as *const [u8] as *const u8, 0)
-                                }),
-                            ));
-                            output.push(segment);
-                        }
-                        self.state = AnsiBuilder::Esc;
+        let ready = std::mem::take(&mut self.ready);
+        Outputs {
+            parser: self,
+            bytes,
+            pos: 0,
+            ready,
+            finished: false,
+        }
+    }
+
+    /// Parse one byte of `bytes`, adding any outputs it finishes to `output`.
+    fn step(&mut self, bytes: &[u8], byte: &u8, output: &mut VecDeque<TerminalOutput<'a>>) {
+        match self.state {
+            AnsiBuilder::Empty => match byte {
+                &ESC => {
+                    if !self.partial.is_empty() {
+                        let segment = TerminalOutput::Text(std::mem::replace(
+                            &mut self.partial,
+                            Cow::Borrowed(unsafe {
+                                std::slice::from_raw_parts(bytes as *const [u8] as *const u8, 0)
+                            }),
+                        ));
+                        output.push_back(segment);
+                    }
+                    self.state = AnsiBuilder::Esc;
+                }
+                0x85 if self.eight_bit_controls => {
+                    self.flush_text(output);
+                    output.push_back(TerminalOutput::NextLine);
+                }
+                b'\r' | b'\n' | b'\t' | &BEL => {
+                    self.flush_text(output);
+                    output.push_back(match byte {
+                        b'\r' => TerminalOutput::CarriageReturn,
+                        b'\n' => TerminalOutput::LineFeed,
+                        b'\t' => TerminalOutput::Tab,
+                        _ => TerminalOutput::Bell,
+                    });
+                }
+                _ => {
+                    self.partial_push(byte);
+                }
+            },
+            AnsiBuilder::Esc => match byte {
+                &CSI => {
+                    self.state = AnsiBuilder::Csi(CsiParser::new());
+                }
+                &OSC => {
+                    self.state = AnsiBuilder::Osc(OscParser::new());
+                }
+                b'P' | b'_' => {
+                    let kind = if *byte == b'P' {
+                        StringKind::Dcs
+                    } else {
+                        StringKind::Apc
+                    };
+                    self.strings_started += 1;
+                    self.state = AnsiBuilder::ControlString(kind);
+                }
+                &ST => {
+                    // The string terminator for an OSC string, which has already been
+                    // dispatched when the ESC was seen.
+                    self.state = AnsiBuilder::Empty;
+                }
+                0x20..=0x2F => {
+                    self.state = AnsiBuilder::EscIntermediate(*byte);
+                }
+                b'E' => {
+                    output.push_back(TerminalOutput::NextLine);
+                    self.state = AnsiBuilder::Empty;
+                }
+                b'M' => {
+                    output.push_back(TerminalOutput::ReverseIndex);
+                    self.state = AnsiBuilder::Empty;
+                }
+                b'H' => {
+                    output.push_back(TerminalOutput::SetTabStop);
+                    self.state = AnsiBuilder::Empty;
+                }
+                // DECSC and DECRC.
+                b'7' => {
+                    output.push_back(TerminalOutput::SaveCursorPos);
+                    self.state = AnsiBuilder::Empty;
+                }
+                b'8' => {
+                    output.push_back(TerminalOutput::RestoreCursorPos);
+                    self.state = AnsiBuilder::Empty;
+                }
+                // Another ESC starts over, and other controls in the
+                // middle of a sequence are ignored.
+                0x00..=0x1F => {}
+                // Any other final byte ends the sequence, e.g. `ESC c`, none
+                // of which are supported yet.
+                0x30..=0x7E => {
+                    // TODO: temporary
+                    output.push_back(TerminalOutput::Ansi(Cow::Borrowed(&[])));
+                    self.state = AnsiBuilder::Empty;
+                }
+                // Not part of an escape sequence at all.
+                _ => self.state = AnsiBuilder::Empty,
+            },
+            AnsiBuilder::EscIntermediate(intermediate) => {
+                let line_size = |size| Some(TerminalOutput::SetLineSize(size));
+                let sequence = match (intermediate, byte) {
+                    (b'#', b'3') => line_size(LineSize::DoubleHeightTop),
+                    (b'#', b'4') => line_size(LineSize::DoubleHeightBottom),
+                    (b'#', b'5') => line_size(LineSize::Normal),
+                    (b'#', b'6') => line_size(LineSize::DoubleWidth),
+                    (b'#', b'8') => Some(TerminalOutput::AlignmentTest),
+                    _ => None,
+                };
+                // Unsupported ones are ignored, like other escape sequences.
+                output.push_back(sequence.unwrap_or(TerminalOutput::Ansi(Cow::Borrowed(&[]))));
+                self.state = AnsiBuilder::Empty;
+            }
+            AnsiBuilder::Osc(ref mut parser) => match byte {
+                &BEL | &ESC => {
+                    let parser = std::mem::take(parser);
+                    output.push_back(parser.finish());
+                    self.state = if *byte == ESC {
+                        AnsiBuilder::Esc
+                    } else {
+                        AnsiBuilder::Empty
+                    };
+                }
+                _ => parser.push(byte),
+            },
+            AnsiBuilder::ControlString(_) => {
+                // The ESC starts the string terminator, which the Esc state
+                // swallows. The whole string is passed on as unsupported.
+                if *byte == ESC {
+                    output.push_back(TerminalOutput::Ansi(Cow::Borrowed(&[])));
+                    self.state = AnsiBuilder::Esc;
+                }
+            }
+            AnsiBuilder::Csi(ref mut parser) => {
+                parser.push(byte);
+                match parser.state {
+                    CsiState::Argument(_) => {}
+                    CsiState::Finished(b'p') if parser.intermediate == Some(b'$') => {
+                        output.push_back(TerminalOutput::RequestMode {
+                            private: parser.private == Some(b'?'),
+                            mode: parser.args.first().copied().unwrap_or(0),
+                        });
+                        self.state = AnsiBuilder::Empty;
                     }
-                    _ => {
-                        self.partial_push(byte);
+                    // An intermediate byte makes it a different command, e.g.
+                    // SL (`CSI Ps SP @`) rather than ICH, and DECRQM is the
+                    // only one of those that's supported.
+                    CsiState::Finished(_) if parser.intermediate.is_some() => {
+                        output.push_back(TerminalOutput::Ansi(Cow::Borrowed(&[])));
+                        self.state = AnsiBuilder::Empty;
                     }
-                },
-                AnsiBuilder::Esc => match byte {
-                    &CSI => {
-                        self.state = AnsiBuilder::Csi(CsiParser::new());
+                    CsiState::Finished(b'H' | b'f') => {
+                        // `CSI row ; col H`, where either can be left out. HVP
+                        // (`CSI row ; col f`) is the same thing.
+                        let arg = |i: usize| parser.args.get(i).copied().unwrap_or(1);
+                        output.push_back(TerminalOutput::SetCursorPos(WirePoint::new(
+                            arg(1),
+                            arg(0),
+                        )));
+                        self.state = AnsiBuilder::Empty;
                     }
-                    byte if byte.is_csi_terminator() => {
-                        unreachable!()
-                        // let segment = TerminalOutput::Ansi(std::mem::replace(
-                        //     &mut self.partial,
-                        //     Cow::Borrowed(unsafe {
-                        //         std::slice::from_raw_parts(bytes as *const [u8] as *const u8, 0)
-                        //     }),
-                        // ));
-                        // output.push(segment);
-                        // self.state = AnsiBuilder::Empty;
+                    CsiState::Finished(
+                        final_byte @ (b'A' | b'B' | b'C' | b'D' | b'E' | b'F' | b'G' | b'`' | b'd'),
+                    ) if parser.private.is_none() => {
+                        // A missing or 0 count means 1.
+                        let n = parser.args.first().copied().unwrap_or(1).max(1);
+                        output.push_back(match final_byte {
+                            b'A' => TerminalOutput::MoveCursorUp { n },
+                            b'B' => TerminalOutput::MoveCursorDown { n },
+                            b'C' => TerminalOutput::MoveCursorForward { n },
+                            b'D' => TerminalOutput::MoveCursorBack { n },
+                            b'E' => TerminalOutput::MoveCursorNextLine { n },
+                            b'F' => TerminalOutput::MoveCursorPrevLine { n },
+                            b'd' => TerminalOutput::MoveCursorToRow { n },
+                            _ => TerminalOutput::MoveCursorToColumn { n },
+                        });
+                        self.state = AnsiBuilder::Empty;
                     }
-                    _ => {
-                        self.partial_push(byte);
+                    CsiState::Finished(b'J') => {
+                        // Only the first parameter counts, like xterm.
+                        let command = match parser.args.first() {
+                            Some(0) | None => TerminalOutput::ClearForwards,
+                            Some(1) => TerminalOutput::ClearBackwards,
+                            Some(2) => TerminalOutput::ClearAll,
+                            Some(3) => TerminalOutput::ClearScrollback,
+                            // Ignored, like any other sequence that isn't understood.
+                            Some(4..) => TerminalOutput::Ansi(Cow::Borrowed(&[])),
+                        };
+                        output.push_back(command);
+                        self.state = AnsiBuilder::Empty;
                     }
-                },
-                AnsiBuilder::Csi(ref mut parser) => {
-                    parser.push(byte);
-                    match parser.state {
-                        CsiState::Argument(_) => {}
-                        CsiState::Finished(b'H') => {
-                            // move cursor to position
-                            output.push(TerminalOutput::SetCursorPos {
-                                x: parser.args.pop().unwrap_or(1),
-                                y: parser.args.pop().unwrap_or(1),
-                            });
-                            self.state = AnsiBuilder::Empty;
-                        }
-                        CsiState::Finished(b'J') => {
-                            // move cursor to position
-                            let command = match parser.args.pop() {
-                                Some(0) | None => TerminalOutput::ClearForwards,
-                                Some(1) => TerminalOutput::ClearBackwards,
-                                Some(2) => TerminalOutput::ClearAll,
-                                Some(3..) => panic!("invalid argument for J command"),
-                            };
-                            output.push(command);
-                            self.state = AnsiBuilder::Empty;
-                        }
-                        CsiState::Finished(b's') => {
-                            output.push(TerminalOutput::SaveCursorPos);
-                            self.state = AnsiBuilder::Empty;
-                        }
-                        CsiState::Finished(b'u') => {
-                            output.push(TerminalOutput::RestoreCursorPos);
-                            self.state = AnsiBuilder::Empty;
-                        }
-                        CsiState::Finished(terminator) => {
-                            // TODO: temporary
-                            output.push(TerminalOutput::Ansi(Cow::Borrowed(&[])));
-                            println!(
-                                "unhandled CSI terminator: {:X} {}",
-                                terminator, terminator as char
-                            );
-                            self.state = AnsiBuilder::Empty;
-                        }
+                    CsiState::Finished(b'K') => {
+                        output.push_back(match parser.args.first() {
+                            Some(0) | None => TerminalOutput::ClearLineForwards,
+                            Some(1) => TerminalOutput::ClearLineBackwards,
+                            Some(2) => TerminalOutput::ClearLineAll,
+                            Some(_) => TerminalOutput::Ansi(Cow::Borrowed(&[])),
+                        });
+                        self.state = AnsiBuilder::Empty;
+                    }
+                    CsiState::Finished(final_byte @ (b'h' | b'l')) => {
+                        output.push_back(TerminalOutput::SetMode {
+                            private: parser.private == Some(b'?'),
+                            params: std::mem::take(&mut parser.args),
+                            enable: final_byte == b'h',
+                        });
+                        self.state = AnsiBuilder::Empty;
+                    }
+                    CsiState::Finished(b's') if parser.private == Some(b'?') => {
+                        output
+                            .push_back(TerminalOutput::SaveModes(std::mem::take(&mut parser.args)));
+                        self.state = AnsiBuilder::Empty;
+                    }
+                    CsiState::Finished(b'r') if parser.private == Some(b'?') => {
+                        output.push_back(TerminalOutput::RestoreModes(std::mem::take(
+                            &mut parser.args,
+                        )));
+                        self.state = AnsiBuilder::Empty;
+                    }
+                    CsiState::Finished(b'r') if parser.private.is_none() => {
+                        // Missing or 0 parameters mean the edges of the screen.
+                        let arg = |i: usize| parser.args.get(i).copied().filter(|&n| n > 0);
+                        output.push_back(TerminalOutput::SetScrollRegion {
+                            top: arg(0).unwrap_or(1),
+                            bottom: arg(1),
+                        });
+                        self.state = AnsiBuilder::Empty;
+                    }
+                    CsiState::Finished(final_byte @ (b'S' | b'T')) if parser.private.is_none() => {
+                        let n = parser.args.first().copied().unwrap_or(1).max(1);
+                        output.push_back(match final_byte {
+                            b'S' => TerminalOutput::ScrollUp { n },
+                            _ => TerminalOutput::ScrollDown { n },
+                        });
+                        self.state = AnsiBuilder::Empty;
+                    }
+                    CsiState::Finished(
+                        final_byte @ (b'@' | b'P' | b'X' | b'b' | b'L' | b'M' | b'I' | b'Z'),
+                    ) if parser.private.is_none() => {
+                        let n = parser.args.first().copied().unwrap_or(1).max(1);
+                        output.push_back(match final_byte {
+                            b'@' => TerminalOutput::InsertChars { n },
+                            b'P' => TerminalOutput::DeleteChars { n },
+                            b'X' => TerminalOutput::EraseChars(n),
+                            b'b' => TerminalOutput::RepeatChar(n),
+                            b'L' => TerminalOutput::InsertLines { n },
+                            b'M' => TerminalOutput::DeleteLines { n },
+                            b'I' => TerminalOutput::TabForward(n),
+                            _ => TerminalOutput::TabBackward(n),
+                        });
+                        self.state = AnsiBuilder::Empty;
+                    }
+                    CsiState::Finished(b'g') if parser.private.is_none() => {
+                        output.push_back(match parser.args.first() {
+                            Some(0) | None => TerminalOutput::ClearTabStop { all: false },
+                            Some(3) => TerminalOutput::ClearTabStop { all: true },
+                            Some(_) => TerminalOutput::Ansi(Cow::Borrowed(&[])),
+                        });
+                        self.state = AnsiBuilder::Empty;
+                    }
+                    CsiState::Finished(b'n') if parser.private.is_none() => {
+                        output.push_back(TerminalOutput::DeviceStatusReport(
+                            parser.args.first().copied().unwrap_or(0),
+                        ));
+                        self.state = AnsiBuilder::Empty;
+                    }
+                    CsiState::Finished(b'c')
+                        if matches!(parser.private, None | Some(b'>'))
+                            && parser.args.iter().all(|&arg| arg == 0) =>
+                    {
+                        output.push_back(TerminalOutput::DeviceAttributes {
+                            secondary: parser.private.is_some(),
+                        });
+                        self.state = AnsiBuilder::Empty;
+                    }
+                    CsiState::Finished(b'm') if parser.private.is_none() => {
+                        output.push_back(TerminalOutput::Sgr(SgrAttribute::parse_with_subparams(
+                            &parser.args,
+                            &parser.subparams,
+                        )));
+                        self.state = AnsiBuilder::Empty;
+                    }
+                    CsiState::Finished(b's') => {
+                        output.push_back(TerminalOutput::SaveCursorPos);
+                        self.state = AnsiBuilder::Empty;
+                    }
+                    CsiState::Finished(b'u') => {
+                        output.push_back(TerminalOutput::RestoreCursorPos);
+                        self.state = AnsiBuilder::Empty;
+                    }
+                    CsiState::Finished(_) => {
+                        // TODO: temporary
+                        output.push_back(TerminalOutput::Ansi(Cow::Borrowed(&[])));
+                        self.state = AnsiBuilder::Empty;
                     }
                 }
             }
         }
-        if let Some(text) = self.partial_take() {
-            output.push(TerminalOutput::Text(text));
-        }
-        output
+    }
+
+    /// Like [`OutputParser::parse`], but also describes each output with
+    /// [`TerminalOutput::describe`].
+    pub fn parse_described<'p>(&'p mut self, bytes: &'p [u8]) -> Vec<(TerminalOutput<'p>, String)> {
+        self.feed(bytes)
+            .map(|output| {
+                let description = output.describe();
+                (output, description)
+            })
+            .collect()
     }
 }
 
@@ -364,7 +1596,10 @@ fn test_parser() {
         panic!("previous assertion should have caught this");
     };
     assert_eq!(slice.len(), 5);
-    assert_eq!(output[1], TerminalOutput::SetCursorPos { x: 12, y: 1 });
+    assert_eq!(
+        output[1],
+        TerminalOutput::SetCursorPos(WirePoint::new(12, 1))
+    );
     assert_eq!(output[2], TerminalOutput::Text(Cow::Borrowed(b"world")));
     let TerminalOutput::Text(Cow::Borrowed(slice)) = output[2] else {
         panic!("previous assertion should have caught this");
@@ -387,3 +1622,795 @@ fn test_parser() {
         _ => panic!("parser state should be AnsiBuilder::Empty"),
     }
 }
+
+#[test]
+fn test_cursor_pos_defaults() {
+    let mut parser = OutputParser::new();
+    assert_eq!(
+        parser.parse(b"\x1b[H"),
+        vec![TerminalOutput::SetCursorPos(WirePoint::new(1, 1))]
+    );
+    // 0 is not a valid 1-based coordinate and means the same as a missing parameter.
+    assert_eq!(
+        parser.parse(b"\x1b[0;0H"),
+        vec![TerminalOutput::SetCursorPos(WirePoint::new(1, 1))]
+    );
+}
+
+#[test]
+fn test_osc_clipboard_query() {
+    let mut parser = OutputParser::new();
+    let expected = TerminalOutput::ClipboardQuery {
+        selection: "c".to_owned(),
+    };
+    assert_eq!(parser.parse(b"\x1b]52;c;?\x07"), vec![expected.clone()]);
+    assert_eq!(parser.parse(b"\x1b]52;c;?\x1b\\"), vec![expected.clone()]);
+
+    // split across reads
+    assert_eq!(parser.parse(b"\x1b]52;"), vec![]);
+    assert_eq!(parser.parse(b"c;?\x07"), vec![expected]);
+}
+
+#[test]
+fn test_osc_title() {
+    let mut parser = OutputParser::new();
+    assert_eq!(
+        parser.parse(b"\x1b]0;vim\x07\x1b]2;htop: load\x1b\\"),
+        vec![
+            TerminalOutput::SetTitle("vim".to_owned()),
+            TerminalOutput::SetTitle("htop: load".to_owned()),
+        ]
+    );
+
+    // The string terminator split across reads: the title is set at the ESC,
+    // and the `\` that finishes it isn't text.
+    assert_eq!(
+        parser.parse(b"\x1b]2;make\x1b"),
+        vec![TerminalOutput::SetTitle("make".to_owned())]
+    );
+    assert_eq!(
+        parser.parse(b"\\done"),
+        vec![TerminalOutput::Text(Cow::Borrowed(b"done"))]
+    );
+}
+
+#[test]
+fn test_osc_colors() {
+    let mut parser = OutputParser::new();
+    assert_eq!(
+        parser.parse(b"\x1b]4;1;rgb:ff/80/0;2;?;300;#fff\x07"),
+        vec![TerminalOutput::Colors(vec![
+            ColorRequest::Set(ColorSlot::Indexed(1), (255, 128, 0)),
+            ColorRequest::Query(ColorSlot::Indexed(2)),
+        ])]
+    );
+    assert_eq!(
+        parser.parse(b"\x1b]11;?;#123456789\x1b\\"),
+        vec![TerminalOutput::Colors(vec![
+            ColorRequest::Query(ColorSlot::Background),
+            ColorRequest::Set(ColorSlot::Cursor, (0x12, 0x45, 0x78)),
+        ])]
+    );
+    assert_eq!(
+        parser.parse(b"\x1b]104\x07\x1b]104;3;4\x07\x1b]112\x07"),
+        vec![
+            TerminalOutput::Colors(vec![ColorRequest::ResetPalette]),
+            TerminalOutput::Colors(vec![
+                ColorRequest::Reset(ColorSlot::Indexed(3)),
+                ColorRequest::Reset(ColorSlot::Indexed(4)),
+            ]),
+            TerminalOutput::Colors(vec![ColorRequest::Reset(ColorSlot::Cursor)]),
+        ]
+    );
+    assert_eq!(
+        parser.parse(b"\x1b]10;rgb:ffff/0/8\x07")[0].describe(),
+        "OSC 10;rgb:ff/00/88 — set the foreground color to #ff0088"
+    );
+}
+
+#[test]
+fn test_color_spec() {
+    assert_eq!(parse_color_spec(b"rgb:f/0/8"), Some((255, 0, 136)));
+    assert_eq!(parse_color_spec(b"rgb:ffff/8000/0000"), Some((255, 128, 0)));
+    assert_eq!(parse_color_spec(b"#f08"), Some((0xf0, 0x00, 0x80)));
+    assert_eq!(parse_color_spec(b"#ff0088"), Some((0xff, 0x00, 0x88)));
+    assert_eq!(parse_color_spec(b"#fff000888"), Some((0xff, 0x00, 0x88)));
+    assert_eq!(parse_color_spec(b"red"), None);
+    assert_eq!(parse_color_spec(b"rgb:ff/00"), None);
+    assert_eq!(parse_color_spec(b"rgb:fffff/0/0"), None);
+    assert_eq!(parse_color_spec(b"#ff00"), None);
+}
+
+#[test]
+fn test_feed() {
+    let mut parser = OutputParser::new();
+    let mut outputs = parser.feed(b"ab\x1b[2Jcd\x1b[1");
+    assert_eq!(
+        outputs.next(),
+        Some(TerminalOutput::Text(Cow::Borrowed(b"ab")))
+    );
+    assert_eq!(outputs.next(), Some(TerminalOutput::ClearAll));
+    drop(outputs);
+    // The rest of the input was still parsed, and the unfinished sequence
+    // carries on into the next one.
+    assert_eq!(
+        parser.feed(b";2H").collect::<Vec<_>>(),
+        vec![TerminalOutput::SetCursorPos(WirePoint::new(2, 1))]
+    );
+    assert_eq!(parser.feed(b"\x1b").count(), 0);
+    assert_eq!(
+        parser.feed(b"[Kx").collect::<Vec<_>>(),
+        vec![
+            TerminalOutput::ClearLineForwards,
+            TerminalOutput::Text(Cow::Borrowed(b"x"))
+        ]
+    );
+}
+
+#[test]
+fn test_parse_described() {
+    let mut parser = OutputParser::new();
+    let descriptions = |parser: &mut OutputParser, bytes: &[u8]| {
+        parser
+            .parse_described(bytes)
+            .into_iter()
+            .map(|(_, description)| description)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        descriptions(&mut parser, b"\x1b[5;10Hhi\r\n\x1b[2J"),
+        [
+            "CSI 5;10H — move cursor to row 5, col 10",
+            "text \"hi\"",
+            "CR — move to the start of the line",
+            "LF — move down a line",
+            "CSI 2J — clear the screen",
+        ]
+    );
+    assert_eq!(
+        descriptions(&mut parser, b"\x1b[?1049;1007h\x1b[4l\x1b#6"),
+        [
+            "CSI ?1049;1007h — set DEC private modes 1049, 1007",
+            "CSI 4l — reset mode 4",
+            "ESC #6 — double width line",
+        ]
+    );
+    // Missing parameters are filled in with their defaults.
+    assert_eq!(
+        descriptions(&mut parser, b"\x1b[H\x1b]133;A\x07"),
+        [
+            "CSI 1;1H — move cursor to row 1, col 1",
+            "OSC 133;A — start of the prompt",
+        ]
+    );
+}
+
+#[test]
+fn test_next_line() {
+    let mut parser = OutputParser::new();
+    assert_eq!(
+        parser.parse(b"a\x1bEb"),
+        vec![
+            TerminalOutput::Text(Cow::Borrowed(b"a")),
+            TerminalOutput::NextLine,
+            TerminalOutput::Text(Cow::Borrowed(b"b")),
+        ]
+    );
+    // 0x85 is a UTF-8 continuation byte ("\u{105}" is C4 85) unless 8-bit
+    // controls are enabled.
+    assert_eq!(
+        parser.parse("\u{105}".as_bytes()),
+        vec![TerminalOutput::Text(Cow::Borrowed("\u{105}".as_bytes()))]
+    );
+    parser.set_eight_bit_controls(true);
+    assert_eq!(
+        parser.parse(b"a\x85b"),
+        vec![
+            TerminalOutput::Text(Cow::Borrowed(b"a")),
+            TerminalOutput::NextLine,
+            TerminalOutput::Text(Cow::Borrowed(b"b")),
+        ]
+    );
+}
+
+#[test]
+fn test_control_strings() {
+    let mut parser = OutputParser::new();
+    assert_eq!(
+        parser.parse(b"a\x1bP1$r0m\x1b\\b"),
+        vec![
+            TerminalOutput::Text(Cow::Borrowed(b"a")),
+            TerminalOutput::Ansi(Cow::Borrowed(&[])),
+            TerminalOutput::Text(Cow::Borrowed(b"b")),
+        ]
+    );
+    assert_eq!(parser.open_string(), None);
+
+    // An APC split across reads stays open until it is terminated.
+    assert_eq!(parser.parse(b"\x1b_Gf=100;"), vec![]);
+    assert_eq!(parser.open_string(), Some((StringKind::Apc, 2)));
+    assert_eq!(parser.parse(b"AAAA"), vec![]);
+    assert_eq!(parser.open_string(), Some((StringKind::Apc, 2)));
+    parser.abort_string();
+    assert_eq!(parser.open_string(), None);
+    assert_eq!(
+        parser.parse(b"c"),
+        vec![TerminalOutput::Text(Cow::Borrowed(b"c"))]
+    );
+}
+
+#[test]
+fn test_sgr() {
+    let mut parser = OutputParser::new();
+    // Like `ls --color`.
+    assert_eq!(
+        parser.parse(b"\x1b[0m\x1b[01;34mdir\x1b[0m  file\x1b[m"),
+        vec![
+            TerminalOutput::Sgr(vec![SgrAttribute::Reset]),
+            TerminalOutput::Sgr(vec![
+                SgrAttribute::Bold,
+                SgrAttribute::Foreground(Color::Blue)
+            ]),
+            TerminalOutput::Text(Cow::Borrowed(b"dir")),
+            TerminalOutput::Sgr(vec![SgrAttribute::Reset]),
+            TerminalOutput::Text(Cow::Borrowed(b"  file")),
+            TerminalOutput::Sgr(vec![SgrAttribute::Reset]),
+        ]
+    );
+    assert_eq!(
+        parser.parse(b"\x1b[1;31;44m"),
+        vec![TerminalOutput::Sgr(vec![
+            SgrAttribute::Bold,
+            SgrAttribute::Foreground(Color::Red),
+            SgrAttribute::Background(Color::Blue),
+        ])]
+    );
+    assert_eq!(
+        parser.parse(b"\x1b[1;38;2;255;100;0;48;5;17mx"),
+        vec![
+            TerminalOutput::Sgr(vec![
+                SgrAttribute::Bold,
+                SgrAttribute::Foreground(Color::Rgb(255, 100, 0)),
+                SgrAttribute::Background(Color::Indexed(17)),
+            ]),
+            TerminalOutput::Text(Cow::Borrowed(b"x")),
+        ]
+    );
+    assert_eq!(
+        SgrAttribute::parse(&[1, 38, 2, 255, 100, 0, 48, 5, 17, 91, 103]),
+        [
+            SgrAttribute::Bold,
+            SgrAttribute::Foreground(Color::Rgb(255, 100, 0)),
+            SgrAttribute::Background(Color::Indexed(17)),
+            SgrAttribute::Foreground(Color::BrightRed),
+            SgrAttribute::Background(Color::BrightYellow),
+        ]
+    );
+    // Truncated or out of range extended colors don't panic.
+    assert_eq!(SgrAttribute::parse(&[38, 5]), [SgrAttribute::Unknown(38)]);
+    assert_eq!(
+        SgrAttribute::parse(&[48, 5, 300]),
+        [SgrAttribute::Unknown(48)]
+    );
+    assert_eq!(
+        SgrAttribute::parse(&[38, 2, 10]),
+        [SgrAttribute::Foreground(Color::Rgb(10, 0, 0))]
+    );
+    assert_eq!(SgrAttribute::parse(&[5]), [SgrAttribute::Unknown(5)]);
+
+    // The parameters round trip.
+    let params = [
+        1, 38, 2, 255, 100, 0, 48, 5, 17, 91, 103, 39, 58, 5, 9, 59, 0,
+    ];
+    let attributes = SgrAttribute::parse(&params);
+    let round_trip = attributes
+        .iter()
+        .flat_map(SgrAttribute::params)
+        .collect::<Vec<_>>();
+    assert_eq!(round_trip, params);
+}
+
+#[test]
+fn test_private_modes() {
+    let mut parser = OutputParser::new();
+    assert_eq!(
+        parser.parse(b"\x1b[?1049;1007h\x1b[4l"),
+        vec![
+            TerminalOutput::SetMode {
+                private: true,
+                params: vec![1049, 1007],
+                enable: true,
+            },
+            TerminalOutput::SetMode {
+                private: false,
+                params: vec![4],
+                enable: false,
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_save_restore_modes() {
+    let mut parser = OutputParser::new();
+    assert_eq!(
+        parser.parse(b"\x1b[?1000;2004s\x1b[s\x1b[?1000r"),
+        vec![
+            TerminalOutput::SaveModes(vec![1000, 2004]),
+            TerminalOutput::SaveCursorPos,
+            TerminalOutput::RestoreModes(vec![1000]),
+        ]
+    );
+}
+
+#[test]
+fn test_request_mode() {
+    let mut parser = OutputParser::new();
+    assert_eq!(
+        parser.parse(b"\x1b[?2048$p\x1b[4$pok"),
+        vec![
+            TerminalOutput::RequestMode {
+                private: true,
+                mode: 2048
+            },
+            TerminalOutput::RequestMode {
+                private: false,
+                mode: 4
+            },
+            TerminalOutput::Text(Cow::Borrowed(b"ok")),
+        ]
+    );
+    assert_eq!(
+        parser.parse(b"\x1b[?2048$p")[0].describe(),
+        "CSI ?2048$p — ask whether DEC private mode 2048 is set"
+    );
+}
+
+#[test]
+fn test_line_size() {
+    let mut parser = OutputParser::new();
+    assert_eq!(
+        parser.parse(b"\x1b#3top\x1b#"),
+        vec![
+            TerminalOutput::SetLineSize(LineSize::DoubleHeightTop),
+            TerminalOutput::Text(Cow::Borrowed(b"top")),
+        ]
+    );
+    // split across reads
+    assert_eq!(
+        parser.parse(b"6"),
+        vec![TerminalOutput::SetLineSize(LineSize::DoubleWidth)]
+    );
+}
+
+#[test]
+fn test_unknown_csi() {
+    let mut parser = OutputParser::new();
+    // DECSCUSR and XTWINOPS, which aren't supported.
+    assert_eq!(
+        parser.parse(b"\x1b[5 qhello world\x1b[22;0tabc"),
+        vec![
+            TerminalOutput::Ansi(Cow::Borrowed(&[])),
+            TerminalOutput::Text(Cow::Borrowed(b"hello world")),
+            TerminalOutput::Ansi(Cow::Borrowed(&[])),
+            TerminalOutput::Text(Cow::Borrowed(b"abc")),
+        ]
+    );
+}
+
+#[test]
+fn test_csi_intermediates() {
+    let mut parser = OutputParser::new();
+    // DECCARA, SL, and SR, which aren't supported, aren't mistaken for
+    // DECSTBM, ICH, and CUU.
+    assert_eq!(
+        parser.parse(b"\x1b[2;5$r\x1b[3 @\x1b[2 A"),
+        vec![TerminalOutput::Ansi(Cow::Borrowed(&[])); 3]
+    );
+    assert_eq!(
+        parser.parse(b"\x1b[?25$p"),
+        vec![TerminalOutput::RequestMode {
+            private: true,
+            mode: 25
+        }]
+    );
+}
+
+#[test]
+fn test_alignment_test() {
+    let mut parser = OutputParser::new();
+    assert_eq!(
+        parser.parse(b"\x1b#8\x1b#9"),
+        vec![
+            TerminalOutput::AlignmentTest,
+            TerminalOutput::Ansi(Cow::Borrowed(&[]))
+        ]
+    );
+}
+
+#[test]
+fn test_prompt_marks() {
+    let mut parser = OutputParser::new();
+    assert_eq!(
+        parser.parse(b"\x1b]133;A\x07$ \x1b]133;B\x07ls\x1b]133;D;0\x1b\\"),
+        vec![
+            TerminalOutput::PromptMark(PromptMarkKind::PromptStart),
+            TerminalOutput::Text(Cow::Borrowed(b"$ ")),
+            TerminalOutput::PromptMark(PromptMarkKind::CommandStart),
+            TerminalOutput::Text(Cow::Borrowed(b"ls")),
+            TerminalOutput::PromptMark(PromptMarkKind::CommandFinished),
+        ]
+    );
+}
+
+#[test]
+fn test_cursor_movement() {
+    let mut parser = OutputParser::new();
+    assert_eq!(
+        parser.parse(b"\x1b[A\x1b[3B\x1b[0C\x1b[12D\x1b[2E\x1b[F\x1b[40G"),
+        vec![
+            TerminalOutput::MoveCursorUp { n: 1 },
+            TerminalOutput::MoveCursorDown { n: 3 },
+            TerminalOutput::MoveCursorForward { n: 1 },
+            TerminalOutput::MoveCursorBack { n: 12 },
+            TerminalOutput::MoveCursorNextLine { n: 2 },
+            TerminalOutput::MoveCursorPrevLine { n: 1 },
+            TerminalOutput::MoveCursorToColumn { n: 40 },
+        ]
+    );
+    assert_eq!(
+        parser.parse(b"\x1b[5d\x1b[d"),
+        vec![
+            TerminalOutput::MoveCursorToRow { n: 5 },
+            TerminalOutput::MoveCursorToRow { n: 1 },
+        ]
+    );
+    assert_eq!(
+        parser.parse(b"\x1b[15`\x1b[`x"),
+        vec![
+            TerminalOutput::MoveCursorToColumn { n: 15 },
+            TerminalOutput::MoveCursorToColumn { n: 1 },
+            TerminalOutput::Text(Cow::Borrowed(b"x")),
+        ]
+    );
+    assert_eq!(
+        parser.parse(b"\x1b[5D")[0].describe(),
+        "CSI 5D — move cursor left 5"
+    );
+    assert_eq!(
+        parser.parse(b"\x1b[E"),
+        vec![TerminalOutput::MoveCursorNextLine { n: 1 }]
+    );
+
+    // Split across reads, in the middle of the count.
+    assert!(parser.parse(b"\x1b[1").is_empty());
+    assert_eq!(
+        parser.parse(b"2E"),
+        vec![TerminalOutput::MoveCursorNextLine { n: 12 }]
+    );
+    assert!(parser.parse(b"\x1b[").is_empty());
+    assert_eq!(
+        parser.parse(b"F"),
+        vec![TerminalOutput::MoveCursorPrevLine { n: 1 }]
+    );
+}
+
+#[test]
+fn test_set_cursor_pos_defaults() {
+    let mut parser = OutputParser::new();
+    assert_eq!(
+        parser.parse(b"\x1b[5H\x1b[;7H\x1b[H\x1b[5;7H"),
+        vec![
+            TerminalOutput::SetCursorPos(WirePoint::new(1, 5)),
+            TerminalOutput::SetCursorPos(WirePoint::new(7, 1)),
+            TerminalOutput::SetCursorPos(WirePoint::new(1, 1)),
+            TerminalOutput::SetCursorPos(WirePoint::new(7, 5)),
+        ]
+    );
+    assert_eq!(
+        parser.parse(b"\x1b[3;4f\x1b[f"),
+        vec![
+            TerminalOutput::SetCursorPos(WirePoint::new(4, 3)),
+            TerminalOutput::SetCursorPos(WirePoint::new(1, 1)),
+        ]
+    );
+}
+
+#[test]
+fn test_sgr_subparams() {
+    let mut parser = OutputParser::new();
+    let sgr = |parser: &mut OutputParser, input: &[u8]| match parser.parse(input).as_slice() {
+        [TerminalOutput::Sgr(attributes)] => attributes.clone(),
+        other => panic!("not one SGR: {:?}", other),
+    };
+    let red = SgrAttribute::Foreground(Color::Rgb(255, 0, 0));
+    assert_eq!(sgr(&mut parser, b"\x1b[38;2;255;0;0m"), [red]);
+    assert_eq!(sgr(&mut parser, b"\x1b[38:2::255:0:0m"), [red]);
+    assert_eq!(sgr(&mut parser, b"\x1b[38:2:1:255:0:0m"), [red]);
+    assert_eq!(sgr(&mut parser, b"\x1b[38:2:255:0:0m"), [red]);
+    assert_eq!(
+        sgr(&mut parser, b"\x1b[1;48:5:196;4:3;31m"),
+        [
+            SgrAttribute::Bold,
+            SgrAttribute::Background(Color::Indexed(196)),
+            SgrAttribute::Underline(UnderlineStyle::Curly),
+            SgrAttribute::Foreground(Color::Red),
+        ]
+    );
+    assert_eq!(
+        sgr(&mut parser, b"\x1b[4:0;38:5m"),
+        [SgrAttribute::NotUnderlined, SgrAttribute::Unknown(38)]
+    );
+    // Split across reads.
+    assert_eq!(parser.parse(b"\x1b[38:2::1"), []);
+    assert_eq!(
+        sgr(&mut parser, b"0:20:30m"),
+        [SgrAttribute::Foreground(Color::Rgb(10, 20, 30))]
+    );
+}
+
+#[test]
+fn test_sgr_underline() {
+    use UnderlineStyle::*;
+
+    assert_eq!(SgrAttribute::parse(&[4]), [SgrAttribute::Underline(Single)]);
+    assert_eq!(SgrAttribute::parse(&[24]), [SgrAttribute::NotUnderlined]);
+    assert_eq!(
+        SgrAttribute::parse(&[21, 53, 55, 9, 29]),
+        [
+            SgrAttribute::Underline(Double),
+            SgrAttribute::Overline,
+            SgrAttribute::NotOverlined,
+            SgrAttribute::Strikethrough,
+            SgrAttribute::NotStrikethrough,
+        ]
+    );
+    assert_eq!(
+        SgrAttribute::parse(&[58, 5, 196, 58, 2, 1, 2, 3, 59]),
+        [
+            SgrAttribute::UnderlineColor(Color::Indexed(196)),
+            SgrAttribute::UnderlineColor(Color::Rgb(1, 2, 3)),
+            SgrAttribute::DefaultUnderlineColor,
+        ]
+    );
+    let mut parser = OutputParser::new();
+    assert_eq!(
+        parser.parse(b"\x1b[4:2;4:3;4:4;4:5;4:9;58:2::255:0:0m"),
+        vec![TerminalOutput::Sgr(vec![
+            SgrAttribute::Underline(Double),
+            SgrAttribute::Underline(Curly),
+            SgrAttribute::Underline(Dotted),
+            SgrAttribute::Underline(Dashed),
+            SgrAttribute::Unknown(4),
+            SgrAttribute::UnderlineColor(Color::Rgb(255, 0, 0)),
+        ])]
+    );
+}
+
+/// Bytes that look enough like escape sequences to reach every state of the
+/// parser, from a simple xorshift generator so failures can be reproduced.
+#[cfg(test)]
+pub(crate) fn garbage(seed: u64, len: usize) -> Vec<u8> {
+    const INTERESTING: &[u8] =
+        b"\x1b\x1b\x1b[[]]PX_\\;;::??<> $#0123456789AaBHJKhlmsrpuEGq\x07\x85\x9b\xe6\x97";
+    let mut state = seed.max(1);
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            match state % 4 {
+                0 => (state >> 8) as u8,
+                _ => INTERESTING[(state >> 8) as usize % INTERESTING.len()],
+            }
+        })
+        .collect()
+}
+
+#[test]
+fn test_parse_garbage() {
+    let mut parser = OutputParser::new();
+    parser.parse(b"\x1b[99999999999999999999999999A\x1b[4J\x1b7\x1b[mX\x1b[12 3m");
+    for seed in 0..200 {
+        let bytes = garbage(seed, 2000);
+        // Split unevenly, so sequences are cut across calls.
+        for chunk in bytes.chunks(1 + seed as usize % 97) {
+            for output in parser.parse(chunk) {
+                output.describe();
+            }
+        }
+    }
+}
+
+#[test]
+fn test_save_restore_cursor_esc() {
+    let mut parser = OutputParser::new();
+    assert_eq!(parser.parse(b"\x1b7"), vec![TerminalOutput::SaveCursorPos]);
+    assert!(matches!(parser.state, AnsiBuilder::Empty));
+    assert_eq!(
+        parser.parse(b"a\x1b8b"),
+        vec![
+            TerminalOutput::Text(Cow::Borrowed(b"a")),
+            TerminalOutput::RestoreCursorPos,
+            TerminalOutput::Text(Cow::Borrowed(b"b")),
+        ]
+    );
+}
+
+#[test]
+fn test_erase_in_display() {
+    let mut parser = OutputParser::new();
+    assert_eq!(
+        parser.parse(b"\x1b[J\x1b[1J\x1b[2J\x1b[3J\x1b[0;1J\x1b[5Jok"),
+        vec![
+            TerminalOutput::ClearForwards,
+            TerminalOutput::ClearBackwards,
+            TerminalOutput::ClearAll,
+            TerminalOutput::ClearScrollback,
+            TerminalOutput::ClearForwards,
+            TerminalOutput::Ansi(Cow::Borrowed(&[])),
+            TerminalOutput::Text(Cow::Borrowed(b"ok")),
+        ]
+    );
+}
+
+#[test]
+fn test_erase_in_line() {
+    let mut parser = OutputParser::new();
+    assert_eq!(
+        parser.parse(b"\x1b[K\x1b[0K\x1b[1K\x1b[2K\x1b[9K"),
+        vec![
+            TerminalOutput::ClearLineForwards,
+            TerminalOutput::ClearLineForwards,
+            TerminalOutput::ClearLineBackwards,
+            TerminalOutput::ClearLineAll,
+            TerminalOutput::Ansi(Cow::Borrowed(&[])),
+        ]
+    );
+}
+
+#[test]
+fn test_scroll_region() {
+    let mut parser = OutputParser::new();
+    assert_eq!(
+        parser.parse(b"\x1b[2;10r\x1b[r\x1b[;5r\x1b[S\x1b[3T\x1b[?1r"),
+        vec![
+            TerminalOutput::SetScrollRegion {
+                top: 2,
+                bottom: Some(10)
+            },
+            TerminalOutput::SetScrollRegion {
+                top: 1,
+                bottom: None
+            },
+            TerminalOutput::SetScrollRegion {
+                top: 1,
+                bottom: Some(5)
+            },
+            TerminalOutput::ScrollUp { n: 1 },
+            TerminalOutput::ScrollDown { n: 3 },
+            TerminalOutput::RestoreModes(vec![1]),
+        ]
+    );
+}
+
+#[test]
+fn test_insert_delete_chars() {
+    let mut parser = OutputParser::new();
+    assert_eq!(
+        parser.parse(b"\x1b[@\x1b[3@\x1b[0P\x1b[12P"),
+        vec![
+            TerminalOutput::InsertChars { n: 1 },
+            TerminalOutput::InsertChars { n: 3 },
+            TerminalOutput::DeleteChars { n: 1 },
+            TerminalOutput::DeleteChars { n: 12 },
+        ]
+    );
+    assert_eq!(
+        parser.parse(b"\x1b[X\x1b[4X"),
+        vec![TerminalOutput::EraseChars(1), TerminalOutput::EraseChars(4)]
+    );
+    assert_eq!(
+        parser.parse(b"\x1b[b\x1b[79b"),
+        vec![
+            TerminalOutput::RepeatChar(1),
+            TerminalOutput::RepeatChar(79)
+        ]
+    );
+    assert_eq!(
+        parser.parse(b"\x1b[L\x1b[2L\x1b[M\x1b[0M"),
+        vec![
+            TerminalOutput::InsertLines { n: 1 },
+            TerminalOutput::InsertLines { n: 2 },
+            TerminalOutput::DeleteLines { n: 1 },
+            TerminalOutput::DeleteLines { n: 1 },
+        ]
+    );
+}
+
+#[test]
+fn test_device_status_report() {
+    let mut parser = OutputParser::new();
+    assert_eq!(
+        parser.parse(b"\x1b[6n\x1b[5n\x1b[n"),
+        vec![
+            TerminalOutput::DeviceStatusReport(6),
+            TerminalOutput::DeviceStatusReport(5),
+            TerminalOutput::DeviceStatusReport(0),
+        ]
+    );
+}
+
+#[test]
+fn test_device_attributes() {
+    let mut parser = OutputParser::new();
+    assert_eq!(
+        parser.parse(b"\x1b[c\x1b[0c\x1b[>c\x1b[>0c"),
+        vec![
+            TerminalOutput::DeviceAttributes { secondary: false },
+            TerminalOutput::DeviceAttributes { secondary: false },
+            TerminalOutput::DeviceAttributes { secondary: true },
+            TerminalOutput::DeviceAttributes { secondary: true },
+        ]
+    );
+}
+
+#[test]
+fn test_c0_controls() {
+    let mut parser = OutputParser::new();
+    assert_eq!(
+        parser.parse(b"a\r\nb\tc\x07d\x1b]2;t\x07"),
+        vec![
+            TerminalOutput::Text(Cow::Borrowed(b"a")),
+            TerminalOutput::CarriageReturn,
+            TerminalOutput::LineFeed,
+            TerminalOutput::Text(Cow::Borrowed(b"b")),
+            TerminalOutput::Tab,
+            TerminalOutput::Text(Cow::Borrowed(b"c")),
+            TerminalOutput::Bell,
+            TerminalOutput::Text(Cow::Borrowed(b"d")),
+            TerminalOutput::SetTitle("t".to_owned()),
+        ]
+    );
+}
+
+#[test]
+fn test_reverse_index() {
+    let mut parser = OutputParser::new();
+    assert_eq!(
+        parser.parse(b"a\x1bMb"),
+        vec![
+            TerminalOutput::Text(Cow::Borrowed(b"a")),
+            TerminalOutput::ReverseIndex,
+            TerminalOutput::Text(Cow::Borrowed(b"b")),
+        ]
+    );
+}
+
+#[test]
+fn test_tab_stops() {
+    let mut parser = OutputParser::new();
+    assert_eq!(
+        parser.parse(b"\x1bH\x1b[g\x1b[0g\x1b[3g"),
+        vec![
+            TerminalOutput::SetTabStop,
+            TerminalOutput::ClearTabStop { all: false },
+            TerminalOutput::ClearTabStop { all: false },
+            TerminalOutput::ClearTabStop { all: true },
+        ]
+    );
+}
+
+#[test]
+fn test_tab_motion() {
+    let mut parser = OutputParser::new();
+    assert_eq!(
+        parser.parse(b"\x1b[I\x1b[2I\x1b[Z\x1b[0Z"),
+        vec![
+            TerminalOutput::TabForward(1),
+            TerminalOutput::TabForward(2),
+            TerminalOutput::TabBackward(1),
+            TerminalOutput::TabBackward(1),
+        ]
+    );
+}
@@ -0,0 +1,345 @@
+//! Headless screenshots of a terminal, for comparing against golden images in
+//! CI with `--screenshot-test`.
+//!
+//! The screen is rasterized in software with egui's monospace font rather than
+//! captured from the window, so it works without a display and doesn't depend
+//! on the GPU.
+use std::{
+    fs::File,
+    io::BufWriter,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use ab_glyph::{Font, FontRef, PxScale, ScaleFont};
+use anyhow::{Context, Result};
+use egui::{Color32, ColorImage};
+
+use termulus::{
+    grid::LineSize,
+    style::{Color, Palette},
+    terminal::Terminal,
+};
+
+/// The font size screenshots are rendered at, in pixels.
+const FONT_SIZE: f32 = 16.0;
+
+/// The colors screenshots are taken with, so they don't depend on the GUI's theme.
+pub fn theme() -> Palette {
+    let mut palette = Palette::default();
+    palette.foreground = Some((0xe5, 0xe5, 0xe5));
+    palette.background = Some((0x00, 0x00, 0x00));
+    palette.cursor = Some((0x80, 0x80, 0x80));
+    palette
+}
+
+/// Render the visible part of the terminal, with the cursor.
+pub fn render(terminal: &Terminal) -> ColorImage {
+    let fonts = egui::FontDefinitions::default();
+    let data = &fonts.font_data["Hack"];
+    let font = FontRef::try_from_slice_and_index(&data.font, data.index)
+        .expect("egui's monospace font to load");
+    let scaled = font.as_scaled(FONT_SIZE);
+    let cell_width = scaled.h_advance(font.glyph_id('M')).round() as usize;
+    let cell_height = (scaled.ascent() - scaled.descent() + scaled.line_gap()).ceil() as usize;
+
+    let palette = terminal.palette();
+    let rgb = |(r, g, b): (u8, u8, u8)| Color32::from_rgb(r, g, b);
+    let color = |color: Option<Color>, default: Option<(u8, u8, u8)>, fallback| {
+        color
+            .map(|color| rgb(palette.rgb(color)))
+            .or(default.map(rgb))
+            .unwrap_or(fallback)
+    };
+    let size = terminal.size();
+    let mut image = ColorImage::new(
+        [size.cols * cell_width, size.rows * cell_height],
+        color(None, palette.background, Color32::BLACK),
+    );
+    let cursor = terminal.visible_cursor();
+    for (y, row) in terminal.visible_rows().enumerate() {
+        let (width, scale, offset) = match row.size() {
+            LineSize::Normal => (cell_width, PxScale::from(FONT_SIZE), 0.0),
+            LineSize::DoubleWidth => (
+                cell_width * 2,
+                PxScale {
+                    x: FONT_SIZE * 2.0,
+                    y: FONT_SIZE,
+                },
+                0.0,
+            ),
+            // Drawn at twice the size, with only the half for this row showing.
+            LineSize::DoubleHeightTop => (cell_width * 2, PxScale::from(FONT_SIZE * 2.0), 0.0),
+            LineSize::DoubleHeightBottom => (
+                cell_width * 2,
+                PxScale::from(FONT_SIZE * 2.0),
+                -(cell_height as f32),
+            ),
+        };
+        let ascent = font.as_scaled(scale).ascent();
+        let top = y * cell_height;
+        let cells = &row.cells()[..row.cols()];
+        for (x, cell) in cells.iter().enumerate() {
+            let left = x * width;
+            let style = cell.style();
+            let mut bg = if style.reverse {
+                color(style.fg, palette.foreground, Color32::WHITE)
+            } else {
+                color(style.bg, palette.background, Color32::BLACK)
+            };
+            if cursor == Some((y, x)) {
+                bg = color(None, palette.cursor, Color32::GRAY);
+            }
+            for py in top..top + cell_height {
+                for px in left..(left + width).min(image.size[0]) {
+                    image[(px, py)] = bg;
+                }
+            }
+        }
+        // Glyphs go on top of all of the backgrounds, since wide characters
+        // cover the next cell too.
+        for (x, cell) in cells.iter().enumerate() {
+            if cell.char() == ' ' {
+                continue;
+            }
+            let left = x * width;
+            let style = cell.style();
+            let fg = if style.reverse {
+                color(style.bg, palette.background, Color32::BLACK)
+            } else {
+                color(style.fg, palette.foreground, Color32::WHITE)
+            };
+            let glyph = font.glyph_id(cell.char()).with_scale_and_position(
+                scale,
+                ab_glyph::point(left as f32, top as f32 + offset + ascent),
+            );
+            let Some(outline) = font.outline_glyph(glyph) else {
+                continue;
+            };
+            let bounds = outline.px_bounds();
+            outline.draw(|gx, gy, coverage| {
+                let px = bounds.min.x as i64 + gx as i64;
+                let py = bounds.min.y as i64 + gy as i64;
+                // Glyphs can stick out of their cell, but not out of their row.
+                if px < 0
+                    || px as usize >= image.size[0]
+                    || py < top as i64
+                    || py as usize >= top + cell_height
+                {
+                    return;
+                }
+                let pixel = &mut image[(px as usize, py as usize)];
+                *pixel = blend(*pixel, fg, coverage);
+            });
+        }
+    }
+    image
+}
+
+fn blend(under: Color32, over: Color32, coverage: f32) -> Color32 {
+    let mix =
+        |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * coverage.clamp(0.0, 1.0)).round() as u8;
+    Color32::from_rgb(
+        mix(under.r(), over.r()),
+        mix(under.g(), over.g()),
+        mix(under.b(), over.b()),
+    )
+}
+
+pub fn save_png(image: &ColorImage, path: &Path) -> Result<()> {
+    let file =
+        File::create(path).with_context(|| format!("failed to create {}", path.display()))?;
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(file),
+        image.size[0] as u32,
+        image.size[1] as u32,
+    );
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(image.as_raw())?;
+    Ok(())
+}
+
+pub fn load_png(path: &Path) -> Result<ColorImage> {
+    let file = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    let mut decoder = png::Decoder::new(file);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf)?;
+    let buf = &buf[..info.buffer_size()];
+    let pixels = match info.color_type {
+        png::ColorType::Rgba => buf
+            .chunks_exact(4)
+            .map(|p| Color32::from_rgba_unmultiplied(p[0], p[1], p[2], p[3]))
+            .collect(),
+        png::ColorType::Rgb => buf
+            .chunks_exact(3)
+            .map(|p| Color32::from_rgb(p[0], p[1], p[2]))
+            .collect(),
+        png::ColorType::GrayscaleAlpha => buf
+            .chunks_exact(2)
+            .map(|p| Color32::from_rgba_unmultiplied(p[0], p[0], p[0], p[1]))
+            .collect(),
+        png::ColorType::Grayscale => buf.iter().map(|v| Color32::from_gray(*v)).collect(),
+        png::ColorType::Indexed => anyhow::bail!("indexed PNGs aren't supported"),
+    };
+    Ok(ColorImage {
+        size: [info.width as usize, info.height as usize],
+        pixels,
+    })
+}
+
+/// Compare two images, counting the pixels where any channel differs by more
+/// than `tolerance`. Returns the count and an image of the differences in red
+/// over a faded copy of `actual`, or `None` if the sizes don't match.
+pub fn diff(
+    expected: &ColorImage,
+    actual: &ColorImage,
+    tolerance: u8,
+) -> Option<(usize, ColorImage)> {
+    if expected.size != actual.size {
+        return None;
+    }
+    let mut different = 0;
+    let pixels = expected
+        .pixels
+        .iter()
+        .zip(&actual.pixels)
+        .map(|(a, b)| {
+            let channels = a.to_array().into_iter().zip(b.to_array());
+            if channels.into_iter().any(|(a, b)| a.abs_diff(b) > tolerance) {
+                different += 1;
+                Color32::RED
+            } else {
+                blend(*b, Color32::BLACK, 0.75)
+            }
+        })
+        .collect();
+    Some((
+        different,
+        ColorImage {
+            size: actual.size,
+            pixels,
+        },
+    ))
+}
+
+/// Wait until the terminal has had no output for a while, or give up after
+/// `timeout`.
+fn wait_for_quiescence(terminal: &mut Terminal, timeout: Duration) -> Result<()> {
+    const QUIET: Duration = Duration::from_millis(500);
+    let start = Instant::now();
+    let mut last_change = start;
+    let mut generation = terminal.generation();
+    while last_change.elapsed() < QUIET && start.elapsed() < timeout {
+        terminal.read()?;
+        if terminal.generation() != generation {
+            generation = terminal.generation();
+            last_change = Instant::now();
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    Ok(())
+}
+
+/// Save a screenshot of the terminal as a PNG.
+pub fn capture_to_png(terminal: &Terminal, path: &Path) -> Result<()> {
+    save_png(&render(terminal), path)
+}
+
+/// Start a shell, type the contents of `expected` with an `.input` extension
+/// into it, and compare the screen with `expected` once the output settles.
+/// The screen is saved to `actual`, and if it doesn't match, the differences
+/// are saved next to it with a `.diff.png` extension. Returns whether they
+/// matched.
+pub fn run_test(expected: &Path, actual: &Path, tolerance: u8) -> Result<bool> {
+    const TIMEOUT: Duration = Duration::from_secs(10);
+    let input_path = expected.with_extension("input");
+    let input = std::fs::read(&input_path)
+        .with_context(|| format!("failed to read {}", input_path.display()))?;
+
+    let mut terminal = crate::shell()
+        .size(nix::pty::Winsize {
+            ws_row: 24,
+            ws_col: 80,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        })
+        .spawn()?;
+    terminal.set_theme(theme());
+    // Let the shell print its prompt before typing.
+    wait_for_quiescence(&mut terminal, TIMEOUT)?;
+    terminal.write(&input)?;
+    wait_for_quiescence(&mut terminal, TIMEOUT)?;
+
+    capture_to_png(&terminal, actual)?;
+    let image = load_png(actual)?;
+    let golden = load_png(expected)?;
+    match diff(&golden, &image, tolerance) {
+        Some((0, _)) => Ok(true),
+        Some((different, diff)) => {
+            let diff_path = actual.with_extension("diff.png");
+            save_png(&diff, &diff_path)?;
+            eprintln!(
+                "{} pixels differ from {}, see {}",
+                different,
+                expected.display(),
+                diff_path.display()
+            );
+            Ok(false)
+        }
+        None => {
+            eprintln!(
+                "the screenshot is {:?} but {} is {:?}",
+                image.size,
+                expected.display(),
+                golden.size
+            );
+            Ok(false)
+        }
+    }
+}
+
+#[test]
+fn test_render() {
+    use termulus::terminal::{feed, test_terminal};
+
+    let (mut term, mut app) = test_terminal();
+    term.set_theme(theme());
+    feed(&mut term, &mut app, b"\x1b[41m \x1b[0mA");
+    let image = render(&term);
+    let rows = term.size().rows;
+    let cell = [image.size[0] / term.size().cols, image.size[1] / rows];
+    let pixels = |col: usize| {
+        (0..cell[1]).flat_map(move |y| (0..cell[0]).map(move |x| (col * cell[0] + x, y)))
+    };
+    // A red space, some glyph pixels, then the cursor.
+    assert!(pixels(0).all(|p| image[p] == Color32::from_rgb(0xcd, 0, 0)));
+    assert!(pixels(1).any(|p| image[p] == Color32::from_rgb(0xe5, 0xe5, 0xe5)));
+    assert!(pixels(1).any(|p| image[p] == Color32::BLACK));
+    assert!(pixels(2).all(|p| image[p] == Color32::from_rgb(0x80, 0x80, 0x80)));
+}
+
+#[test]
+fn test_png_round_trip_and_diff() {
+    let (term, _app) = termulus::terminal::test_terminal();
+    let image = render(&term);
+    let path = std::env::temp_dir().join(format!("termulus-{}.png", std::process::id()));
+    save_png(&image, &path).unwrap();
+    let loaded = load_png(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(loaded, image);
+
+    // The bottom right corner is background, away from the cursor.
+    let last = image.pixels.len() - 1;
+    let mut changed = image.clone();
+    changed.pixels[last] = Color32::from_rgb(10, 0, 0);
+    changed.pixels[last - 1] = Color32::from_rgb(11, 0, 0);
+    assert_eq!(diff(&image, &changed, 10).unwrap().0, 1);
+    assert_eq!(diff(&image, &changed, 11).unwrap().0, 0);
+    let (_, highlighted) = diff(&image, &changed, 0).unwrap();
+    assert_eq!(highlighted.pixels[last], Color32::RED);
+    assert!(diff(&image, &ColorImage::new([1, 1], Color32::BLACK), 0).is_none());
+}
@@ -0,0 +1,421 @@
+//! Colors and text attributes, set with SGR (`CSI Pm m`).
+use crate::parser::SgrAttribute;
+
+/// A color as the application asked for it. Anything but [`Color::Rgb`] is
+/// looked up in the [`Palette`] when it's drawn, so the user's theme applies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Color {
+    Black,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    White,
+    BrightBlack,
+    BrightRed,
+    BrightGreen,
+    BrightYellow,
+    BrightBlue,
+    BrightMagenta,
+    BrightCyan,
+    BrightWhite,
+    /// `38;5;n`, an entry in the 256 color palette.
+    Indexed(u8),
+    /// `38;2;r;g;b`
+    Rgb(u8, u8, u8),
+}
+
+impl Color {
+    const NAMED: [Color; 16] = [
+        Color::Black,
+        Color::Red,
+        Color::Green,
+        Color::Yellow,
+        Color::Blue,
+        Color::Magenta,
+        Color::Cyan,
+        Color::White,
+        Color::BrightBlack,
+        Color::BrightRed,
+        Color::BrightGreen,
+        Color::BrightYellow,
+        Color::BrightBlue,
+        Color::BrightMagenta,
+        Color::BrightCyan,
+        Color::BrightWhite,
+    ];
+
+    /// One of the 16 named colors, where 0 is black and 8 is bright black.
+    pub fn named(index: u8) -> Option<Self> {
+        Self::NAMED.get(index as usize).copied()
+    }
+
+    /// The same color, with the first 16 palette entries as named colors, so
+    /// that e.g. `38;5;1` and `31` make equal styles and don't split runs.
+    pub fn canonical(self) -> Self {
+        match self {
+            Color::Indexed(index) => Self::named(index).unwrap_or(self),
+            color => color,
+        }
+    }
+
+    /// The palette index, if the color isn't RGB.
+    pub fn index(&self) -> Option<u8> {
+        match self {
+            Color::Indexed(index) => Some(*index),
+            Color::Rgb(..) => None,
+            named => Self::NAMED.iter().position(|c| c == named).map(|i| i as u8),
+        }
+    }
+
+    /// The SGR parameters for the color, where `base` is 30 for the
+    /// foreground or 40 for the background.
+    fn sgr(&self, base: u8) -> String {
+        match *self {
+            Color::Indexed(index) => format!("{};5;{}", base + 8, index),
+            Color::Rgb(r, g, b) => format!("{};2;{};{};{}", base + 8, r, g, b),
+            named => match named.index().unwrap_or_default() {
+                index @ 0..=7 => (base + index).to_string(),
+                index => (base + 60 + index - 8).to_string(),
+            },
+        }
+    }
+
+    /// The name of one of the 16 named colors, e.g. "bright red".
+    pub fn name(&self) -> Option<&'static str> {
+        const NAMES: [&str; 16] = [
+            "black",
+            "red",
+            "green",
+            "yellow",
+            "blue",
+            "magenta",
+            "cyan",
+            "white",
+            "bright black",
+            "bright red",
+            "bright green",
+            "bright yellow",
+            "bright blue",
+            "bright magenta",
+            "bright cyan",
+            "bright white",
+        ];
+        match self {
+            Color::Indexed(_) | Color::Rgb(..) => None,
+            named => Some(NAMES[named.index()? as usize]),
+        }
+    }
+}
+
+/// A color that applications can change with OSC 4 and OSC 10 to 12.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorSlot {
+    Indexed(u8),
+    /// The default foreground color, OSC 10.
+    Foreground,
+    /// The default background color, OSC 11.
+    Background,
+    /// OSC 12.
+    Cursor,
+}
+
+/// The RGB values of the 256 indexed colors, and the default colors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette {
+    colors: [(u8, u8, u8); 256],
+    /// `None` leaves the default colors up to the embedder.
+    pub foreground: Option<(u8, u8, u8)>,
+    pub background: Option<(u8, u8, u8)>,
+    pub cursor: Option<(u8, u8, u8)>,
+}
+
+impl Palette {
+    pub fn get(&self, index: u8) -> (u8, u8, u8) {
+        self.colors[index as usize]
+    }
+
+    pub fn set(&mut self, index: u8, rgb: (u8, u8, u8)) {
+        self.colors[index as usize] = rgb;
+    }
+
+    /// The RGB value of a color.
+    pub fn rgb(&self, color: Color) -> (u8, u8, u8) {
+        match color {
+            Color::Rgb(r, g, b) => (r, g, b),
+            color => self.get(color.index().unwrap_or_default()),
+        }
+    }
+
+    pub fn slot(&self, slot: ColorSlot) -> Option<(u8, u8, u8)> {
+        match slot {
+            ColorSlot::Indexed(index) => Some(self.get(index)),
+            ColorSlot::Foreground => self.foreground,
+            ColorSlot::Background => self.background,
+            ColorSlot::Cursor => self.cursor,
+        }
+    }
+
+    pub fn set_slot(&mut self, slot: ColorSlot, rgb: (u8, u8, u8)) {
+        match slot {
+            ColorSlot::Indexed(index) => self.set(index, rgb),
+            ColorSlot::Foreground => self.foreground = Some(rgb),
+            ColorSlot::Background => self.background = Some(rgb),
+            ColorSlot::Cursor => self.cursor = Some(rgb),
+        }
+    }
+
+    /// Set `slot` back to its color in `theme`.
+    pub fn reset_slot(&mut self, slot: ColorSlot, theme: &Palette) {
+        match slot {
+            ColorSlot::Indexed(index) => self.set(index, theme.get(index)),
+            ColorSlot::Foreground => self.foreground = theme.foreground,
+            ColorSlot::Background => self.background = theme.background,
+            ColorSlot::Cursor => self.cursor = theme.cursor,
+        }
+    }
+
+    /// Set all of the indexed colors back to the ones in `theme`, but not the
+    /// default colors.
+    pub fn reset_indexed(&mut self, theme: &Palette) {
+        self.colors = theme.colors;
+    }
+}
+
+impl Default for Palette {
+    /// xterm's default colors.
+    fn default() -> Self {
+        const ANSI: [(u8, u8, u8); 16] = [
+            (0x00, 0x00, 0x00),
+            (0xcd, 0x00, 0x00),
+            (0x00, 0xcd, 0x00),
+            (0xcd, 0xcd, 0x00),
+            (0x00, 0x00, 0xee),
+            (0xcd, 0x00, 0xcd),
+            (0x00, 0xcd, 0xcd),
+            (0xe5, 0xe5, 0xe5),
+            (0x7f, 0x7f, 0x7f),
+            (0xff, 0x00, 0x00),
+            (0x00, 0xff, 0x00),
+            (0xff, 0xff, 0x00),
+            (0x5c, 0x5c, 0xff),
+            (0xff, 0x00, 0xff),
+            (0x00, 0xff, 0xff),
+            (0xff, 0xff, 0xff),
+        ];
+        const LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+        let mut colors = [(0, 0, 0); 256];
+        colors[..16].copy_from_slice(&ANSI);
+        // A 6x6x6 color cube...
+        for i in 0..216 {
+            colors[16 + i] = (LEVELS[i / 36], LEVELS[i / 6 % 6], LEVELS[i % 6]);
+        }
+        // ...and a grayscale ramp.
+        for i in 0..24 {
+            let level = 8 + 10 * i as u8;
+            colors[232 + i] = (level, level, level);
+        }
+        Self {
+            colors,
+            foreground: None,
+            background: None,
+            cursor: None,
+        }
+    }
+}
+
+/// How text is underlined, set with `CSI 4 m` or `CSI 4:n m`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Hash)]
+pub enum UnderlineStyle {
+    #[default]
+    None,
+    Single,
+    Double,
+    /// Also known as undercurl, used by editors for diagnostics.
+    Curly,
+    Dotted,
+    Dashed,
+}
+
+impl UnderlineStyle {
+    /// The style for the `n` in `4:n`.
+    pub fn from_subparam(n: usize) -> Option<Self> {
+        Some(match n {
+            0 => UnderlineStyle::None,
+            1 => UnderlineStyle::Single,
+            2 => UnderlineStyle::Double,
+            3 => UnderlineStyle::Curly,
+            4 => UnderlineStyle::Dotted,
+            5 => UnderlineStyle::Dashed,
+            _ => return None,
+        })
+    }
+
+    /// The `n` in `4:n`, the reverse of [`UnderlineStyle::from_subparam`].
+    pub fn subparam(self) -> usize {
+        self as usize
+    }
+
+    fn name(self) -> Option<&'static str> {
+        match self {
+            UnderlineStyle::None => None,
+            UnderlineStyle::Single => Some("underline"),
+            UnderlineStyle::Double => Some("double underline"),
+            UnderlineStyle::Curly => Some("curly underline"),
+            UnderlineStyle::Dotted => Some("dotted underline"),
+            UnderlineStyle::Dashed => Some("dashed underline"),
+        }
+    }
+}
+
+/// The colors and attributes of a cell. Also used for the terminal's pen, the
+/// style that newly written text gets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Style {
+    /// `None` for the default color.
+    pub fg: Option<Color>,
+    pub bg: Option<Color>,
+    pub bold: bool,
+    pub dim: bool,
+    pub italic: bool,
+    pub underline: UnderlineStyle,
+    /// `None` to underline in the text color.
+    pub underline_color: Option<Color>,
+    pub reverse: bool,
+    pub strikethrough: bool,
+    pub overline: bool,
+}
+
+impl Style {
+    pub fn apply(&mut self, attribute: SgrAttribute) {
+        match attribute {
+            SgrAttribute::Reset => *self = Style::default(),
+            SgrAttribute::Bold => self.bold = true,
+            SgrAttribute::Dim => self.dim = true,
+            SgrAttribute::Italic => self.italic = true,
+            SgrAttribute::Underline(style) => self.underline = style,
+            SgrAttribute::Reverse => self.reverse = true,
+            SgrAttribute::Strikethrough => self.strikethrough = true,
+            SgrAttribute::Overline => self.overline = true,
+            SgrAttribute::NormalIntensity => {
+                self.bold = false;
+                self.dim = false;
+            }
+            SgrAttribute::NotItalic => self.italic = false,
+            SgrAttribute::NotUnderlined => self.underline = UnderlineStyle::None,
+            SgrAttribute::NotReversed => self.reverse = false,
+            SgrAttribute::NotStrikethrough => self.strikethrough = false,
+            SgrAttribute::NotOverlined => self.overline = false,
+            SgrAttribute::Foreground(color) => self.fg = Some(color.canonical()),
+            SgrAttribute::Background(color) => self.bg = Some(color.canonical()),
+            SgrAttribute::DefaultForeground => self.fg = None,
+            SgrAttribute::DefaultBackground => self.bg = None,
+            SgrAttribute::UnderlineColor(color) => self.underline_color = Some(color.canonical()),
+            SgrAttribute::DefaultUnderlineColor => self.underline_color = None,
+            SgrAttribute::Unknown(_) => {}
+        }
+    }
+
+    /// The SGR parameters that select this style from scratch, e.g. `0;1;31`.
+    pub fn sgr(&self) -> String {
+        let mut params = vec!["0".to_owned()];
+        for (on, param) in [
+            (self.bold, "1"),
+            (self.dim, "2"),
+            (self.italic, "3"),
+            (self.underline == UnderlineStyle::Single, "4"),
+            (self.reverse, "7"),
+            (self.strikethrough, "9"),
+            (self.overline, "53"),
+        ] {
+            if on {
+                params.push(param.to_owned());
+            }
+        }
+        if !matches!(
+            self.underline,
+            UnderlineStyle::None | UnderlineStyle::Single
+        ) {
+            params.push(format!("4:{}", self.underline.subparam()));
+        }
+        if let Some(fg) = self.fg {
+            params.push(fg.sgr(30));
+        }
+        if let Some(bg) = self.bg {
+            params.push(bg.sgr(40));
+        }
+        if let Some(color) = self.underline_color {
+            // There's no short form for the underline color.
+            params.push(match color {
+                Color::Rgb(r, g, b) => format!("58;2;{};{};{}", r, g, b),
+                color => format!("58;5;{}", color.index().unwrap_or_default()),
+            });
+        }
+        params.join(";")
+    }
+
+    /// The names of the attributes that are on, e.g. `["bold", "italic"]`.
+    pub fn attribute_names(&self) -> Vec<&'static str> {
+        [
+            (self.bold, "bold"),
+            (self.dim, "dim"),
+            (self.italic, "italic"),
+            (self.reverse, "reverse"),
+            (self.strikethrough, "strikethrough"),
+            (self.overline, "overline"),
+        ]
+        .into_iter()
+        .filter_map(|(on, name)| on.then_some(name))
+        .chain(self.underline.name())
+        .collect()
+    }
+}
+
+#[test]
+fn test_default_palette() {
+    let palette = Palette::default();
+    assert_eq!(palette.rgb(Color::Red), (0xcd, 0, 0));
+    assert_eq!(palette.rgb(Color::BrightBlue), (0x5c, 0x5c, 0xff));
+    assert_eq!(palette.get(16), (0, 0, 0));
+    assert_eq!(palette.get(208), (255, 135, 0));
+    assert_eq!(palette.get(231), (255, 255, 255));
+    assert_eq!(palette.get(244), (128, 128, 128));
+    assert_eq!(palette.rgb(Color::Rgb(1, 2, 3)), (1, 2, 3));
+}
+
+#[test]
+fn test_sgr() {
+    assert_eq!(Style::default().sgr(), "0");
+    let style = Style {
+        fg: Some(Color::BrightRed),
+        bg: Some(Color::Indexed(208)),
+        bold: true,
+        reverse: true,
+        ..Style::default()
+    };
+    assert_eq!(style.sgr(), "0;1;7;91;48;5;208");
+    let style = Style {
+        fg: Some(Color::Rgb(1, 2, 3)),
+        bg: Some(Color::Blue),
+        ..Style::default()
+    };
+    assert_eq!(style.sgr(), "0;38;2;1;2;3;44");
+
+    let style = Style {
+        underline: UnderlineStyle::Curly,
+        underline_color: Some(Color::Red),
+        ..Style::default()
+    };
+    assert_eq!(style.sgr(), "0;4:3;58;5;1");
+    assert_eq!(style.attribute_names(), ["curly underline"]);
+
+    // Palette entries with names are stored by name.
+    let mut style = Style::default();
+    style.apply(SgrAttribute::Foreground(Color::Indexed(9)));
+    style.apply(SgrAttribute::Background(Color::Indexed(16)));
+    assert_eq!(style.fg, Some(Color::BrightRed));
+    assert_eq!(style.bg, Some(Color::Indexed(16)));
+}
@@ -1,79 +1,714 @@
-use std::os::fd::{AsRawFd, OwnedFd};
+use std::{
+    collections::{BTreeSet, HashMap, VecDeque},
+    ffi::{CString, OsStr, OsString},
+    io::IoSlice,
+    ops::Range,
+    os::{
+        fd::{AsRawFd, OwnedFd},
+        unix::ffi::OsStringExt,
+    },
+    time::{Duration, Instant},
+};
 
-use crate::parser::{OutputParser, TerminalOutput};
+use crate::{
+    clock::{Clock, SystemClock},
+    coords::{AbsolutePoint, ScreenPoint, ScreenSize},
+    grid::{char_width, Cell, Grid, LineFlags, LineSize, Row},
+    input::{encode_arrows, Arrow, MouseEvent, MouseInput},
+    parser::{ColorRequest, OutputParser, PromptMarkKind, StringKind, TerminalOutput},
+    style::{ColorSlot, Palette, Style, UnderlineStyle},
+};
 use anyhow::Result;
-use egui::{self, Vec2};
 use nix::{
     errno::Errno,
     fcntl::{FcntlArg, OFlag},
     libc::O_ACCMODE,
 };
 
-#[derive(Debug, Clone)]
+/// The reply to DA1 (`CSI c`): a VT220 with ANSI color. Update it as more
+/// features are supported.
+const PRIMARY_DEVICE_ATTRIBUTES: &[u8] = b"\x1b[?62;22c";
+/// The reply to DA2 (`CSI > c`): a VT220, firmware version 10, no ROM cartridge.
+const SECONDARY_DEVICE_ATTRIBUTES: &[u8] = b"\x1b[>1;10;0c";
+
+/// The distance between the tab stops a terminal starts with.
+const TAB_WIDTH: usize = 8;
+
+/// Whether each column starts with a tab stop: every [`TAB_WIDTH`] columns.
+fn default_tab_stops(cols: Range<usize>) -> impl Iterator<Item = bool> {
+    cols.map(|col| col % TAB_WIDTH == 0)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct CursorPos {
     x: usize,
     y: usize,
 }
 
+/// What DECSC (`ESC 7`) and `CSI s` save and DECRC (`ESC 8`) and `CSI u`
+/// bring back. A pending wrap is kept too, since it's a cursor sitting one past
+/// the last column.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SavedCursor {
+    cursor: CursorPos,
+    pen: Style,
+}
+
 impl CursorPos {
     fn new(x: usize, y: usize) -> Self {
         Self { x, y }
     }
 
-    pub fn to_buffer_pos(&self, buffer: &[u8]) -> usize {
-        buffer
-            .split(|b| *b == b'\n')
-            .take(self.y)
-            .map(|line| line.len())
-            .sum::<usize>()
-            + self.x
+    fn set(&mut self, point: ScreenPoint) {
+        self.x = point.col();
+        self.y = point.row();
+    }
+
+    pub fn to_screen_point(&self, size: ScreenSize) -> ScreenPoint {
+        ScreenPoint::clamped(self.x, self.y, size)
+    }
+
+    pub fn x(&self) -> usize {
+        self.x
+    }
+
+    pub fn y(&self) -> usize {
+        self.y
+    }
+}
+
+/// Which mouse events are reported to the application.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MouseProtocol {
+    #[default]
+    Off,
+    /// 9: presses only.
+    X10,
+    /// 1000: presses and releases.
+    Normal,
+    /// 1002: presses, releases, and motion while a button is held.
+    ButtonEvent,
+    /// 1003: presses, releases, and all motion.
+    AnyEvent,
+}
+
+impl MouseProtocol {
+    fn from_mode(mode: usize) -> Option<Self> {
+        match mode {
+            9 => Some(MouseProtocol::X10),
+            1000 => Some(MouseProtocol::Normal),
+            1002 => Some(MouseProtocol::ButtonEvent),
+            1003 => Some(MouseProtocol::AnyEvent),
+            _ => None,
+        }
+    }
+}
+
+/// How mouse events are written, see [`crate::input::MouseInput`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MouseEncoding {
+    /// `CSI M` followed by three bytes, offset by 32. Positions past 223 can't
+    /// be reported.
+    #[default]
+    Default,
+    /// 1005: like the default, but positions are UTF-8 encoded so they can go
+    /// up to 2015.
+    Utf8,
+    /// 1006: `CSI < b ; x ; y M`, with `m` instead of `M` for releases.
+    Sgr,
+    /// 1015: `CSI b ; x ; y M`, with the button offset by 32 like the default.
+    Urxvt,
+}
+
+impl MouseEncoding {
+    fn from_mode(mode: usize) -> Option<Self> {
+        match mode {
+            1005 => Some(MouseEncoding::Utf8),
+            1006 => Some(MouseEncoding::Sgr),
+            1015 => Some(MouseEncoding::Urxvt),
+            _ => None,
+        }
+    }
+}
+
+/// DEC private modes, set with `CSI ? Pm h` and reset with `CSI ? Pm l`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Modes {
+    /// DECCKM (1): cursor keys send `ESC O` sequences instead of `ESC [`.
+    pub application_cursor_keys: bool,
+    /// DECAWM (7): text that reaches the right margin continues on the next
+    /// line. On by default, otherwise the last column is overwritten.
+    pub auto_wrap: bool,
+    /// DECTCEM (25), on by default.
+    pub cursor_visible: bool,
+    /// DECARM (8): held keys repeat. On by default, applications that don't
+    /// want repeats reset it.
+    pub auto_repeat: bool,
+    /// 9, 1000, 1002, or 1003. Only one can be on at a time.
+    pub mouse: MouseProtocol,
+    /// 1005, 1006, or 1015.
+    pub mouse_encoding: MouseEncoding,
+    /// Alternate scroll (1007): the mouse wheel sends cursor keys on the alternate screen.
+    pub alternate_scroll: bool,
+    /// 47, 1047, or 1049.
+    pub alternate_screen: bool,
+    /// Bracketed paste (2004): pasted text is wrapped in `CSI 200 ~` and `CSI 201 ~`.
+    pub bracketed_paste: bool,
+    /// Synchronized update (2026): the application is in the middle of drawing
+    /// a frame.
+    pub synchronized_update: bool,
+    /// In-band resize (2048): size changes are also reported with `CSI 48 t`,
+    /// for applications that can't rely on SIGWINCH reaching them.
+    pub in_band_resize: bool,
+    /// Modes that are set but don't do anything yet. These are still tracked so
+    /// that they can be saved and restored.
+    other: BTreeSet<usize>,
+}
+
+impl Default for Modes {
+    fn default() -> Self {
+        Self {
+            application_cursor_keys: false,
+            auto_wrap: true,
+            cursor_visible: true,
+            auto_repeat: true,
+            mouse: MouseProtocol::Off,
+            mouse_encoding: MouseEncoding::Default,
+            alternate_scroll: false,
+            alternate_screen: false,
+            bracketed_paste: false,
+            synchronized_update: false,
+            in_band_resize: false,
+            other: BTreeSet::new(),
+        }
+    }
+}
+
+impl Modes {
+    pub fn get_private(&self, mode: usize) -> bool {
+        match mode {
+            1 => self.application_cursor_keys,
+            7 => self.auto_wrap,
+            8 => self.auto_repeat,
+            25 => self.cursor_visible,
+            9 | 1000 | 1002 | 1003 => MouseProtocol::from_mode(mode) == Some(self.mouse),
+            1005 | 1006 | 1015 => MouseEncoding::from_mode(mode) == Some(self.mouse_encoding),
+            1007 => self.alternate_scroll,
+            47 | 1047 | 1049 => self.alternate_screen,
+            2004 => self.bracketed_paste,
+            2026 => self.synchronized_update,
+            2048 => self.in_band_resize,
+            _ => self.other.contains(&mode),
+        }
+    }
+
+    /// The answer to DECRQM for a DEC private mode: 1 if it's set, 2 if it's
+    /// reset, or 0 if it isn't recognized.
+    pub fn report_private(&self, mode: usize) -> u8 {
+        match mode {
+            1 | 7 | 8 | 25 | 9 | 1000 | 1002 | 1003 | 1005 | 1006 | 1015 | 1007 | 47 | 1047
+            | 1049 | 2004 | 2026 | 2048 => {
+                if self.get_private(mode) {
+                    1
+                } else {
+                    2
+                }
+            }
+            _ if self.other.contains(&mode) => 1,
+            _ => 0,
+        }
     }
 
-    pub fn update(&mut self, incoming: &[u8]) {
-        for byte in incoming.iter() {
-            match byte {
-                b'\n' => {
-                    self.x = 0;
-                    self.y += 1;
+    pub fn set_private(&mut self, mode: usize, enable: bool) {
+        match mode {
+            1 => self.application_cursor_keys = enable,
+            7 => self.auto_wrap = enable,
+            8 => self.auto_repeat = enable,
+            25 => self.cursor_visible = enable,
+            9 | 1000 | 1002 | 1003 => {
+                if enable {
+                    self.mouse = MouseProtocol::from_mode(mode).unwrap_or_default();
+                } else {
+                    // Like xterm, turning off any of them turns off reporting.
+                    self.mouse = MouseProtocol::Off;
                 }
-                b'\r' => {
-                    self.x = 0;
+            }
+            1005 | 1006 | 1015 => {
+                let encoding = MouseEncoding::from_mode(mode).unwrap_or_default();
+                if enable {
+                    self.mouse_encoding = encoding;
+                } else if self.mouse_encoding == encoding {
+                    self.mouse_encoding = MouseEncoding::Default;
                 }
-                b'\t' => {
-                    self.x += 4;
+            }
+            1007 => self.alternate_scroll = enable,
+            // The screens are switched by the terminal, see `Terminal::set_private_mode`.
+            47 | 1047 | 1049 => self.alternate_screen = enable,
+            2004 => self.bracketed_paste = enable,
+            2026 => self.synchronized_update = enable,
+            2048 => self.in_band_resize = enable,
+            // Kept so they can be reported, though they do nothing.
+            _ => {
+                if enable {
+                    self.other.insert(mode);
+                } else {
+                    self.other.remove(&mode);
                 }
-                _ => {
-                    self.x += 1;
+            }
+        }
+    }
+
+    pub fn summary(&self) -> ModeSummary {
+        ModeSummary {
+            alternate_screen: self.alternate_screen,
+            mouse: self.mouse,
+            bracketed_paste: self.bracketed_paste,
+            application_cursor_keys: self.application_cursor_keys,
+            cursor_visible: self.cursor_visible,
+            synchronized_update: self.synchronized_update,
+        }
+    }
+}
+
+/// The modes an embedder is likely to reflect in its UI, e.g. a badge while
+/// mouse reporting is on. Cheap enough to fetch every frame, and changes are
+/// reported with [`TerminalEvent::ModesChanged`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModeSummary {
+    pub alternate_screen: bool,
+    pub mouse: MouseProtocol,
+    pub bracketed_paste: bool,
+    pub application_cursor_keys: bool,
+    pub cursor_visible: bool,
+    pub synchronized_update: bool,
+}
+
+/// Running totals for diagnosing performance.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TerminalStats {
+    /// Bytes read from the pty.
+    pub bytes_read: u64,
+    /// Outputs produced by the parser.
+    pub outputs_parsed: u64,
+}
+
+/// A request from the application to read a selection (OSC 52 with a `?` payload).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClipboardQuery {
+    /// The selection parameter as sent by the application, e.g. `c` for the
+    /// clipboard or `p` for the primary selection.
+    pub selection: String,
+}
+
+/// The embedder's answer to a [`ClipboardQuery`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClipboardAnswer {
+    /// Don't respond at all, which is what xterm does when reads are disallowed.
+    Deny,
+    /// Respond right away with the given contents.
+    Contents(String),
+    /// Respond later with [`Terminal::answer_clipboard_query`], e.g. after
+    /// prompting the user.
+    Defer,
+}
+
+/// Whether applications are allowed to read the clipboard.
+///
+/// Reading the clipboard lets any program running in the terminal (including
+/// one on the other end of an ssh connection) see whatever the user last copied,
+/// so it is denied unless the embedder opts in.
+#[derive(Default)]
+pub enum ClipboardPolicy {
+    #[default]
+    Deny,
+    /// Pass every query on to the embedder as a [`TerminalEvent::ClipboardQuery`].
+    Allow,
+    /// Ask a callback, which can prompt the user before deciding.
+    Ask(Box<dyn FnMut(&ClipboardQuery) -> ClipboardAnswer + Send>),
+}
+
+/// A prompt mark (OSC 133) and the line it was placed on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PromptMark {
+    pub kind: PromptMarkKind,
+    /// The absolute line number, see [`AbsolutePoint`].
+    pub line: usize,
+    /// The column the cursor was in, e.g. where the command starts for
+    /// [`PromptMarkKind::CommandStart`].
+    pub col: usize,
+}
+
+/// A range of selected text, from `start` to `end` inclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Selection {
+    start: AbsolutePoint,
+    end: AbsolutePoint,
+}
+
+impl Selection {
+    /// Create a selection between two points, in either order.
+    pub fn new(a: AbsolutePoint, b: AbsolutePoint) -> Self {
+        Self {
+            start: a.min(b),
+            end: a.max(b),
+        }
+    }
+
+    pub fn start(&self) -> AbsolutePoint {
+        self.start
+    }
+
+    pub fn end(&self) -> AbsolutePoint {
+        self.end
+    }
+}
+
+/// The parts of the visible screen that changed since the last call to
+/// [`Terminal::take_damage`].
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Damage {
+    /// Everything needs to be redrawn, e.g. because the display scrolled.
+    pub full: bool,
+    /// Visible rows that changed.
+    pub rows: BTreeSet<usize>,
+}
+
+impl Damage {
+    pub fn is_empty(&self) -> bool {
+        !self.full && self.rows.is_empty()
+    }
+
+    pub fn contains(&self, row: usize) -> bool {
+        self.full || self.rows.contains(&row)
+    }
+}
+
+/// Protocols that hold back output or collect data until the application ends
+/// them. A program that crashes halfway through would leave them open forever,
+/// so each one is given a deadline, see [`Terminal::apply_pending`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Protocol {
+    /// Mode 2026, output is held back until the update is done.
+    SynchronizedUpdate,
+    /// A DCS string.
+    Dcs,
+    /// An APC string, e.g. a kitty graphics chunk.
+    Apc,
+}
+
+impl Protocol {
+    pub const ALL: [Protocol; 3] = [Protocol::SynchronizedUpdate, Protocol::Dcs, Protocol::Apc];
+
+    pub fn default_timeout(self) -> Duration {
+        match self {
+            // Long enough for any sane frame, short enough that a stuck screen
+            // isn't mistaken for a hang.
+            Protocol::SynchronizedUpdate => Duration::from_secs(1),
+            // Images can take a while to send over a slow connection.
+            Protocol::Dcs | Protocol::Apc => Duration::from_secs(10),
+        }
+    }
+}
+
+/// A search pattern for [`Terminal::history_search`].
+enum Matcher<'p> {
+    Plain(&'p str),
+    #[cfg(feature = "regex")]
+    Regex(regex::Regex),
+}
+
+impl Matcher<'_> {
+    /// The byte ranges of the matches in `text`.
+    fn find(&self, text: &str) -> Vec<Range<usize>> {
+        match self {
+            Matcher::Plain(pattern) => text
+                .match_indices(pattern)
+                .map(|(start, m)| start..start + m.len())
+                .collect(),
+            #[cfg(feature = "regex")]
+            Matcher::Regex(re) => re.find_iter(text).map(|m| m.range()).collect(),
+        }
+    }
+}
+
+/// The text of a visible row for screen readers, see [`Terminal::accessible_lines`].
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct AccessibleLine {
+    /// The row's text, without trailing blanks.
+    pub text: String,
+    /// The byte offset in `text` of each column. Spacers aren't part of the
+    /// text, so they get the offset of whatever comes after them.
+    offsets: Vec<usize>,
+}
+
+impl AccessibleLine {
+    fn new(row: &Row) -> Self {
+        let mut text = String::new();
+        let mut offsets = Vec::with_capacity(row.cols());
+        for cell in &row.cells()[..row.cols()] {
+            offsets.push(text.len());
+            if !cell.is_spacer() {
+                text.push(cell.text_char());
+            }
+        }
+        text.truncate(text.trim_end().len());
+        for offset in offsets.iter_mut() {
+            *offset = (*offset).min(text.len());
+        }
+        Self { text, offsets }
+    }
+
+    /// The byte offset in `text` of a column. Columns past the end of the text
+    /// map to the end.
+    pub fn column_to_offset(&self, col: usize) -> usize {
+        self.offsets.get(col).copied().unwrap_or(self.text.len())
+    }
+
+    /// The column that the byte at `offset` in `text` is in.
+    pub fn offset_to_column(&self, offset: usize) -> usize {
+        self.offsets
+            .partition_point(|start| *start <= offset)
+            .saturating_sub(1)
+    }
+}
+
+/// Where bytes written to the application came from, see
+/// [`Terminal::set_outgoing_tap`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteOrigin {
+    /// Key presses, mouse reports, and pastes, from [`Terminal::write`].
+    Input,
+    /// Replies to the application's queries.
+    Response,
+    /// Sequences sent by hand, e.g. from the GUI's send dialog.
+    Injected,
+}
+
+/// See [`Terminal::set_outgoing_tap`].
+type OutgoingTap<'a> = Box<dyn FnMut(WriteOrigin, &[u8]) + Send + 'a>;
+
+/// Decode `text` as UTF-8, carrying on from the `pending` bytes of a sequence
+/// that was split off the end of the previous text. An incomplete sequence at
+/// the end of `text` is left in `pending`, and invalid bytes become U+FFFD.
+fn decode_utf8(pending: &mut Vec<u8>, text: &[u8], mut f: impl FnMut(char)) {
+    let joined;
+    let mut bytes = if pending.is_empty() {
+        text
+    } else {
+        pending.extend_from_slice(text);
+        joined = std::mem::take(pending);
+        &joined[..]
+    };
+    loop {
+        match std::str::from_utf8(bytes) {
+            Ok(text) => {
+                text.chars().for_each(f);
+                return;
+            }
+            Err(e) => {
+                let (valid, rest) = bytes.split_at(e.valid_up_to());
+                std::str::from_utf8(valid)
+                    .expect("to be valid up to the error")
+                    .chars()
+                    .for_each(&mut f);
+                match e.error_len() {
+                    Some(len) => {
+                        f(char::REPLACEMENT_CHARACTER);
+                        bytes = &rest[len..];
+                    }
+                    None => {
+                        pending.extend_from_slice(rest);
+                        return;
+                    }
                 }
             }
         }
     }
 }
 
+/// Bytes in caret notation, as `cat -v` shows them: `^[` for ESC, `^?` for
+/// DEL, and so on. Text is left as it is.
+pub fn caret_notation(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    for c in String::from_utf8_lossy(bytes).chars() {
+        match c {
+            '\0'..='\x1f' => {
+                out.push('^');
+                out.push((c as u8 + 0x40) as char);
+            }
+            '\x7f' => out.push_str("^?"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Things the embedder may want to react to. Drained with [`Terminal::events`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TerminalEvent {
+    /// The application is waiting for the contents of the clipboard, which should
+    /// be supplied with [`Terminal::answer_clipboard_query`].
+    ClipboardQuery(ClipboardQuery),
+    /// Something in [`Terminal::mode_summary`] changed.
+    ModesChanged(ModeSummary),
+    /// A protocol was left open for too long and has been closed.
+    TimedOut(Protocol),
+    /// The application changed the title. See [`Terminal::set_title_debounce`].
+    TitleChanged(String),
+    /// The application rang the bell (BEL).
+    Bell,
+}
+
 pub struct Terminal<'a> {
     parser: OutputParser<'a>,
-    buffer: Vec<u8>,
+    /// The start of a UTF-8 sequence that was split across reads.
+    utf8_pending: Vec<u8>,
+    grid: Grid,
+    /// The screen that isn't showing: the alternate screen, or the primary one
+    /// while the alternate screen is active (modes 47, 1047 and 1049).
+    other_grid: Grid,
+    /// The rows that scroll, set with DECSTBM. `None` for the whole screen.
+    scroll_region: Option<Range<usize>>,
+    /// Whether there's a tab stop at each column, set with HTS and cleared
+    /// with TBC.
+    tab_stops: Vec<bool>,
+    /// Lines that have scrolled off the top of the screen, oldest first.
+    scrollback: VecDeque<Row>,
+    scrollback_limit: usize,
+    /// How many lines have been dropped from the front of the scrollback, which
+    /// is the absolute line number of the first line in it.
+    lines_dropped: usize,
+    /// How many lines the display is scrolled back into the scrollback.
+    display_offset: usize,
+    prompt_marks: VecDeque<PromptMark>,
+    selection: Option<Selection>,
+    damage: Damage,
+    /// Set by the application with OSC 0 or OSC 2.
+    title: String,
+    title_debounce: Option<Duration>,
+    /// When the last [`TerminalEvent::TitleChanged`] was queued.
+    title_sent: Option<Instant>,
+    /// Whether the title has changed since the last event was queued.
+    title_pending: bool,
+    /// Outputs held back while a synchronized update (mode 2026) is in progress.
+    held: Vec<TerminalOutput<'static>>,
+    /// How many synchronized updates have been started, to tell them apart.
+    sync_updates: u64,
+    clock: Box<dyn Clock>,
+    /// Timeouts that have been changed from [`Protocol::default_timeout`].
+    timeouts: HashMap<Protocol, Duration>,
+    /// When each open protocol will be closed, and which instance of it
+    /// (e.g. which DCS string) the deadline is for.
+    deadlines: HashMap<Protocol, (u64, Instant)>,
+    /// Descriptions of the most recent outputs, if inspecting is enabled.
+    inspector: Option<VecDeque<String>>,
+    outgoing_tap: Option<OutgoingTap<'a>>,
     cursor: CursorPos,
-    saved_cursor: Option<CursorPos>,
+    /// The cursor and pen saved by DECSC (`ESC 7`) or `CSI s`.
+    saved_cursor: Option<SavedCursor>,
+    /// The last character printed on this screen and the style it had, for
+    /// REP.
+    last_printed: Option<(char, Style)>,
+    /// The style given to text as it's written, changed with SGR.
+    pen: Style,
+    mouse: MouseInput,
+    palette: Palette,
+    /// The colors that resets (OSC 104, 110 to 112) go back to.
+    theme: Palette,
+    size: ScreenSize,
+    /// The height and width of the window in pixels, for in-band resize reports.
+    pixel_size: (u16, u16),
+    modes: Modes,
+    /// Values saved by XTSAVE, a stack per mode so that nested saves restore in order.
+    saved_modes: HashMap<usize, Vec<bool>>,
+    clipboard_policy: ClipboardPolicy,
+    /// Bytes waiting to be written back to the application, e.g. replies to queries.
+    responses: Vec<u8>,
+    events: VecDeque<TerminalEvent>,
+    /// Bumped whenever something visible changes, so the GUI can tell when the
+    /// screen is different without diffing it.
+    generation: u64,
+    stats: TerminalStats,
     fd: OwnedFd,
+    /// The process [`TerminalBuilder`] started, to reap once it's gone.
+    child: Option<nix::unistd::Pid>,
+}
+
+impl Drop for Terminal<'_> {
+    fn drop(&mut self) {
+        // The process gets SIGHUP when the pty is closed, just after this. It's
+        // waited for on another thread so closing a pane doesn't block on it.
+        if let Some(child) = self.child {
+            std::thread::spawn(move || nix::sys::wait::waitpid(child, None));
+        }
+    }
 }
 
 impl<'a> Terminal<'a> {
-    // TODO: write a builder that spawns a new process so the fd doesn't need to be exposed
-    // to the rest of the program.
+    pub const DEFAULT_SCROLLBACK_LIMIT: usize = 1000;
+    /// How many descriptions the inspector keeps.
+    pub const INSPECTOR_LIMIT: usize = 200;
+
+    /// A terminal for the controlling end of a pty that's already set up.
+    /// [`TerminalBuilder`] starts the process and the pty as well.
     pub fn new(fd: OwnedFd) -> Self {
         let flags = nix::fcntl::fcntl(fd.as_raw_fd(), FcntlArg::F_GETFL).expect("fcntl");
         let mut flags = OFlag::from_bits(flags & O_ACCMODE).unwrap();
         // set fd to nonblocking
         flags.set(OFlag::O_NONBLOCK, true);
         nix::fcntl::fcntl(fd.as_raw_fd(), FcntlArg::F_SETFL(flags)).expect("fcntl");
-        Self {
+        let mut term = Self {
             fd,
+            child: None,
             parser: OutputParser::new(),
+            utf8_pending: Vec::new(),
             cursor: CursorPos::new(0, 0),
             saved_cursor: None,
-            buffer: Vec::new(),
+            last_printed: None,
+            pen: Style::default(),
+            mouse: MouseInput::default(),
+            palette: Palette::default(),
+            theme: Palette::default(),
+            size: ScreenSize::DEFAULT,
+            pixel_size: (0, 0),
+            modes: Modes::default(),
+            saved_modes: HashMap::new(),
+            clipboard_policy: ClipboardPolicy::default(),
+            responses: Vec::new(),
+            events: VecDeque::new(),
+            generation: 0,
+            stats: TerminalStats::default(),
+            grid: Grid::new(ScreenSize::DEFAULT),
+            other_grid: Grid::new(ScreenSize::DEFAULT),
+            scroll_region: None,
+            tab_stops: default_tab_stops(0..ScreenSize::DEFAULT.cols).collect(),
+            scrollback: VecDeque::new(),
+            scrollback_limit: Self::DEFAULT_SCROLLBACK_LIMIT,
+            lines_dropped: 0,
+            display_offset: 0,
+            prompt_marks: VecDeque::new(),
+            selection: None,
+            damage: Damage::default(),
+            title: String::new(),
+            title_debounce: None,
+            title_sent: None,
+            title_pending: false,
+            inspector: None,
+            held: Vec::new(),
+            sync_updates: 0,
+            outgoing_tap: None,
+            clock: Box::new(SystemClock),
+            timeouts: HashMap::new(),
+            deadlines: HashMap::new(),
+        };
+        if let Ok(ws) = term.get_window_size() {
+            term.size = ScreenSize::from(ws);
+            term.pixel_size = (ws.ws_ypixel, ws.ws_xpixel);
+            term.grid.resize(term.size);
+            term.other_grid.resize(term.size);
+            term.tab_stops = default_tab_stops(0..term.size.cols).collect();
         }
+        term
     }
 
     pub fn get_window_size(&self) -> Result<nix::pty::Winsize> {
@@ -101,95 +736,3615 @@ impl<'a> Terminal<'a> {
         unsafe {
             raw_set_win_size(self.fd.as_raw_fd(), size)?;
         }
+        self.size = ScreenSize::from(*size);
+        self.pixel_size = (size.ws_ypixel, size.ws_xpixel);
+        self.grid.resize(self.size);
+        self.other_grid.resize(self.size);
+        self.scroll_region = None;
+        // Stops that were set are kept, and new columns get the default ones.
+        let old_cols = self.tab_stops.len();
+        self.tab_stops.truncate(self.size.cols);
+        self.tab_stops
+            .extend(default_tab_stops(old_cols..self.size.cols));
+        self.cursor.set(self.cursor.to_screen_point(self.size));
+        self.damage_all();
+        if self.modes.in_band_resize {
+            self.queue_resize_report();
+            self.flush_responses()?;
+        }
         Ok(())
     }
 
-    /// Access the buffer as a &str. This function is safe because
-    /// we know that all non-printable characters have been removed by
-    /// the parser.
-    pub fn buffer(&self) -> &str {
-        unsafe { std::str::from_utf8_unchecked(&self.buffer) }
+    pub fn size(&self) -> ScreenSize {
+        self.size
     }
 
-    pub fn cursor_pos(&self) -> &CursorPos {
-        &self.cursor
+    /// The process started by [`TerminalBuilder`], if that's where this
+    /// terminal came from.
+    pub fn child(&self) -> Option<nix::unistd::Pid> {
+        self.child
     }
 
-    pub fn char_to_cursor_offset(&self) -> Vec2 {
-        println!("Retrieved cursor pos: {}, {}", self.cursor.x, self.cursor.y);
-        let lines = self.buffer.split(|b| *b == b'\n').count();
+    pub fn grid(&self) -> &Grid {
+        &self.grid
+    }
 
-        let x_off = self.cursor.x as f32;
-        let y_off = (self.cursor.y as isize - lines as isize) as f32;
-        Vec2::new(x_off, y_off)
+    /// The rows currently in view, taking the display offset into account.
+    pub fn visible_rows(&self) -> impl Iterator<Item = &Row> {
+        self.scrollback
+            .iter()
+            .skip(self.scrollback.len() - self.display_offset)
+            .chain(self.grid.rows())
+            .take(self.size.rows)
     }
 
-    pub fn write(&mut self, bytes: &[u8]) -> anyhow::Result<()> {
-        let mut bytes = &bytes[..];
-        while bytes.len() > 0 {
-            match nix::unistd::write(self.fd.as_raw_fd(), &bytes) {
-                Ok(written) => {
-                    bytes = &bytes[written..];
-                }
-                Err(Errno::EAGAIN) => {
+    /// The text of a row in view, see [`Row::text`]. Rows past the bottom of
+    /// the screen are empty.
+    pub fn get_line_text(&self, row: usize) -> String {
+        self.visible_rows()
+            .nth(row)
+            .map(Row::text)
+            .unwrap_or_default()
+    }
+
+    pub fn scrollback(&self) -> &VecDeque<Row> {
+        &self.scrollback
+    }
+
+    pub fn set_scrollback_limit(&mut self, limit: usize) {
+        self.scrollback_limit = limit;
+        self.drop_scrollback(self.scrollback.len().saturating_sub(limit));
+    }
+
+    /// The absolute line number of the oldest line still in the scrollback.
+    pub fn first_line(&self) -> usize {
+        self.lines_dropped
+    }
+
+    pub fn display_offset(&self) -> usize {
+        self.display_offset
+    }
+
+    /// The absolute line number of the first visible row.
+    pub fn top_line(&self) -> usize {
+        self.lines_dropped + self.scrollback.len() - self.display_offset
+    }
+
+    /// The absolute line number of a visible row, taking the display offset
+    /// into account.
+    pub fn visible_to_absolute(&self, row: usize) -> usize {
+        self.top_line() + row
+    }
+
+    /// The visible row an absolute line is shown on, or `None` if it's
+    /// scrolled out of view (or has been dropped from the scrollback).
+    pub fn absolute_to_visible(&self, line: usize) -> Option<usize> {
+        line.checked_sub(self.top_line())
+            .filter(|row| *row < self.size.rows)
+    }
+
+    /// Scroll the display as little as possible to bring an absolute line into view.
+    pub fn scroll_to_line(&mut self, line: usize) {
+        let top = self.top_line();
+        if line < top {
+            self.scroll_display((top - line) as isize);
+        } else if self.absolute_to_visible(line).is_none() {
+            self.scroll_display(-((line + 1 - top - self.size.rows) as isize));
+        }
+    }
+
+    /// Scroll the display up (positive) or down (negative) through the scrollback.
+    pub fn scroll_display(&mut self, lines: isize) {
+        let offset = self
+            .display_offset
+            .saturating_add_signed(lines)
+            .min(self.scrollback.len());
+        if offset != self.display_offset {
+            self.display_offset = offset;
+            self.damage_all();
+        }
+    }
+
+    /// Find every match of `pattern` in the scrollback and on the screen,
+    /// oldest first. Matches don't span lines.
+    ///
+    /// With `regex` the pattern is a regular expression, which needs the
+    /// `regex` feature.
+    pub fn history_search(&self, pattern: &str, regex: bool) -> Result<Vec<Selection>> {
+        if pattern.is_empty() {
+            return Ok(Vec::new());
+        }
+        let matcher = if regex {
+            #[cfg(feature = "regex")]
+            {
+                use anyhow::Context;
+                Matcher::Regex(regex::Regex::new(pattern).context("Invalid regex")?)
+            }
+            #[cfg(not(feature = "regex"))]
+            anyhow::bail!("Regex search needs the regex feature");
+        } else {
+            Matcher::Plain(pattern)
+        };
+
+        let mut matches = Vec::new();
+        let lines = self.scrollback.iter().chain(self.grid.rows());
+        for (line, row) in (self.lines_dropped..).zip(lines) {
+            let text = AccessibleLine::new(row);
+            for range in matcher.find(&text.text) {
+                // Empty matches don't select anything.
+                if range.is_empty() {
                     continue;
                 }
-                Err(e) => {
-                    return Err(anyhow::anyhow!("Error writing to fd: {:?}", e));
+                let start = text.offset_to_column(range.start);
+                let mut end = text.offset_to_column(range.end - 1);
+                // Include the right half of a wide character.
+                while row.cells().get(end + 1).is_some_and(Cell::is_spacer) {
+                    end += 1;
                 }
+                matches.push(Selection::new(
+                    AbsolutePoint::new(line, start),
+                    AbsolutePoint::new(line, end),
+                ));
+            }
+        }
+        Ok(matches)
+    }
+
+    pub fn prompt_marks(&self) -> impl Iterator<Item = &PromptMark> {
+        self.prompt_marks.iter()
+    }
+
+    pub fn selection(&self) -> Option<Selection> {
+        self.selection
+    }
+
+    pub fn set_selection(&mut self, selection: Option<Selection>) {
+        self.selection = selection;
+        self.damage_all();
+    }
+
+    /// The selected text, for copying. Trailing blanks are trimmed from each
+    /// line, and lines that wrapped are joined without a newline.
+    pub fn selection_text(&self) -> Option<String> {
+        let Selection { start, end } = self.selection?;
+        let mut text = String::new();
+        for line in start.line..=end.line {
+            let Some(row) = self.row_at_line(line) else {
+                continue;
+            };
+            let from = if line == start.line { start.col } else { 0 };
+            let to = if line == end.line {
+                end.col + 1
+            } else {
+                row.cols()
             };
+            let part = row.text_in(from..to);
+            if line == end.line {
+                text.push_str(part.trim_end());
+            } else if row.wrapped() {
+                text.push_str(&part);
+            } else {
+                text.push_str(part.trim_end());
+                text.push('\n');
+            }
         }
-        Ok(())
+        Some(text)
     }
 
-    pub fn read(&mut self) -> anyhow::Result<()> {
-        let mut buf = vec![0u8; 4096];
-        match nix::unistd::read(self.fd.as_raw_fd(), &mut buf) {
-            Ok(n_bytes) => {
-                let bytes = &buf[..n_bytes];
-                let segments = self.parser.parse(bytes);
-                for segment in segments {
-                    match segment {
-                        TerminalOutput::Ansi(_seq) => {
-                            // panic!("not implemented");
-                        }
-                        TerminalOutput::Text(text) => {
-                            self.cursor.update(&text);
-                            println!("updated cursor to {}, {}", self.cursor.x, self.cursor.y);
-                            self.buffer.extend_from_slice(&text);
-                        }
-                        TerminalOutput::SetCursorPos { x, y } => {
-                            self.cursor.x = x - 1;
-                            self.cursor.y = y - 1;
-                            println!("need to set cursor to x: {}, y: {}", x, y);
-                        }
-                        TerminalOutput::ClearForwards => {
-                            let pos = self.cursor.to_buffer_pos(&self.buffer);
-                            self.buffer.drain(pos..);
-                        }
-                        TerminalOutput::ClearBackwards => {
-                            let pos = self.cursor.to_buffer_pos(&self.buffer);
-                            self.buffer.drain(..pos);
-                        }
-                        TerminalOutput::ClearAll => {
-                            self.buffer.clear();
-                            self.cursor.x = 0;
-                            self.cursor.y = 0;
-                        }
-                        TerminalOutput::RestoreCursorPos => {
-                            if let Some(saved) = self.saved_cursor.take() {
-                                self.cursor = saved;
-                            }
-                        }
-                        TerminalOutput::SaveCursorPos => {
-                            self.saved_cursor = Some(self.cursor.clone());
-                        }
+    /// The visible rows as text, for screen readers. Use [`Terminal::take_damage`]
+    /// and [`Terminal::accessible_line`] to keep them up to date.
+    pub fn accessible_lines(&self) -> Vec<AccessibleLine> {
+        self.visible_rows().map(AccessibleLine::new).collect()
+    }
+
+    pub fn accessible_line(&self, row: usize) -> AccessibleLine {
+        self.visible_rows()
+            .nth(row)
+            .map(AccessibleLine::new)
+            .unwrap_or_default()
+    }
+
+    /// The visible row and column of the cursor, if it's in view.
+    pub fn visible_cursor(&self) -> Option<(usize, usize)> {
+        let row = self.absolute_to_visible(self.absolute_line(self.cursor.y))?;
+        Some((row, self.cursor.x))
+    }
+
+    /// Take the damage accumulated since the last call.
+    pub fn take_damage(&mut self) -> Damage {
+        std::mem::take(&mut self.damage)
+    }
+
+    /// Drop all of the scrollback, leaving the screen as it is.
+    pub fn clear_scrollback(&mut self) {
+        self.drop_scrollback(self.scrollback.len());
+    }
+
+    /// Drop the scrollback from before a prompt mark, e.g. to clear a pane's
+    /// history but keep the output of the last command.
+    pub fn clear_scrollback_before(&mut self, mark: &PromptMark) {
+        let lines = mark.line.saturating_sub(self.lines_dropped);
+        self.drop_scrollback(lines.min(self.scrollback.len()));
+    }
+
+    /// Drop the oldest `lines` lines of the scrollback, along with anything
+    /// that refers to them.
+    fn drop_scrollback(&mut self, lines: usize) {
+        if lines == 0 {
+            return;
+        }
+        self.scrollback.drain(..lines);
+        self.lines_dropped += lines;
+
+        let first = self.lines_dropped;
+        while self
+            .prompt_marks
+            .front()
+            .is_some_and(|mark| mark.line < first)
+        {
+            self.prompt_marks.pop_front();
+        }
+        if let Some(selection) = &mut self.selection {
+            if selection.end.line < first {
+                self.selection = None;
+            } else if selection.start.line < first {
+                selection.start = AbsolutePoint::new(first, 0);
+            }
+        }
+        if self.display_offset > 0 {
+            // The lines in view have either moved or are gone.
+            self.display_offset = self.display_offset.min(self.scrollback.len());
+            self.damage_all();
+        }
+    }
+
+    fn push_scrollback(&mut self, row: Row) {
+        self.scrollback.push_back(row);
+        if self.display_offset > 0 {
+            // Keep the view on the same lines while output scrolls underneath.
+            self.display_offset += 1;
+        }
+        let excess = self.scrollback.len().saturating_sub(self.scrollback_limit);
+        self.drop_scrollback(excess);
+    }
+
+    /// The absolute line number of a row on the screen.
+    fn absolute_line(&self, row: usize) -> usize {
+        self.lines_dropped + self.scrollback.len() + row
+    }
+
+    /// The row on an absolute line, from either the scrollback or the screen.
+    fn row_at_line(&self, line: usize) -> Option<&Row> {
+        let i = line.checked_sub(self.lines_dropped)?;
+        match i.checked_sub(self.scrollback.len()) {
+            Some(i) => self.grid.rows().get(i),
+            None => self.scrollback.get(i),
+        }
+    }
+
+    fn damage_row(&mut self, row: usize) {
+        // Damage is tracked for visible rows, which is the same thing unless
+        // the display is scrolled back.
+        let row = row + self.display_offset;
+        if row < self.size.rows {
+            self.damage.rows.insert(row);
+        }
+    }
+
+    fn damage_all(&mut self) {
+        self.damage.full = true;
+        self.damage.rows.clear();
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    /// The title, size, cursor, and text of the screen as JSON, for checking
+    /// the result of a headless run.
+    pub fn snapshot_json(&self) -> String {
+        let lines = self
+            .grid
+            .rows()
+            .iter()
+            .map(|row| format!("    {}", json_string(&row.text())))
+            .collect::<Vec<_>>()
+            .join(",\n");
+        format!(
+            "{{\n  \"title\": {},\n  \"size\": {{ \"rows\": {}, \"cols\": {} }},\n  \
+             \"cursor\": {{ \"row\": {}, \"col\": {} }},\n  \"lines\": [\n{}\n  ]\n}}\n",
+            json_string(&self.title),
+            self.size.rows,
+            self.size.cols,
+            self.cursor.y,
+            self.cursor.x,
+            lines
+        )
+    }
+
+    /// The rows of the scrollback and the screen to export, oldest first,
+    /// without the blank ones at the bottom of the screen.
+    fn export_rows(&self) -> Vec<&Row> {
+        let mut rows: Vec<&Row> = self.scrollback.iter().chain(self.grid.rows()).collect();
+        let end = rows
+            .iter()
+            .rposition(|row| !row.flags().is_empty() || !row.text().is_empty())
+            .map_or(0, |i| i + 1);
+        rows.truncate(end);
+        rows
+    }
+
+    /// The text of the scrollback and the screen. Wrapped rows are joined to
+    /// the next one, so lines come out as the application wrote them.
+    pub fn contents(&self) -> String {
+        let mut out = String::new();
+        let rows = self.export_rows();
+        for (i, row) in rows.iter().enumerate() {
+            if row.wrapped() {
+                out.extend(row.chars().map(|(c, _)| c));
+            } else {
+                out.push_str(&row.text());
+                if i + 1 < rows.len() {
+                    out.push('\n');
+                }
+            }
+        }
+        out
+    }
+
+    /// Like [`Terminal::contents`], but with the escape sequences to draw it
+    /// again: SGR for the styles, `ESC #` for the line sizes, and OSC 133 for
+    /// prompts. Writing it to a terminal of the same width brings back the
+    /// same rows with the same [`LineFlags`].
+    pub fn contents_ansi(&self) -> String {
+        let mut out = String::new();
+        let rows = self.export_rows();
+        let mut pen = Style::default();
+        for (i, row) in rows.iter().enumerate() {
+            let flags = row.flags();
+            if flags.contains(LineFlags::PROMPT) {
+                out.push_str("\x1b]133;A\x07");
+            }
+            match row.size() {
+                LineSize::Normal => {}
+                LineSize::DoubleWidth => out.push_str("\x1b#6"),
+                LineSize::DoubleHeightTop => out.push_str("\x1b#3"),
+                LineSize::DoubleHeightBottom => out.push_str("\x1b#4"),
+            }
+            for (c, style) in export_chars(row) {
+                if *style != pen {
+                    pen = *style;
+                    out.push_str(&format!("\x1b[{}m", pen.sgr()));
+                }
+                out.push(c);
+            }
+            if row.wrapped() {
+                continue;
+            }
+            if pen != Style::default() {
+                pen = Style::default();
+                out.push_str("\x1b[0m");
+            }
+            if i + 1 < rows.len() {
+                out.push_str("\r\n");
+            }
+        }
+        out
+    }
+
+    /// Like [`Terminal::contents`], but as an HTML `<pre>` block with the
+    /// colors from the current palette. Line sizes and prompts have no HTML
+    /// equivalent, so they're noted in comments at the start of the line.
+    pub fn contents_html(&self) -> String {
+        let mut out = String::from("<pre>\n");
+        let rows = self.export_rows();
+        for (i, row) in rows.iter().enumerate() {
+            let flags = row.flags();
+            if flags.contains(LineFlags::PROMPT) {
+                out.push_str("<!-- prompt -->");
+            }
+            match row.size() {
+                LineSize::Normal => {}
+                LineSize::DoubleWidth => out.push_str("<!-- double-width -->"),
+                LineSize::DoubleHeightTop => out.push_str("<!-- double-height-top -->"),
+                LineSize::DoubleHeightBottom => out.push_str("<!-- double-height-bottom -->"),
+            }
+            let chars: Vec<_> = export_chars(row).collect();
+            for run in chars.chunk_by(|a, b| a.1 == b.1) {
+                let css = html_style(run[0].1, &self.palette);
+                if !css.is_empty() {
+                    out.push_str(&format!("<span style=\"{}\">", css));
+                }
+                for (c, _) in run {
+                    match c {
+                        '&' => out.push_str("&amp;"),
+                        '<' => out.push_str("&lt;"),
+                        '>' => out.push_str("&gt;"),
+                        c => out.push(*c),
                     }
                 }
-                Ok(())
+                if !css.is_empty() {
+                    out.push_str("</span>");
+                }
+            }
+            if !row.wrapped() && i + 1 < rows.len() {
+                out.push('\n');
             }
-            Err(Errno::EAGAIN) => Ok(()),
-            Err(e) => Err(anyhow::anyhow!("Error reading from fd: {:?}", e)),
         }
+        out.push_str("\n</pre>\n");
+        out
+    }
+
+    pub fn modes(&self) -> &Modes {
+        &self.modes
+    }
+
+    pub fn mode_summary(&self) -> ModeSummary {
+        self.modes.summary()
     }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn stats(&self) -> TerminalStats {
+        self.stats
+    }
+
+    /// A rough estimate of the heap memory held by the screen and scrollback.
+    pub fn memory_usage(&self) -> usize {
+        let size = self.grid.size();
+        let history = self
+            .scrollback
+            .iter()
+            .map(|row| row.cells().len())
+            .sum::<usize>();
+        (size.rows * size.cols + history) * std::mem::size_of::<Cell>()
+            + self.scrollback.capacity() * std::mem::size_of::<Row>()
+    }
+
+    pub fn pen(&self) -> &Style {
+        &self.pen
+    }
+
+    /// Set the colors to use, and to go back to when the application resets
+    /// them.
+    pub fn set_theme(&mut self, theme: Palette) {
+        self.palette = theme.clone();
+        self.theme = theme;
+        self.generation += 1;
+        self.damage_all();
+    }
+
+    /// The colors used for everything but RGB colors.
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    pub fn cursor_pos(&self) -> &CursorPos {
+        &self.cursor
+    }
+
+    /// Report a mouse event to the application, if it asked for mouse events.
+    /// Returns whether it did, otherwise the embedder can use the mouse itself,
+    /// e.g. to select text.
+    pub fn mouse_input(&mut self, event: MouseEvent) -> Result<bool> {
+        if let Some(bytes) = self.mouse.encode(event, &self.modes) {
+            self.write(&bytes)?;
+        }
+        Ok(self.modes.mouse != MouseProtocol::Off)
+    }
+
+    /// The arrow keys that would move the shell's cursor to a visible cell, so
+    /// that clicking in the command being typed can move the cursor there.
+    ///
+    /// Returns `None` unless the cursor is in the command after the last
+    /// prompt (between OSC 133 B and C), the click is in the same command, and
+    /// the application hasn't asked for the mouse. The command can wrap onto
+    /// several rows, and a wide character is a single key press. Clicks on the
+    /// prompt go to the start of the command, and clicks past the end of it go
+    /// to the end.
+    pub fn arrows_to_move_cursor_to(&self, col: usize, row: usize) -> Option<Vec<u8>> {
+        if self.modes.mouse != MouseProtocol::Off || self.modes.alternate_screen {
+            return None;
+        }
+        let mark = self
+            .prompt_marks
+            .back()
+            .filter(|mark| mark.kind == PromptMarkKind::CommandStart)?;
+        let mut last = mark.line;
+        while self.row_at_line(last)?.wrapped() && self.row_at_line(last + 1).is_some() {
+            last += 1;
+        }
+        let lines = mark.line..=last;
+        let cursor_line = self.absolute_line(self.cursor.y);
+        let line = self.visible_to_absolute(row);
+        if !lines.contains(&cursor_line) || !lines.contains(&line) {
+            return None;
+        }
+
+        // The cells of the command's rows, one after another.
+        let mut cells = Vec::new();
+        let mut starts = Vec::new();
+        for line in lines {
+            let row = self.row_at_line(line)?;
+            starts.push(cells.len());
+            cells.extend_from_slice(&row.cells()[..row.cols()]);
+        }
+        let index = |line: usize, col: usize| {
+            let i = line - mark.line;
+            let end = starts.get(i + 1).copied().unwrap_or(cells.len());
+            (starts[i] + col).min(end - 1)
+        };
+        let start = index(mark.line, mark.col);
+        let cursor = index(cursor_line, self.cursor.x);
+        let end = cells
+            .iter()
+            .rposition(|cell| !cell.is_spacer() && cell.char() != ' ')
+            .map_or(0, |i| i + 1)
+            .max(cursor);
+        let mut target = index(line, col).clamp(start, end);
+        if cells.get(target).is_some_and(Cell::is_spacer) {
+            if target > 0 && char_width(cells[target - 1].char()) > 1 {
+                // The right half of a wide character.
+                target -= 1;
+            } else {
+                // The gap left at the end of a row by a wide character that
+                // didn't fit, which belongs to the character after it.
+                while target < end && cells[target].is_spacer() {
+                    target += 1;
+                }
+            }
+        }
+
+        let (arrow, range) = if target > cursor {
+            (Arrow::Right, cursor..target)
+        } else {
+            (Arrow::Left, target..cursor)
+        };
+        let presses = cells[range].iter().filter(|cell| !cell.is_spacer()).count();
+        Some(encode_arrows(arrow, presses, &self.modes))
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) -> anyhow::Result<()> {
+        self.write_from(WriteOrigin::Input, bytes)
+    }
+
+    /// Like [`Terminal::write`], but tagged with where the bytes came from for
+    /// the tap set with [`Terminal::set_outgoing_tap`].
+    pub fn write_from(&mut self, origin: WriteOrigin, bytes: &[u8]) -> anyhow::Result<()> {
+        let mut bytes = bytes;
+        while !bytes.is_empty() {
+            match nix::unistd::write(self.fd.as_raw_fd(), bytes) {
+                Ok(written) => {
+                    if let Some(tap) = &mut self.outgoing_tap {
+                        tap(origin, &bytes[..written]);
+                    }
+                    bytes = &bytes[written..];
+                }
+                Err(Errno::EAGAIN) => {
+                    continue;
+                }
+                Err(e) => {
+                    return Err(anyhow::anyhow!("Error writing to fd: {:?}", e));
+                }
+            };
+        }
+        Ok(())
+    }
+
+    /// Write several buffers with one system call where possible, as if they
+    /// had been concatenated and passed to [`Terminal::write`].
+    pub fn write_vectored(&mut self, slices: &[IoSlice]) -> anyhow::Result<()> {
+        let mut slices = slices.to_vec();
+        let mut slices = slices.as_mut_slice();
+        while !slices.is_empty() {
+            match nix::sys::uio::writev(&self.fd, slices) {
+                Ok(written) => {
+                    if let Some(tap) = &mut self.outgoing_tap {
+                        let mut left = written;
+                        for slice in slices.iter() {
+                            if left == 0 {
+                                break;
+                            }
+                            let n = left.min(slice.len());
+                            tap(WriteOrigin::Input, &slice[..n]);
+                            left -= n;
+                        }
+                    }
+                    // Only part of the data may have been taken, leaving a
+                    // slice partly written, so pick up from the byte after.
+                    IoSlice::advance_slices(&mut slices, written);
+                }
+                Err(Errno::EAGAIN) => {
+                    continue;
+                }
+                Err(e) => {
+                    return Err(anyhow::anyhow!("Error writing to fd: {:?}", e));
+                }
+            };
+        }
+        Ok(())
+    }
+
+    pub fn read(&mut self) -> anyhow::Result<()> {
+        let mut buf = vec![0u8; 4096];
+        match nix::unistd::read(self.fd.as_raw_fd(), &mut buf) {
+            Ok(n_bytes) => {
+                self.process(&buf[..n_bytes]);
+                self.apply_pending();
+                self.flush_responses()
+            }
+            Err(Errno::EAGAIN) => {
+                // A program that stopped halfway through something won't send
+                // anything else, so the deadlines need checking regardless.
+                self.apply_pending();
+                Ok(())
+            }
+            Err(e) => Err(anyhow::anyhow!("Error reading from fd: {:?}", e)),
+        }
+    }
+
+    /// Process `bytes` as if the application had written them, without
+    /// touching the pty. Replies to queries stay queued until
+    /// [`Terminal::flush_responses`].
+    pub fn inject_bytes(&mut self, bytes: &[u8]) {
+        self.process(bytes);
+        self.apply_pending();
+    }
+
+    /// The cursor position report (`CSI row ; col R`) that answers DSR 6,
+    /// without sending it anywhere.
+    pub fn cursor_position_report(&self) -> Vec<u8> {
+        let cursor = self.cursor.to_screen_point(self.size);
+        format!("\x1b[{};{}R", cursor.row() + 1, cursor.col() + 1).into_bytes()
+    }
+
+    /// Call `tap` with everything written to the application, as it's
+    /// written, for auditing what the embedder and the terminal send.
+    pub fn set_outgoing_tap(&mut self, tap: impl FnMut(WriteOrigin, &[u8]) + Send + 'a) {
+        self.outgoing_tap = Some(Box::new(tap));
+    }
+
+    /// Keep a description of everything the application sends, see
+    /// [`TerminalOutput::describe`].
+    pub fn set_inspect(&mut self, enable: bool) {
+        if !enable {
+            self.inspector = None;
+        } else if self.inspector.is_none() {
+            self.inspector = Some(VecDeque::new());
+        }
+    }
+
+    /// Copy what the terminal is showing, to start another terminal from the
+    /// same screen with [`FrozenTerminal::thaw`].
+    pub fn freeze(&self) -> FrozenTerminal {
+        // The new process starts on the primary screen.
+        let grid = if self.modes.alternate_screen {
+            &self.other_grid
+        } else {
+            &self.grid
+        };
+        FrozenTerminal {
+            grid: grid.clone(),
+            scrollback: self.scrollback.clone(),
+            scrollback_limit: self.scrollback_limit,
+            lines_dropped: self.lines_dropped,
+            prompt_marks: self.prompt_marks.clone(),
+            title: self.title.clone(),
+            cursor: self.cursor.clone(),
+            saved_cursor: self.saved_cursor.clone(),
+            pen: self.pen,
+            palette: self.palette.clone(),
+            theme: self.theme.clone(),
+            size: self.size,
+            inspect: self.inspector.is_some(),
+        }
+    }
+
+    /// The descriptions of the most recent outputs, oldest first.
+    pub fn inspected(&self) -> impl Iterator<Item = &str> {
+        self.inspector.iter().flatten().map(String::as_str)
+    }
+
+    /// Treat raw C1 control bytes such as 0x85 (NEL) as controls. This is
+    /// only correct for applications that don't send UTF-8.
+    pub fn set_eight_bit_controls(&mut self, enable: bool) {
+        self.parser.set_eight_bit_controls(enable);
+    }
+
+    /// Queue at most one [`TerminalEvent::TitleChanged`] per `interval`, for
+    /// applications that change the title on every prompt or key press. The
+    /// last title is always sent once the interval is up.
+    pub fn set_title_debounce(&mut self, interval: Option<Duration>) {
+        self.title_debounce = interval;
+    }
+
+    /// Queue a [`TerminalEvent::TitleChanged`] for a pending title change,
+    /// unless one was queued less than the debounce interval ago.
+    fn send_title(&mut self, now: Instant) {
+        if !self.title_pending || self.title_deadline().is_some_and(|deadline| now < deadline) {
+            return;
+        }
+        self.title_pending = false;
+        self.title_sent = Some(now);
+        self.events
+            .push_back(TerminalEvent::TitleChanged(self.title.clone()));
+    }
+
+    /// When a pending title change can be sent, if it's being held back.
+    fn title_deadline(&self) -> Option<Instant> {
+        let sent = self.title_sent.filter(|_| self.title_pending)?;
+        Some(sent + self.title_debounce?)
+    }
+
+    pub fn set_clock(&mut self, clock: impl Clock + 'static) {
+        self.clock = Box::new(clock);
+    }
+
+    pub fn set_timeout(&mut self, protocol: Protocol, timeout: Duration) {
+        self.timeouts.insert(protocol, timeout);
+    }
+
+    pub fn timeout(&self, protocol: Protocol) -> Duration {
+        self.timeouts
+            .get(&protocol)
+            .copied()
+            .unwrap_or(protocol.default_timeout())
+    }
+
+    /// The earliest deadline of any open protocol or debounced title change.
+    /// The embedder should make sure [`Terminal::read`] or
+    /// [`Terminal::apply_pending`] is called by then, even if there is no output.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.deadlines
+            .values()
+            .map(|(_, deadline)| *deadline)
+            .chain(self.title_deadline())
+            .min()
+    }
+
+    /// Start the deadlines of protocols that have been opened, and force close
+    /// the ones that have been open for too long, queuing a
+    /// [`TerminalEvent::TimedOut`] for each. Also sends a debounced title
+    /// change once it's due. Called by [`Terminal::read`].
+    pub fn apply_pending(&mut self) {
+        let now = self.clock.now();
+        self.send_title(now);
+        for protocol in Protocol::ALL {
+            let open = match protocol {
+                Protocol::SynchronizedUpdate => {
+                    self.modes.synchronized_update.then_some(self.sync_updates)
+                }
+                Protocol::Dcs | Protocol::Apc => {
+                    let kind = if protocol == Protocol::Dcs {
+                        StringKind::Dcs
+                    } else {
+                        StringKind::Apc
+                    };
+                    self.parser
+                        .open_string()
+                        .filter(|(open, _)| *open == kind)
+                        .map(|(_, id)| id)
+                }
+            };
+            let Some(id) = open else {
+                self.deadlines.remove(&protocol);
+                continue;
+            };
+            let deadline = now + self.timeout(protocol);
+            let (_, deadline) = *self
+                .deadlines
+                .entry(protocol)
+                .and_modify(|entry| {
+                    if entry.0 != id {
+                        *entry = (id, deadline);
+                    }
+                })
+                .or_insert((id, deadline));
+            if now >= deadline {
+                self.deadlines.remove(&protocol);
+                match protocol {
+                    Protocol::SynchronizedUpdate => {
+                        self.modes.synchronized_update = false;
+                        self.release_held();
+                    }
+                    Protocol::Dcs | Protocol::Apc => self.parser.abort_string(),
+                }
+                self.events.push_back(TerminalEvent::TimedOut(protocol));
+            }
+        }
+    }
+
+    pub fn set_clipboard_policy(&mut self, policy: ClipboardPolicy) {
+        self.clipboard_policy = policy;
+    }
+
+    /// Drain the events that have happened since the last call.
+    pub fn events(&mut self) -> impl Iterator<Item = TerminalEvent> + '_ {
+        self.events.drain(..)
+    }
+
+    /// Send the contents of the clipboard in reply to a query that was passed
+    /// on to the embedder. To deny a deferred query, just don't answer it.
+    pub fn answer_clipboard_query(
+        &mut self,
+        query: &ClipboardQuery,
+        contents: &str,
+    ) -> anyhow::Result<()> {
+        self.queue_clipboard_response(query, contents);
+        self.flush_responses()
+    }
+
+    fn queue_clipboard_response(&mut self, query: &ClipboardQuery, contents: &str) {
+        let response = format!(
+            "\x1b]52;{};{}\x07",
+            query.selection,
+            base64_encode(contents.as_bytes())
+        );
+        self.responses.extend_from_slice(response.as_bytes());
+    }
+
+    /// Reply to OSC 4 or 10 to 12 with `?`. Default colors that are left up
+    /// to the embedder can't be reported, so those queries aren't answered.
+    fn queue_color_response(&mut self, slot: ColorSlot) {
+        let Some((r, g, b)) = self.palette.slot(slot) else {
+            return;
+        };
+        let code = match slot {
+            ColorSlot::Indexed(index) => format!("4;{}", index),
+            ColorSlot::Foreground => "10".to_owned(),
+            ColorSlot::Background => "11".to_owned(),
+            ColorSlot::Cursor => "12".to_owned(),
+        };
+        // Like xterm, with 16 bits per component.
+        let response = format!(
+            "\x1b]{};rgb:{:02x}{:02x}/{:02x}{:02x}/{:02x}{:02x}\x07",
+            code, r, r, g, g, b, b
+        );
+        self.responses.extend_from_slice(response.as_bytes());
+    }
+
+    /// The in-band resize notification, `CSI 48 ; rows ; cols ; height ; width t`.
+    fn queue_resize_report(&mut self) {
+        let (height, width) = self.pixel_size;
+        let response = format!(
+            "\x1b[48;{};{};{};{}t",
+            self.size.rows, self.size.cols, height, width
+        );
+        self.responses.extend_from_slice(response.as_bytes());
+    }
+
+    /// Write any queued responses to the application.
+    pub fn flush_responses(&mut self) -> anyhow::Result<()> {
+        if self.responses.is_empty() {
+            return Ok(());
+        }
+        let responses = std::mem::take(&mut self.responses);
+        self.write_from(WriteOrigin::Response, &responses)
+    }
+
+    fn process(&mut self, bytes: &[u8]) {
+        // The segments borrow from the parser, so it needs to be moved out of
+        // self while they are applied.
+        let mut parser = std::mem::take(&mut self.parser);
+        let modes = self.mode_summary();
+        self.stats.bytes_read += bytes.len() as u64;
+        for segment in parser.feed(bytes) {
+            self.stats.outputs_parsed += 1;
+            if let Some(inspector) = &mut self.inspector {
+                if inspector.len() == Self::INSPECTOR_LIMIT {
+                    inspector.pop_front();
+                }
+                inspector.push_back(segment.describe());
+            }
+            // Mode 2026 can only be ended by changing (or restoring) it.
+            let touches_sync = match &segment {
+                TerminalOutput::SetMode {
+                    private: true,
+                    params,
+                    ..
+                }
+                | TerminalOutput::RestoreModes(params) => params.contains(&2026),
+                _ => false,
+            };
+            // Queries are answered right away, only screen changes are held.
+            if self.modes.synchronized_update && !segment.is_query() {
+                if !touches_sync {
+                    self.held.push(segment.into_owned());
+                    continue;
+                }
+                self.release_held();
+            }
+            let synced = self.modes.synchronized_update;
+            self._apply_segment(segment);
+            if self.modes.synchronized_update && !synced {
+                self.sync_updates += 1;
+            }
+        }
+        self.parser = parser;
+        // Only the state at the end of the read matters, so a mode that is
+        // turned on and off again in one read doesn't count as a change.
+        if self.mode_summary() != modes {
+            self.events
+                .push_back(TerminalEvent::ModesChanged(self.mode_summary()));
+        }
+    }
+
+    /// Apply the outputs that were held back during a synchronized update.
+    fn release_held(&mut self) {
+        for output in std::mem::take(&mut self.held) {
+            self._apply_segment(output);
+        }
+    }
+
+    fn _apply_segment(&mut self, segment: TerminalOutput) {
+        if !matches!(
+            segment,
+            TerminalOutput::Ansi(_)
+                | TerminalOutput::Sgr(_)
+                | TerminalOutput::SetTitle(_)
+                | TerminalOutput::ClipboardQuery { .. }
+                | TerminalOutput::Colors(_)
+        ) {
+            self.generation += 1;
+        }
+        // A sequence can only carry on in the next piece of text, anything
+        // else in between means it was cut short.
+        if !self.utf8_pending.is_empty() && !matches!(segment, TerminalOutput::Text(_)) {
+            self.utf8_pending.clear();
+            self.print(char::REPLACEMENT_CHARACTER);
+        }
+        match segment {
+            TerminalOutput::Ansi(_seq) => {
+                // panic!("not implemented");
+            }
+            TerminalOutput::Text(text) => {
+                let mut pending = std::mem::take(&mut self.utf8_pending);
+                decode_utf8(&mut pending, &text, |c| self.print(c));
+                self.utf8_pending = pending;
+            }
+            TerminalOutput::SetCursorPos(pos) => {
+                self.set_cursor(pos.to_screen(self.size));
+            }
+            TerminalOutput::MoveCursorUp { n } => self.move_cursor(0, -(n as isize)),
+            TerminalOutput::MoveCursorDown { n } => self.move_cursor(0, n as isize),
+            TerminalOutput::MoveCursorForward { n } => self.move_cursor(n as isize, 0),
+            TerminalOutput::MoveCursorBack { n } => self.move_cursor(-(n as isize), 0),
+            TerminalOutput::MoveCursorNextLine { n } => {
+                self.cursor.x = 0;
+                self.move_cursor(0, n as isize);
+            }
+            TerminalOutput::MoveCursorPrevLine { n } => {
+                self.cursor.x = 0;
+                self.move_cursor(0, -(n as isize));
+            }
+            TerminalOutput::MoveCursorToColumn { n } => {
+                let cursor = self.cursor.to_screen_point(self.size);
+                self.set_cursor(ScreenPoint::clamped(n - 1, cursor.row(), self.size));
+            }
+            TerminalOutput::MoveCursorToRow { n } => {
+                let cursor = self.cursor.to_screen_point(self.size);
+                self.set_cursor(ScreenPoint::clamped(cursor.col(), n - 1, self.size));
+            }
+            TerminalOutput::SetScrollRegion { top, bottom } => {
+                let bottom = bottom.map_or(self.size.rows, |bottom| bottom.min(self.size.rows));
+                // Like xterm, a region of less than two lines is ignored.
+                if top < bottom {
+                    self.scroll_region = if top == 1 && bottom == self.size.rows {
+                        None
+                    } else {
+                        Some(top - 1..bottom)
+                    };
+                    self.set_cursor(ScreenPoint::clamped(0, 0, self.size));
+                }
+            }
+            TerminalOutput::ScrollUp { n } => self.scroll_up(n),
+            TerminalOutput::ScrollDown { n } => self.scroll_down(n),
+            TerminalOutput::InsertChars { n } | TerminalOutput::DeleteChars { n } => {
+                let cursor = self.cursor.to_screen_point(self.size);
+                let row = self.grid.row_mut(cursor.row());
+                let cols = row.cols();
+                // Characters shifted past the right edge are lost, and blanks
+                // fill the gap that's left.
+                let cells = &mut row.cells_mut()[cursor.col().min(cols)..cols];
+                let n = n.min(cells.len());
+                if matches!(segment, TerminalOutput::InsertChars { .. }) {
+                    cells.rotate_right(n);
+                    cells[..n].fill(Cell::default());
+                } else {
+                    cells.rotate_left(n);
+                    let len = cells.len();
+                    cells[len - n..].fill(Cell::default());
+                }
+                self.damage_row(cursor.row());
+            }
+            TerminalOutput::EraseChars(n) => {
+                let cursor = self.cursor.to_screen_point(self.size);
+                let blank = self.blank();
+                let row = self.grid.row_mut(cursor.row());
+                let end = cursor.col().saturating_add(n).min(row.cols());
+                row.cells_mut()[cursor.col().min(end)..end].fill(blank);
+                self.damage_row(cursor.row());
+            }
+            TerminalOutput::RepeatChar(n) => {
+                if let Some((c, style)) = self.last_printed {
+                    let pen = std::mem::replace(&mut self.pen, style);
+                    // More than a screenful would only overwrite itself.
+                    for _ in 0..n.min(self.size.rows * self.size.cols) {
+                        self.print(c);
+                    }
+                    self.pen = pen;
+                }
+            }
+            TerminalOutput::InsertLines { n } | TerminalOutput::DeleteLines { n } => {
+                let region = self.scroll_region();
+                // Outside the scroll region these do nothing.
+                if region.contains(&self.cursor.y) {
+                    let region = self.cursor.y..region.end;
+                    let blank = self.blank();
+                    for _ in 0..n.min(region.len()) {
+                        if matches!(segment, TerminalOutput::InsertLines { .. }) {
+                            self.grid.scroll_region_down(region.clone(), blank);
+                        } else {
+                            self.grid.scroll_region_up(region.clone(), blank);
+                        }
+                    }
+                    self.cursor.x = 0;
+                    for row in region {
+                        self.damage_row(row);
+                    }
+                }
+            }
+            TerminalOutput::SetTabStop => {
+                let cursor = self.cursor.to_screen_point(self.size);
+                self.tab_stops[cursor.col()] = true;
+            }
+            TerminalOutput::ClearTabStop { all: false } => {
+                let cursor = self.cursor.to_screen_point(self.size);
+                self.tab_stops[cursor.col()] = false;
+            }
+            TerminalOutput::ClearTabStop { all: true } => self.tab_stops.fill(false),
+            TerminalOutput::TabForward(n) => {
+                for _ in 0..n.min(self.size.cols) {
+                    self.cursor.x = self.next_tab_stop();
+                }
+            }
+            TerminalOutput::TabBackward(n) => {
+                for _ in 0..n.min(self.size.cols) {
+                    self.cursor.x = self.prev_tab_stop();
+                }
+            }
+            TerminalOutput::Sgr(attributes) => {
+                for attribute in attributes {
+                    self.pen.apply(attribute);
+                }
+            }
+            TerminalOutput::NextLine => self.next_line(),
+            TerminalOutput::ReverseIndex => self.reverse_index(),
+            TerminalOutput::CarriageReturn => self.cursor.x = 0,
+            TerminalOutput::LineFeed => self.linefeed(),
+            TerminalOutput::Tab => self.cursor.x = self.next_tab_stop(),
+            TerminalOutput::Bell => self.events.push_back(TerminalEvent::Bell),
+            TerminalOutput::ClearForwards => {
+                let cursor = self.cursor.to_screen_point(self.size);
+                let blank = self.blank();
+                let row = self.grid.row_mut(cursor.row());
+                row.cells_mut()[cursor.col()..].fill(blank);
+                for row in cursor.row() + 1..self.size.rows {
+                    self.grid.row_mut(row).clear_with(blank);
+                }
+                for row in cursor.row()..self.size.rows {
+                    self.damage_row(row);
+                }
+            }
+            TerminalOutput::ClearBackwards => {
+                let cursor = self.cursor.to_screen_point(self.size);
+                let blank = self.blank();
+                for row in 0..cursor.row() {
+                    self.grid.row_mut(row).clear_with(blank);
+                }
+                let row = self.grid.row_mut(cursor.row());
+                row.cells_mut()[..=cursor.col()].fill(blank);
+                for row in 0..=cursor.row() {
+                    self.damage_row(row);
+                }
+            }
+            TerminalOutput::ClearAll => {
+                self.grid.clear_with(self.blank());
+                self.damage_all();
+            }
+            TerminalOutput::ClearScrollback => {
+                self.clear_scrollback();
+            }
+            TerminalOutput::ClearLineForwards
+            | TerminalOutput::ClearLineBackwards
+            | TerminalOutput::ClearLineAll => {
+                let cursor = self.cursor.to_screen_point(self.size);
+                let blank = self.blank();
+                let row = self.grid.row_mut(cursor.row());
+                let cols = row.cols();
+                let range = match segment {
+                    TerminalOutput::ClearLineForwards => cursor.col().min(cols)..cols,
+                    TerminalOutput::ClearLineBackwards => 0..cursor.col() + 1,
+                    _ => 0..cols,
+                };
+                row.cells_mut()[range].fill(blank);
+                self.damage_row(cursor.row());
+            }
+            TerminalOutput::PromptMark(kind) => {
+                let line = self.absolute_line(self.cursor.y);
+                // Marks are almost always added in order, but the cursor could
+                // have been moved up since the last one.
+                let i = self.prompt_marks.partition_point(|mark| mark.line <= line);
+                let col = self.cursor.x;
+                self.prompt_marks.insert(i, PromptMark { kind, line, col });
+                if kind == PromptMarkKind::PromptStart {
+                    self.grid
+                        .row_mut(self.cursor.y)
+                        .set_flags(LineFlags::PROMPT, true);
+                }
+            }
+            TerminalOutput::SetLineSize(size) => {
+                let cursor = self.cursor.to_screen_point(self.size);
+                self.grid.row_mut(cursor.row()).set_size(size);
+                self.damage_row(cursor.row());
+                // The line may only have room for half as many characters now.
+                self.set_cursor(cursor);
+            }
+            TerminalOutput::AlignmentTest => {
+                // Every line goes back to normal size too.
+                self.grid.clear_with(Cell::new('E'));
+                self.scroll_region = None;
+                self.set_cursor(ScreenPoint::clamped(0, 0, self.size));
+                self.damage_all();
+            }
+            TerminalOutput::RestoreCursorPos => self.restore_cursor(),
+            TerminalOutput::SaveCursorPos => self.save_cursor(),
+            TerminalOutput::SetMode {
+                private,
+                params,
+                enable,
+            } => {
+                for mode in params {
+                    // None of the ANSI modes are supported.
+                    if private {
+                        self.set_private_mode(mode, enable);
+                    }
+                }
+            }
+            TerminalOutput::SaveModes(modes) => {
+                for mode in modes {
+                    let value = self.modes.get_private(mode);
+                    self.saved_modes.entry(mode).or_default().push(value);
+                }
+            }
+            TerminalOutput::RestoreModes(modes) => {
+                for mode in modes {
+                    if let Some(value) = self.saved_modes.get_mut(&mode).and_then(Vec::pop) {
+                        self.set_private_mode(mode, value);
+                    }
+                }
+            }
+            TerminalOutput::SetTitle(title) => {
+                if title != self.title {
+                    self.title = title;
+                    self.title_pending = true;
+                    self.send_title(self.clock.now());
+                }
+            }
+            TerminalOutput::RequestMode { private, mode } => {
+                // ANSI modes aren't tracked, so none of them are recognized.
+                let (marker, value) = if private {
+                    ("?", self.modes.report_private(mode))
+                } else {
+                    ("", 0)
+                };
+                let response = format!("\x1b[{}{};{}$y", marker, mode, value);
+                self.responses.extend_from_slice(response.as_bytes());
+            }
+            TerminalOutput::DeviceStatusReport(5) => {
+                self.responses.extend_from_slice(b"\x1b[0n");
+            }
+            TerminalOutput::DeviceStatusReport(6) => {
+                let response = self.cursor_position_report();
+                self.responses.extend_from_slice(&response);
+            }
+            TerminalOutput::DeviceStatusReport(_) => {}
+            TerminalOutput::DeviceAttributes { secondary } => {
+                self.responses.extend_from_slice(if secondary {
+                    SECONDARY_DEVICE_ATTRIBUTES
+                } else {
+                    PRIMARY_DEVICE_ATTRIBUTES
+                });
+            }
+            TerminalOutput::Colors(requests) => {
+                let mut changed = false;
+                for request in requests {
+                    match request {
+                        ColorRequest::Set(slot, rgb) => {
+                            self.palette.set_slot(slot, rgb);
+                            changed = true;
+                        }
+                        ColorRequest::Query(slot) => self.queue_color_response(slot),
+                        ColorRequest::Reset(slot) => {
+                            self.palette.reset_slot(slot, &self.theme);
+                            changed = true;
+                        }
+                        ColorRequest::ResetPalette => {
+                            self.palette.reset_indexed(&self.theme);
+                            changed = true;
+                        }
+                    }
+                }
+                if changed {
+                    // Any cell could be using the color.
+                    self.generation += 1;
+                    self.damage_all();
+                }
+            }
+            TerminalOutput::ClipboardQuery { selection } => {
+                let query = ClipboardQuery { selection };
+                let answer = match &mut self.clipboard_policy {
+                    ClipboardPolicy::Deny => ClipboardAnswer::Deny,
+                    ClipboardPolicy::Allow => ClipboardAnswer::Defer,
+                    ClipboardPolicy::Ask(ask) => ask(&query),
+                };
+                match answer {
+                    ClipboardAnswer::Deny => {}
+                    ClipboardAnswer::Contents(contents) => {
+                        self.queue_clipboard_response(&query, &contents);
+                    }
+                    ClipboardAnswer::Defer => {
+                        self.events.push_back(TerminalEvent::ClipboardQuery(query));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Move the cursor, keeping it within the visible part of lines that are
+    /// double width.
+    fn set_cursor(&mut self, point: ScreenPoint) {
+        let cols = self.grid.row(point.row()).cols();
+        self.cursor.set(point);
+        self.cursor.x = self.cursor.x.min(cols - 1);
+    }
+
+    /// Move the cursor relative to where it is, stopping at the edges of the
+    /// screen.
+    fn move_cursor(&mut self, cols: isize, rows: isize) {
+        let cursor = self.cursor.to_screen_point(self.size);
+        self.set_cursor(ScreenPoint::clamped(
+            cursor.col().saturating_add_signed(cols),
+            cursor.row().saturating_add_signed(rows),
+            self.size,
+        ));
+    }
+
+    /// Handle a single character of text, including the control characters
+    /// that can appear in text segments.
+    fn print(&mut self, c: char) {
+        match c {
+            '\x08' => self.cursor.x = self.cursor.x.saturating_sub(1),
+            // NEL as a code point, which is how it arrives in UTF-8 text.
+            '\u{85}' => self.next_line(),
+            c if c.is_control() => {}
+            c if char_width(c) == 0 => {
+                // Combining marks go on the character before the cursor,
+                // without moving it.
+                let cells = self.grid.row_mut(self.cursor.y).cells_mut();
+                let before = cells[..self.cursor.x]
+                    .iter_mut()
+                    .rev()
+                    .find(|cell| !cell.is_spacer());
+                if let Some(cell) = before {
+                    cell.add_mark(c);
+                    self.damage_row(self.cursor.y);
+                }
+            }
+            c => {
+                let cols = self.grid.row(self.cursor.y).cols();
+                // Squeezed into one cell if the row is too narrow for it.
+                let width = char_width(c).min(cols);
+                if self.cursor.x + width > cols && !self.modes.auto_wrap {
+                    // The character replaces whatever is at the right margin.
+                    self.cursor.x = cols - width;
+                } else if self.cursor.x + width > cols {
+                    // The cursor was left past the last column by the character
+                    // before, so the wrap happens now.
+                    let row = self.grid.row_mut(self.cursor.y);
+                    if let Some(gap) = row.cells_mut()[..cols].get_mut(self.cursor.x) {
+                        *gap = Cell::spacer(self.pen);
+                    }
+                    row.set_wrapped(true);
+                    self.cursor.x = 0;
+                    self.linefeed();
+                }
+                self.put_char(c, width);
+                self.damage_row(self.cursor.y);
+                self.cursor.x += width;
+                self.last_printed = Some((c, self.pen));
+            }
+        }
+    }
+
+    /// Write a character at the cursor, with a spacer after it if it's wide.
+    /// Overwriting half of a wide character blanks the other half.
+    fn put_char(&mut self, c: char, width: usize) {
+        let x = self.cursor.x;
+        let cells = self.grid.row_mut(self.cursor.y).cells_mut();
+        if x > 0 && cells[x].is_spacer() && char_width(cells[x - 1].char()) > 1 {
+            cells[x - 1] = Cell::styled(' ', *cells[x - 1].style());
+        }
+        if cells.get(x + width).is_some_and(Cell::is_spacer) {
+            cells[x + width] = Cell::styled(' ', *cells[x + width].style());
+        }
+        cells[x] = Cell::styled(c, self.pen);
+        for cell in &mut cells[x + 1..x + width] {
+            *cell = Cell::spacer(self.pen);
+        }
+    }
+
+    fn next_line(&mut self) {
+        self.cursor.x = 0;
+        self.linefeed();
+    }
+
+    /// Set a DEC private mode, along with whatever the terminal has to do
+    /// when it changes.
+    fn set_private_mode(&mut self, mode: usize, enable: bool) {
+        match mode {
+            // Applications get the current size as soon as they ask for reports.
+            2048 if enable && !self.modes.in_band_resize => self.queue_resize_report(),
+            47 | 1047 | 1049 if enable != self.modes.alternate_screen => {
+                self.switch_screen(mode, enable)
+            }
+            _ => {}
+        }
+        self.modes.set_private(mode, enable);
+    }
+
+    fn save_cursor(&mut self) {
+        self.saved_cursor = Some(SavedCursor {
+            cursor: self.cursor.clone(),
+            pen: self.pen,
+        });
+    }
+
+    /// Go back to the saved cursor, which stays saved so it can be restored
+    /// again. With nothing saved this homes the cursor and resets the pen.
+    fn restore_cursor(&mut self) {
+        match &self.saved_cursor {
+            Some(saved) => {
+                // The screen may have shrunk since the cursor was saved. A
+                // pending wrap is kept if the line is still as wide.
+                let y = saved.cursor.y.min(self.size.rows - 1);
+                let cols = self.grid.row(y).cols();
+                let x = if saved.cursor.x > cols {
+                    cols - 1
+                } else {
+                    saved.cursor.x
+                };
+                self.cursor = CursorPos::new(x, y);
+                self.pen = saved.pen;
+            }
+            None => {
+                self.cursor = CursorPos::new(0, 0);
+                self.pen = Style::default();
+            }
+        }
+    }
+
+    /// Swap the primary and alternate screens. Like xterm, 1049 also saves the
+    /// cursor on the way in and restores it on the way out, and 1047 and 1049
+    /// start the alternate screen blank.
+    fn switch_screen(&mut self, mode: usize, enable: bool) {
+        if enable && mode == 1049 {
+            self.save_cursor();
+        }
+        std::mem::swap(&mut self.grid, &mut self.other_grid);
+        if enable && mode != 47 {
+            self.grid.clear();
+        }
+        if !enable && mode == 1049 {
+            self.restore_cursor();
+        }
+        self.selection = None;
+        self.last_printed = None;
+        self.damage_all();
+    }
+
+    /// The column of the first tab stop after the cursor, or the last column
+    /// if there isn't one.
+    fn next_tab_stop(&self) -> usize {
+        let cols = self.grid.row(self.cursor.y).cols();
+        (self.cursor.x + 1..cols)
+            .find(|&col| self.tab_stops[col])
+            .unwrap_or(cols - 1)
+    }
+
+    /// The column of the first tab stop before the cursor, or the first column
+    /// if there isn't one.
+    fn prev_tab_stop(&self) -> usize {
+        let cols = self.grid.row(self.cursor.y).cols();
+        (1..self.cursor.x.min(cols))
+            .rev()
+            .find(|&col| self.tab_stops[col])
+            .unwrap_or(0)
+    }
+
+    /// The rows that scroll, see [`TerminalOutput::SetScrollRegion`].
+    fn scroll_region(&self) -> Range<usize> {
+        self.scroll_region.clone().unwrap_or(0..self.size.rows)
+    }
+
+    /// Move the cursor down a line, scrolling if it's at the bottom of the
+    /// scroll region. Below the region, it stops at the bottom of the screen.
+    fn linefeed(&mut self) {
+        if self.cursor.y + 1 == self.scroll_region().end {
+            self.scroll_up(1);
+        } else if self.cursor.y + 1 < self.size.rows {
+            self.cursor.y += 1;
+        }
+        self.clamp_cursor_to_row();
+    }
+
+    /// Move the cursor up a line, scrolling down if it's at the top of the
+    /// scroll region. Above the region, it stops at the top of the screen.
+    fn reverse_index(&mut self) {
+        if self.cursor.y == self.scroll_region().start {
+            self.scroll_down(1);
+        } else if self.cursor.y > 0 {
+            self.cursor.y -= 1;
+        }
+        self.clamp_cursor_to_row();
+    }
+
+    /// Keep the cursor within its row after a vertical move, since it may
+    /// have landed on a double width one with half as many columns.
+    fn clamp_cursor_to_row(&mut self) {
+        let cols = self.grid.row(self.cursor.y).cols();
+        self.cursor.x = self.cursor.x.min(cols - 1);
+    }
+
+    /// A blank cell for erasing with. It takes the pen's background color,
+    /// like xterm, so a program can fill the screen with a color by setting it
+    /// and clearing.
+    fn blank(&self) -> Cell {
+        Cell::styled(
+            ' ',
+            Style {
+                bg: self.pen.bg,
+                ..Style::default()
+            },
+        )
+    }
+
+    /// Scroll the lines in the scroll region up by `n`.
+    fn scroll_up(&mut self, n: usize) {
+        let region = self.scroll_region();
+        let blank = self.blank();
+        for _ in 0..n.min(region.len()) {
+            let row = self.grid.scroll_region_up(region.clone(), blank);
+            // Lines scrolled off the alternate screen are gone, so a full-screen
+            // application doesn't fill the scrollback with its redraws. So are
+            // lines scrolled out of a region that isn't the whole screen.
+            if !self.modes.alternate_screen && self.scroll_region.is_none() {
+                self.push_scrollback(row);
+            }
+        }
+        self.damage_all();
+    }
+
+    /// Scroll the lines in the scroll region down by `n`.
+    fn scroll_down(&mut self, n: usize) {
+        let region = self.scroll_region();
+        let blank = self.blank();
+        for _ in 0..n.min(region.len()) {
+            self.grid.scroll_region_down(region.clone(), blank);
+        }
+        self.damage_all();
+    }
+}
+
+/// Starts a process in a new pty, and a [`Terminal`] connected to it.
+#[derive(Clone)]
+pub struct TerminalBuilder {
+    command: String,
+    args: Vec<String>,
+    /// Variables to set for the process, on top of the ones it inherits.
+    env: Vec<(OsString, OsString)>,
+    size: Option<nix::pty::Winsize>,
+}
+
+impl Default for TerminalBuilder {
+    fn default() -> Self {
+        Self {
+            command: "sh".into(),
+            args: Vec::new(),
+            env: Vec::new(),
+            size: None,
+        }
+    }
+}
+
+impl TerminalBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The program to run, looked up in `PATH`. Defaults to `sh`.
+    pub fn command(mut self, command: &str) -> Self {
+        self.command = command.into();
+        self
+    }
+
+    /// Arguments for the program, after the ones already given.
+    pub fn args(mut self, args: &[&str]) -> Self {
+        self.args.extend(args.iter().map(|arg| arg.to_string()));
+        self
+    }
+
+    pub fn env(mut self, key: impl AsRef<OsStr>, value: impl AsRef<OsStr>) -> Self {
+        self.env
+            .push((key.as_ref().to_owned(), value.as_ref().to_owned()));
+        self
+    }
+
+    /// The size of the pty. Without one, the pty starts with no size at all.
+    pub fn size(mut self, size: nix::pty::Winsize) -> Self {
+        self.size = Some(size);
+        self
+    }
+
+    /// Start the process.
+    pub fn spawn<'a>(self) -> Result<Terminal<'a>> {
+        // Everything the child needs is prepared before forking, so all it
+        // does afterwards is exec.
+        let command = CString::new(self.command)?;
+        let args = std::iter::once(Ok(command.clone()))
+            .chain(self.args.into_iter().map(CString::new))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut env: HashMap<OsString, OsString> = std::env::vars_os().collect();
+        env.extend(self.env);
+        let env = env
+            .into_iter()
+            .map(|(key, value)| {
+                let mut var = key;
+                var.push("=");
+                var.push(value);
+                CString::new(var.into_vec())
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let failed = format!("failed to start {:?}\n", command);
+
+        let nix::pty::ForkptyResult {
+            master,
+            fork_result,
+        } = unsafe { nix::pty::forkpty(self.size.as_ref(), None)? };
+        match fork_result {
+            nix::unistd::ForkResult::Parent { child } => {
+                let mut terminal = Terminal::new(master);
+                terminal.child = Some(child);
+                Ok(terminal)
+            }
+            nix::unistd::ForkResult::Child => {
+                let _ = nix::unistd::execvpe(&command, &args, &env);
+                // Another of the parent's threads could have held a lock, e.g.
+                // on stderr, when it forked, so only async-signal-safe calls
+                // are made here. Returning or exiting normally would run the
+                // parent's code and exit handlers in the child.
+                let _ = nix::unistd::write(2, failed.as_bytes());
+                unsafe { nix::libc::_exit(127) }
+            }
+        }
+    }
+}
+
+/// The screen and scrollback of a terminal, without the process behind it.
+///
+/// Modes aren't kept: they were set by the old process, and the new one won't
+/// know to turn them off again.
+pub struct FrozenTerminal {
+    grid: Grid,
+    scrollback: VecDeque<Row>,
+    scrollback_limit: usize,
+    lines_dropped: usize,
+    prompt_marks: VecDeque<PromptMark>,
+    title: String,
+    cursor: CursorPos,
+    saved_cursor: Option<SavedCursor>,
+    pen: Style,
+    palette: Palette,
+    theme: Palette,
+    size: ScreenSize,
+    inspect: bool,
+}
+
+impl FrozenTerminal {
+    /// Start a new process from `builder`, showing the frozen screen. The pty
+    /// is given the size of the screen.
+    pub fn thaw<'a>(self, builder: TerminalBuilder) -> Result<Terminal<'a>> {
+        let mut term = builder
+            .size(nix::pty::Winsize {
+                ws_row: self.size.rows as u16,
+                ws_col: self.size.cols as u16,
+                ws_xpixel: 0,
+                ws_ypixel: 0,
+            })
+            .spawn()?;
+        term.grid = self.grid;
+        term.scrollback = self.scrollback;
+        term.scrollback_limit = self.scrollback_limit;
+        term.lines_dropped = self.lines_dropped;
+        term.prompt_marks = self.prompt_marks;
+        term.title = self.title;
+        term.cursor = self.cursor;
+        term.saved_cursor = self.saved_cursor;
+        term.pen = self.pen;
+        term.palette = self.palette;
+        term.theme = self.theme;
+        term.set_inspect(self.inspect);
+        term.damage_all();
+        term.generation += 1;
+        Ok(term)
+    }
+}
+
+/// The characters of a row for the styled exports. Rows that wrap keep their
+/// trailing blanks, since the text carries on through them. Other rows drop
+/// the trailing blanks that have nothing to show.
+fn export_chars(row: &Row) -> impl Iterator<Item = (char, &Style)> {
+    let chars: Vec<_> = row.chars().collect();
+    let end = if row.wrapped() {
+        chars.len()
+    } else {
+        chars
+            .iter()
+            .rposition(|(c, style)| *c != ' ' || **style != Style::default())
+            .map_or(0, |i| i + 1)
+    };
+    chars.into_iter().take(end)
+}
+
+/// The CSS for a style in [`Terminal::contents_html`], empty for the default.
+fn html_style(style: &Style, palette: &Palette) -> String {
+    let hex = |(r, g, b): (u8, u8, u8)| format!("#{:02x}{:02x}{:02x}", r, g, b);
+    let mut fg = style.fg.map(|color| palette.rgb(color));
+    let mut bg = style.bg.map(|color| palette.rgb(color));
+    if style.reverse {
+        // The default colors are up to the embedder, so fall back to xterm's.
+        let default_fg = palette.foreground.unwrap_or(palette.get(7));
+        let default_bg = palette.background.unwrap_or(palette.get(0));
+        (fg, bg) = (bg.or(Some(default_bg)), fg.or(Some(default_fg)));
+    }
+    let mut css = Vec::new();
+    if let Some(fg) = fg {
+        css.push(format!("color:{}", hex(fg)));
+    }
+    if let Some(bg) = bg {
+        css.push(format!("background-color:{}", hex(bg)));
+    }
+    if style.bold {
+        css.push("font-weight:bold".to_owned());
+    }
+    if style.dim {
+        css.push("opacity:0.5".to_owned());
+    }
+    if style.italic {
+        css.push("font-style:italic".to_owned());
+    }
+    let decoration = match style.underline {
+        UnderlineStyle::None => None,
+        UnderlineStyle::Single => Some("solid"),
+        UnderlineStyle::Double => Some("double"),
+        UnderlineStyle::Curly => Some("wavy"),
+        UnderlineStyle::Dotted => Some("dotted"),
+        UnderlineStyle::Dashed => Some("dashed"),
+    };
+    let lines: Vec<_> = [
+        (decoration.is_some(), "underline"),
+        (style.overline, "overline"),
+        (style.strikethrough, "line-through"),
+    ]
+    .into_iter()
+    .filter_map(|(on, line)| on.then_some(line))
+    .collect();
+    if !lines.is_empty() {
+        css.push(format!(
+            "text-decoration:{} {}",
+            lines.join(" "),
+            decoration.unwrap_or("solid")
+        ));
+    }
+    if let (Some(_), Some(color)) = (decoration, style.underline_color) {
+        css.push(format!("text-decoration-color:{}", hex(palette.rgb(color))));
+    }
+    css.join(";")
+}
+
+/// Quote and escape a string for JSON.
+fn json_string(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() + 2);
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn base64_encode(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = match *chunk {
+            [a, b, c] => (a as u32) << 16 | (b as u32) << 8 | c as u32,
+            [a, b] => (a as u32) << 16 | (b as u32) << 8,
+            [a] => (a as u32) << 16,
+            _ => unreachable!(),
+        };
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - i * 6)) as usize & 0x3F] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// A terminal connected to one end of a socket pair instead of a pty, so tests
+/// can play the part of the application on the other end. Also used by the
+/// debug GUI's tests, through the `test-support` feature.
+#[cfg(any(test, feature = "test-support"))]
+pub fn test_terminal() -> (Terminal<'static>, std::os::unix::net::UnixStream) {
+    let (ours, theirs) = std::os::unix::net::UnixStream::pair().expect("socketpair");
+    theirs.set_nonblocking(true).expect("nonblocking");
+    (Terminal::new(ours.into()), theirs)
+}
+
+/// Write `input` as the application and let the terminal process it.
+#[cfg(any(test, feature = "test-support"))]
+pub fn feed(term: &mut Terminal, app: &mut std::os::unix::net::UnixStream, input: &[u8]) {
+    use std::io::Write;
+    app.write_all(input).expect("write");
+    term.read().expect("read");
+}
+
+/// Everything the terminal has written back to the application so far.
+#[cfg(test)]
+fn replies(app: &mut std::os::unix::net::UnixStream) -> Vec<u8> {
+    use std::io::Read;
+    let mut buf = Vec::new();
+    match app.read_to_end(&mut buf) {
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+        Err(e) => panic!("read: {e}"),
+    }
+    buf
+}
+
+#[test]
+fn test_json_string() {
+    assert_eq!(json_string(""), r#""""#);
+    assert_eq!(json_string(r#"a "b" \ é"#), r#""a \"b\" \\ é""#);
+    assert_eq!(json_string("\t\x1b\n"), r#""\t\u001b\n""#);
+}
+
+#[test]
+fn test_snapshot_json() {
+    let (mut term, mut app) = test_terminal();
+    feed(&mut term, &mut app, b"\x1b]2;say \"hi\"\x07$ echo hi\r\nhi");
+    let json = term.snapshot_json();
+    assert!(json.starts_with("{\n  \"title\": \"say \\\"hi\\\"\",\n"));
+    assert!(json.contains("\"size\": { \"rows\": 24, \"cols\": 80 },"));
+    assert!(json.contains("\"cursor\": { \"row\": 1, \"col\": 2 },"));
+    assert!(json.contains("\"lines\": [\n    \"$ echo hi\",\n    \"hi\",\n    \"\","));
+    assert!(json.ends_with("    \"\"\n  ]\n}\n"));
+}
+
+#[test]
+fn test_base64_encode() {
+    assert_eq!(base64_encode(b""), "");
+    assert_eq!(base64_encode(b"f"), "Zg==");
+    assert_eq!(base64_encode(b"fo"), "Zm8=");
+    assert_eq!(base64_encode(b"foo"), "Zm9v");
+    assert_eq!(base64_encode(b"hello world"), "aGVsbG8gd29ybGQ=");
+}
+
+#[test]
+fn test_clipboard_query_denied_by_default() {
+    let (mut term, mut app) = test_terminal();
+    feed(&mut term, &mut app, b"\x1b]52;c;?\x07");
+    // Like xterm, a denied query gets no response at all rather than an empty one.
+    assert_eq!(replies(&mut app), b"");
+    assert_eq!(term.events().count(), 0);
+}
+
+#[test]
+fn test_clipboard_query_allowed() {
+    let (mut term, mut app) = test_terminal();
+    term.set_clipboard_policy(ClipboardPolicy::Allow);
+    feed(&mut term, &mut app, b"\x1b]52;c;?\x07");
+    let events = term.events().collect::<Vec<_>>();
+    let query = ClipboardQuery {
+        selection: "c".to_owned(),
+    };
+    assert_eq!(events, vec![TerminalEvent::ClipboardQuery(query.clone())]);
+
+    term.answer_clipboard_query(&query, "hello").unwrap();
+    assert_eq!(replies(&mut app), b"\x1b]52;c;aGVsbG8=\x07");
+
+    // An empty clipboard is an empty payload, not a missing response.
+    term.answer_clipboard_query(&query, "").unwrap();
+    assert_eq!(replies(&mut app), b"\x1b]52;c;\x07");
+}
+
+#[test]
+fn test_clipboard_query_callback() {
+    let (mut term, mut app) = test_terminal();
+    term.set_clipboard_policy(ClipboardPolicy::Ask(Box::new(|query| {
+        match query.selection.as_str() {
+            "c" => ClipboardAnswer::Contents("hello".to_owned()),
+            "p" => ClipboardAnswer::Defer,
+            _ => ClipboardAnswer::Deny,
+        }
+    })));
+
+    feed(&mut term, &mut app, b"\x1b]52;c;?\x07");
+    assert_eq!(replies(&mut app), b"\x1b]52;c;aGVsbG8=\x07");
+
+    feed(&mut term, &mut app, b"\x1b]52;s0;?\x07");
+    assert_eq!(replies(&mut app), b"");
+
+    // The callback defers, e.g. to show a prompt, and the answer arrives on a
+    // later frame.
+    feed(&mut term, &mut app, b"\x1b]52;p;?\x1b\\");
+    assert_eq!(replies(&mut app), b"");
+    let Some(TerminalEvent::ClipboardQuery(query)) = term.events().next() else {
+        panic!("expected a deferred clipboard query");
+    };
+    feed(&mut term, &mut app, b"some more output");
+    term.answer_clipboard_query(&query, "later").unwrap();
+    assert_eq!(replies(&mut app), b"\x1b]52;p;bGF0ZXI=\x07");
+}
+
+#[test]
+fn test_save_restore_modes() {
+    let (mut term, mut app) = test_terminal();
+    feed(&mut term, &mut app, b"\x1b[?1;2004h");
+    feed(&mut term, &mut app, b"\x1b[?1;1007;2004s");
+    feed(&mut term, &mut app, b"\x1b[?1;2004l\x1b[?1007h");
+    assert!(!term.modes().get_private(1));
+    assert!(term.modes().get_private(1007));
+    assert!(!term.modes().get_private(2004));
+
+    feed(&mut term, &mut app, b"\x1b[?1;1007;2004r");
+    assert!(term.modes().get_private(1));
+    assert!(!term.modes().get_private(1007));
+    assert!(term.modes().get_private(2004));
+
+    // Nothing saved for this one, so restoring leaves it alone.
+    feed(&mut term, &mut app, b"\x1b[?25h\x1b[?1;25r");
+    assert!(term.modes().get_private(25));
+}
+
+#[test]
+fn test_text_fills_grid() {
+    let (mut term, mut app) = test_terminal();
+    feed(&mut term, &mut app, b"hello\r\nworld\x1b[1;3Hy");
+    assert_eq!(term.grid().row(0).text(), "heylo");
+    assert_eq!(term.grid().row(1).text(), "world");
+    assert_eq!((term.cursor_pos().x(), term.cursor_pos().y()), (3, 0));
+}
+
+#[test]
+fn test_double_width_line() {
+    let (mut term, mut app) = test_terminal();
+    let cols = term.size().cols;
+    feed(&mut term, &mut app, b"\x1b#6");
+    feed(&mut term, &mut app, "x".repeat(cols / 2 + 1).as_bytes());
+    // Only half as many characters fit, the rest wraps onto the next line.
+    assert_eq!(term.grid().row(0).text(), "x".repeat(cols / 2));
+    assert_eq!(term.grid().row(1).text(), "x");
+
+    // The cursor can't be moved past the visible half of the line.
+    feed(&mut term, &mut app, format!("\x1b[1;{}H", cols).as_bytes());
+    assert_eq!(term.cursor_pos().x(), cols / 2 - 1);
+    feed(&mut term, &mut app, format!("\x1b[2;{}H", cols).as_bytes());
+    assert_eq!(term.cursor_pos().x(), cols - 1);
+    feed(&mut term, &mut app, b"\x1b#3");
+    assert_eq!(term.cursor_pos().x(), cols / 2 - 1);
+}
+
+#[test]
+fn test_edits_past_the_end_of_a_double_width_line() {
+    let (mut term, mut app) = test_terminal();
+    // Scrolling with SU puts a double width line under the cursor without
+    // moving it, past the end of the line.
+    for edit in [&b"\x1b[@"[..], b"\x1b[P", b"\x1b[X", b"\x1b[K"] {
+        feed(&mut term, &mut app, b"\x1b[2;1H\x1b#6ab\x1b[1;70H\x1b[S");
+        feed(&mut term, &mut app, edit);
+        assert_eq!(term.get_line_text(0), "ab");
+    }
+}
+
+#[test]
+fn test_vertical_moves_onto_double_width_line() {
+    let (mut term, mut app) = test_terminal();
+    let half = term.size().cols / 2;
+    // A line feed onto a double width line keeps the cursor inside it.
+    feed(&mut term, &mut app, b"\x1b[2;1H\x1b#6\x1b[1;70H\n\x1b[@");
+    assert_eq!(
+        (term.cursor_pos().x(), term.cursor_pos().y()),
+        (half - 1, 1)
+    );
+
+    // So does a reverse index.
+    feed(&mut term, &mut app, b"\x1b[3;70H\x1bM\x1b[@");
+    assert_eq!(
+        (term.cursor_pos().x(), term.cursor_pos().y()),
+        (half - 1, 1)
+    );
+}
+
+#[cfg(test)]
+fn scrollback_text(term: &Terminal) -> Vec<String> {
+    term.scrollback().iter().map(Row::text).collect()
+}
+
+#[test]
+fn test_scrollback() {
+    let (mut term, mut app) = test_terminal();
+    let rows = term.size().rows;
+    for i in 0..rows + 5 {
+        feed(&mut term, &mut app, format!("{}\r\n", i).as_bytes());
+    }
+    // The cursor ends up on the line after the last one, so one more line
+    // than the 5 extra has scrolled off.
+    assert_eq!(scrollback_text(&term), ["0", "1", "2", "3", "4", "5"]);
+    assert_eq!(term.grid().row(0).text(), "6");
+
+    term.set_scrollback_limit(2);
+    assert_eq!(scrollback_text(&term), ["4", "5"]);
+    assert_eq!(term.first_line(), 4);
+}
+
+#[test]
+fn test_clear_scrollback_before_mark() {
+    let (mut term, mut app) = test_terminal();
+    let rows = term.size().rows;
+    for command in 0..3 {
+        feed(&mut term, &mut app, b"\x1b]133;A\x07$ cmd\r\n");
+        for i in 0..rows {
+            feed(
+                &mut term,
+                &mut app,
+                format!("{} {}\r\n", command, i).as_bytes(),
+            );
+        }
+    }
+    let marks = term.prompt_marks().copied().collect::<Vec<_>>();
+    assert_eq!(marks.len(), 3);
+    assert_eq!(marks[1].line, rows + 1);
+
+    term.scroll_display(isize::MAX);
+    let offset = term.display_offset();
+    let selection = Selection::new(AbsolutePoint::new(0, 0), AbsolutePoint::new(rows + 2, 3));
+    term.set_selection(Some(selection));
+    term.take_damage();
+
+    term.clear_scrollback_before(&marks[1]);
+    // The first command is gone, the rest are untouched.
+    assert_eq!(term.first_line(), marks[1].line);
+    assert_eq!(term.scrollback().front().unwrap().text(), "$ cmd");
+    assert_eq!(term.prompt_marks().copied().collect::<Vec<_>>(), marks[1..]);
+    assert_eq!(
+        term.selection(),
+        Some(Selection::new(
+            AbsolutePoint::new(marks[1].line, 0),
+            AbsolutePoint::new(rows + 2, 3)
+        ))
+    );
+    assert_eq!(term.display_offset(), offset - marks[1].line);
+    assert!(term.take_damage().full);
+
+    // CSI 3 J drops the rest, but leaves the screen (and the prompt on it) alone.
+    feed(&mut term, &mut app, b"\x1b]133;A\x07$ ");
+    let screen = term.grid().to_string();
+    feed(&mut term, &mut app, b"\x1b[3J");
+    assert!(term.scrollback().is_empty());
+    assert_eq!(term.prompt_marks().count(), 1);
+    assert_eq!(term.selection(), None);
+    assert_eq!(term.display_offset(), 0);
+    assert_eq!(term.grid().to_string(), screen);
+}
+
+#[test]
+fn test_history_search() {
+    let (mut term, mut app) = test_terminal();
+    let rows = term.size().rows;
+    feed(&mut term, &mut app, b"call 555-1234 or 555-9876\r\n");
+    for i in 0..rows {
+        feed(&mut term, &mut app, format!("line {}\r\n", i).as_bytes());
+    }
+    feed(&mut term, &mut app, b"office: 555-0000");
+    assert_eq!(term.scrollback().len(), 2);
+
+    let matches = term.history_search("555-", false).unwrap();
+    assert_eq!(matches.len(), 3);
+    assert_eq!(matches[0].start(), AbsolutePoint::new(0, 5));
+    assert_eq!(matches[0].end(), AbsolutePoint::new(0, 8));
+    assert!(term.history_search("", false).unwrap().is_empty());
+
+    #[cfg(feature = "regex")]
+    {
+        let matches = term.history_search(r"\d{3}-\d{4}", true).unwrap();
+        let found = matches
+            .iter()
+            .map(|m| (m.start().line, m.start().col, m.end().col))
+            .collect::<Vec<_>>();
+        assert_eq!(found, [(0, 5, 12), (0, 17, 24), (rows + 1, 8, 15)]);
+
+        let err = term.history_search("[unclosed", true).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid regex");
+    }
+
+    // The display scrolls back just far enough to show the first match.
+    term.scroll_to_line(matches[0].start().line);
+    assert_eq!(term.top_line(), 0);
+    assert_eq!(term.display_offset(), 2);
+    term.scroll_to_line(rows + 1);
+    assert_eq!(term.display_offset(), 0);
+}
+
+#[test]
+fn test_next_line() {
+    let (mut term, mut app) = test_terminal();
+    let rows = term.size().rows;
+    feed(&mut term, &mut app, b"one\x1bEtwo");
+    assert_eq!(term.grid().row(0).text(), "one");
+    assert_eq!(term.grid().row(1).text(), "two");
+    assert_eq!((term.cursor_pos().x(), term.cursor_pos().y()), (3, 1));
+
+    // At the bottom of the screen it scrolls, like a line feed.
+    feed(&mut term, &mut app, format!("\x1b[{};5H", rows).as_bytes());
+    feed(&mut term, &mut app, b"\x1bEthree");
+    assert_eq!(term.scrollback().len(), 1);
+    assert_eq!(term.grid().row(0).text(), "two");
+    assert_eq!(term.grid().row(rows - 1).text(), "three");
+    assert_eq!(
+        (term.cursor_pos().x(), term.cursor_pos().y()),
+        (5, rows - 1)
+    );
+
+    // As a UTF-8 code point, and as a raw byte once 8-bit controls are on.
+    feed(&mut term, &mut app, "\u{85}four".as_bytes());
+    assert_eq!(term.grid().row(rows - 1).text(), "four");
+    term.set_eight_bit_controls(true);
+    feed(&mut term, &mut app, b"\x85five");
+    assert_eq!(term.grid().row(rows - 1).text(), "five");
+    assert_eq!(term.scrollback().len(), 3);
+}
+
+#[test]
+fn test_mode_summary() {
+    let (mut term, mut app) = test_terminal();
+    let summary = term.mode_summary();
+    assert!(summary.cursor_visible);
+    assert_eq!(summary.mouse, MouseProtocol::Off);
+
+    feed(&mut term, &mut app, b"\x1b[?1049h\x1b[?1002;2004h\x1b[?25l");
+    let summary = term.mode_summary();
+    assert!(summary.alternate_screen);
+    assert_eq!(summary.mouse, MouseProtocol::ButtonEvent);
+    assert!(summary.bracketed_paste);
+    assert!(!summary.cursor_visible);
+    assert_eq!(
+        term.events().collect::<Vec<_>>(),
+        [TerminalEvent::ModesChanged(summary)]
+    );
+
+    // Turning off a different mouse mode still turns reporting off.
+    feed(&mut term, &mut app, b"\x1b[?1000l");
+    assert_eq!(term.mode_summary().mouse, MouseProtocol::Off);
+    assert_eq!(term.events().count(), 1);
+
+    // No event when nothing in the summary changes.
+    feed(&mut term, &mut app, b"\x1b[?2026h\x1b[?2026l\x1b[?12h");
+    assert_eq!(term.events().count(), 0);
+}
+
+#[test]
+fn test_synchronized_update_timeout() {
+    let (mut term, mut app) = test_terminal();
+    let clock = crate::clock::FakeClock::new();
+    term.set_clock(clock.clone());
+
+    // Nothing is drawn until the update is finished.
+    feed(&mut term, &mut app, b"\x1b[?2026hhello");
+    assert_eq!(term.grid().row(0).text(), "");
+    clock.advance(Duration::from_millis(500));
+    term.apply_pending();
+    assert_eq!(term.grid().row(0).text(), "");
+    feed(&mut term, &mut app, b" world\x1b[?2026l");
+    assert_eq!(term.grid().row(0).text(), "hello world");
+    assert_eq!(term.next_deadline(), None);
+
+    // An update that is never finished is given up on.
+    feed(&mut term, &mut app, b"\x1b[?2026h!");
+    assert_eq!(term.grid().row(0).text(), "hello world");
+    clock.advance(Protocol::SynchronizedUpdate.default_timeout());
+    term.apply_pending();
+    assert_eq!(term.grid().row(0).text(), "hello world!");
+    assert!(!term.modes().synchronized_update);
+    assert!(term
+        .events()
+        .any(|event| event == TerminalEvent::TimedOut(Protocol::SynchronizedUpdate)));
+
+    // Rendering resumes.
+    feed(&mut term, &mut app, b"?");
+    assert_eq!(term.grid().row(0).text(), "hello world!?");
+}
+
+#[test]
+fn test_queries_during_synchronized_update() {
+    let (mut term, mut app) = test_terminal();
+
+    feed(
+        &mut term,
+        &mut app,
+        b"\x1b[?2026hhello\x1b[5n\x1b[c\x1b[?2026$p\x1b]4;1;?\x07",
+    );
+    assert_eq!(term.grid().row(0).text(), "");
+    assert_eq!(
+        replies(&mut app),
+        b"\x1b[0n\x1b[?62;22c\x1b[?2026;1$y\x1b]4;1;rgb:cdcd/0000/0000\x07"
+    );
+
+    // A color change is held back, even with a query in the same sequence.
+    feed(&mut term, &mut app, b"\x1b]4;1;rgb:ff/ff/ff;1;?\x07");
+    assert_eq!(replies(&mut app), b"");
+    feed(&mut term, &mut app, b"\x1b[?2026l");
+    assert_eq!(term.grid().row(0).text(), "hello");
+    assert_eq!(replies(&mut app), b"\x1b]4;1;rgb:ffff/ffff/ffff\x07");
+}
+
+#[test]
+fn test_control_string_timeout() {
+    let (mut term, mut app) = test_terminal();
+    let clock = crate::clock::FakeClock::new();
+    term.set_clock(clock.clone());
+    term.set_timeout(Protocol::Apc, Duration::from_secs(2));
+
+    // A sixel image that never ends.
+    feed(&mut term, &mut app, b"\x1bPq#0;2;0;0;0#0~~");
+    assert!(term.next_deadline().is_some());
+    clock.advance(Protocol::Dcs.default_timeout());
+    term.apply_pending();
+    assert_eq!(
+        term.events().collect::<Vec<_>>(),
+        [TerminalEvent::TimedOut(Protocol::Dcs)]
+    );
+    feed(&mut term, &mut app, b"text");
+    assert_eq!(term.grid().row(0).text(), "text");
+
+    // Each kitty graphics chunk gets its own deadline.
+    feed(&mut term, &mut app, b"\x1b_Gm=1;AAAA");
+    clock.advance(Duration::from_millis(1500));
+    feed(&mut term, &mut app, b"\x1b\\\x1b_Gm=0;AAAA");
+    clock.advance(Duration::from_millis(1500));
+    term.apply_pending();
+    assert_eq!(term.events().count(), 0);
+    clock.advance(Duration::from_millis(500));
+    term.apply_pending();
+    assert_eq!(
+        term.events().collect::<Vec<_>>(),
+        [TerminalEvent::TimedOut(Protocol::Apc)]
+    );
+    feed(&mut term, &mut app, b" more");
+    assert_eq!(term.grid().row(0).text(), "text more");
+}
+
+#[test]
+fn test_sgr_styles_cells() {
+    use crate::style::Color;
+
+    let (mut term, mut app) = test_terminal();
+    feed(&mut term, &mut app, b"\x1b[1;31mA\x1b[39mB\x1b[0mC");
+    let row = term.grid().row(0);
+    let a = row.cells()[0].style();
+    assert_eq!(a.fg, Some(Color::Red));
+    assert!(a.bold);
+    let b = row.cells()[1].style();
+    assert_eq!(b.fg, None);
+    assert!(b.bold);
+    assert_eq!(*row.cells()[2].style(), Style::default());
+    assert_eq!(*term.pen(), Style::default());
+}
+
+#[test]
+fn test_visible_absolute_mapping() {
+    let (mut term, mut app) = test_terminal();
+    let rows = term.size().rows;
+    for i in 0..rows + 5 {
+        feed(&mut term, &mut app, format!("{}\r\n", i).as_bytes());
+    }
+    // Six lines have scrolled off, so the top row is line 6.
+    assert_eq!(term.visible_to_absolute(0), 6);
+    assert_eq!(term.absolute_to_visible(6), Some(0));
+    assert_eq!(term.absolute_to_visible(rows + 5), Some(rows - 1));
+    assert_eq!(term.absolute_to_visible(5), None);
+    assert_eq!(term.absolute_to_visible(rows + 6), None);
+
+    term.scroll_display(2);
+    assert_eq!(term.visible_to_absolute(0), 4);
+    assert_eq!(term.absolute_to_visible(4), Some(0));
+    assert_eq!(term.absolute_to_visible(rows + 5), None);
+    assert_eq!(
+        term.visible_rows().next().unwrap().text(),
+        term.scrollback()[4].text()
+    );
+
+    // Dropping old lines doesn't move the view or renumber the lines.
+    term.set_scrollback_limit(3);
+    assert_eq!(term.first_line(), 3);
+    assert_eq!(term.visible_to_absolute(0), 4);
+    assert_eq!(term.absolute_to_visible(4), Some(0));
+    assert_eq!(term.absolute_to_visible(2), None);
+}
+
+#[test]
+fn test_accessible_lines() {
+    let (mut term, mut app) = test_terminal();
+    feed(
+        &mut term,
+        &mut app,
+        "h\u{e9}llo w\u{f6}rld  \r\n$ ".as_bytes(),
+    );
+    let lines = term.accessible_lines();
+    assert_eq!(lines.len(), term.size().rows);
+    let line = &lines[0];
+    assert_eq!(line.text, "h\u{e9}llo w\u{f6}rld");
+    // The accented characters take two bytes.
+    assert_eq!(line.column_to_offset(1), 1);
+    assert_eq!(line.column_to_offset(2), 3);
+    assert_eq!(line.column_to_offset(8), 10);
+    assert_eq!(line.column_to_offset(50), line.text.len());
+    assert_eq!(line.offset_to_column(2), 1);
+    assert_eq!(line.offset_to_column(3), 2);
+    assert_eq!(lines[1].text, "$");
+    assert_eq!(term.visible_cursor(), Some((1, 2)));
+
+    term.take_damage();
+    feed(&mut term, &mut app, b"ls");
+    let damage = term.take_damage();
+    assert_eq!(damage.rows.iter().copied().collect::<Vec<_>>(), [1]);
+    assert_eq!(term.accessible_line(1).text, "$ ls");
+}
+
+#[test]
+fn test_freeze_and_thaw() {
+    let (mut term, mut app) = test_terminal();
+    feed(&mut term, &mut app, b"\x1b]2;build\x07\x1b[31m$ make\r\n");
+    term.set_scrollback_limit(1);
+    feed(&mut term, &mut app, &b"\n".repeat(30));
+
+    let builder = TerminalBuilder::new().command("cat");
+    let mut thawed = term.freeze().thaw(builder).expect("thaw");
+    assert_eq!(thawed.title(), "build");
+    assert_eq!(thawed.size(), term.size());
+    assert_eq!(thawed.grid(), term.grid());
+    assert_eq!(thawed.cursor_pos().y(), term.cursor_pos().y());
+    assert_eq!(thawed.pen(), term.pen());
+    assert_eq!(thawed.top_line(), term.top_line());
+    assert!(thawed.take_damage().full);
+
+    // The two carry on separately.
+    feed(&mut term, &mut app, b"test");
+    assert_ne!(thawed.grid(), term.grid());
+}
+
+#[test]
+fn test_title_debounce() {
+    let (mut term, mut app) = test_terminal();
+    let clock = crate::clock::FakeClock::new();
+    term.set_clock(clock.clone());
+    let titles = |term: &mut Terminal| {
+        term.events()
+            .filter_map(|event| match event {
+                TerminalEvent::TitleChanged(title) => Some(title),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+    };
+
+    feed(&mut term, &mut app, b"\x1b]2;a\x07\x1b]2;b\x07");
+    assert_eq!(titles(&mut term), ["a", "b"]);
+
+    term.set_title_debounce(Some(Duration::from_millis(100)));
+    clock.advance(Duration::from_millis(100));
+    feed(&mut term, &mut app, b"\x1b]2;c\x07");
+    assert_eq!(titles(&mut term), ["c"]);
+    for title in ["d", "e", "f"] {
+        clock.advance(Duration::from_millis(20));
+        feed(
+            &mut term,
+            &mut app,
+            format!("\x1b]2;{}\x07", title).as_bytes(),
+        );
+    }
+    assert_eq!(titles(&mut term), Vec::<String>::new());
+    assert_eq!(term.title(), "f");
+
+    clock.advance(Duration::from_millis(40));
+    assert_eq!(term.next_deadline(), Some(clock.now()));
+    term.apply_pending();
+    assert_eq!(titles(&mut term), ["f"]);
+    assert_eq!(term.next_deadline(), None);
+}
+
+#[test]
+fn test_inject_bytes() {
+    // Anything that fcntl accepts will do, nothing is read or written.
+    let fd = std::fs::File::open("/dev/null").expect("open /dev/null");
+    let mut term = Terminal::new(fd.into());
+    term.inject_bytes(b"hello\r\n\x1b[1mworld");
+    term.inject_bytes(b"\x1b[2;3H!");
+    assert_eq!(term.grid().row(0).text(), "hello");
+    assert_eq!(term.grid().row(1).text(), "wo!ld");
+    assert!(term.pen().bold);
+    assert_eq!(term.stats().bytes_read, 23);
+}
+
+#[test]
+fn test_terminal_is_send() {
+    fn assert_send<T: Send>() {}
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send::<Terminal<'static>>();
+    assert_send_sync::<FrozenTerminal>();
+    assert_send_sync::<Grid>();
+    assert_send_sync::<Row>();
+    assert_send_sync::<Damage>();
+}
+
+#[test]
+fn test_terminal_across_threads() {
+    use std::sync::{Arc, Mutex};
+
+    let (term, _app) = test_terminal();
+    let term = Arc::new(Mutex::new(term));
+    let feeder = {
+        let term = Arc::clone(&term);
+        std::thread::spawn(move || {
+            for i in 0..500 {
+                let line = format!("\x1b[{}mline {}\x1b[0m\r\n", 31 + i % 7, i);
+                term.lock().unwrap().inject_bytes(line.as_bytes());
+            }
+        })
+    };
+    let mut snapshots = 0;
+    while !feeder.is_finished() {
+        let mut term = term.lock().unwrap();
+        let damage = term.take_damage();
+        let grid = term.grid().clone();
+        drop(term);
+        // Whatever was caught, it's a consistent screen.
+        assert_eq!(grid.rows().len(), grid.size().rows);
+        for row in damage.rows {
+            assert!(row < grid.size().rows);
+        }
+        snapshots += 1;
+    }
+    feeder.join().unwrap();
+    assert!(snapshots > 0);
+    let term = term.lock().unwrap();
+    let rows = term.size().rows;
+    assert_eq!(term.grid().row(rows - 2).text(), "line 499");
+}
+
+#[test]
+fn test_color_set_query_reset() {
+    let (mut term, mut app) = test_terminal();
+    let mut theme = Palette::default();
+    theme.set(1, (0xaa, 0x11, 0x22));
+    theme.foreground = Some((0xdd, 0xdd, 0xdd));
+    term.set_theme(theme);
+    term.take_damage();
+
+    feed(
+        &mut term,
+        &mut app,
+        b"\x1b]4;1;?\x07\x1b]10;?\x07\x1b]12;?\x07",
+    );
+    assert_eq!(
+        replies(&mut app),
+        b"\x1b]4;1;rgb:aaaa/1111/2222\x07\x1b]10;rgb:dddd/dddd/dddd\x07"
+    );
+    assert!(term.take_damage().is_empty());
+
+    feed(
+        &mut term,
+        &mut app,
+        b"\x1b]4;1;#ff0000;2;#00ff00\x07\x1b]10;rgb:12/34/56\x07",
+    );
+    assert!(term.take_damage().full);
+    feed(&mut term, &mut app, b"\x1b]4;1;?\x07\x1b]10;?\x07");
+    assert_eq!(
+        replies(&mut app),
+        b"\x1b]4;1;rgb:ffff/0000/0000\x07\x1b]10;rgb:1212/3434/5656\x07"
+    );
+
+    // Resets go back to the theme, not xterm's colors.
+    feed(&mut term, &mut app, b"\x1b]104;1\x07\x1b]110\x07");
+    assert!(term.take_damage().full);
+    feed(&mut term, &mut app, b"\x1b]4;1;?;2;?\x07\x1b]10;?\x07");
+    assert_eq!(
+        replies(&mut app),
+        b"\x1b]4;1;rgb:aaaa/1111/2222\x07\x1b]4;2;rgb:0000/ffff/0000\x07\x1b]10;rgb:dddd/dddd/dddd\x07"
+    );
+    feed(&mut term, &mut app, b"\x1b]104\x07");
+    assert_eq!(term.palette().get(2), Palette::default().get(2));
+    assert_eq!(term.palette().get(1), (0xaa, 0x11, 0x22));
+}
+
+#[test]
+fn test_request_mode() {
+    let (mut term, mut app) = test_terminal();
+    feed(&mut term, &mut app, b"\x1b[?2048$p\x1b[?2004h\x1b[?2004$p");
+    feed(
+        &mut term,
+        &mut app,
+        b"\x1b[?1234$p\x1b[?1234h\x1b[?1234$p\x1b[4$p",
+    );
+    assert_eq!(
+        replies(&mut app),
+        b"\x1b[?2048;2$y\x1b[?2004;1$y\x1b[?1234;0$y\x1b[?1234;1$y\x1b[4;0$y"
+    );
+}
+
+#[test]
+fn test_device_status_report() {
+    let (mut term, mut app) = test_terminal();
+    feed(
+        &mut term,
+        &mut app,
+        b"\x1b[5n\x1b[3;7H\x1b[6n\x1b[999;999H\x1b[6n",
+    );
+    assert_eq!(replies(&mut app), b"\x1b[0n\x1b[3;7R\x1b[24;80R");
+
+    // Made for the embedder, the report doesn't reach the application, even
+    // in the middle of a sequence or a synchronized update.
+    feed(&mut term, &mut app, b"\x1b[?2026h\x1b[3");
+    assert_eq!(term.cursor_position_report(), b"\x1b[24;80R");
+    feed(&mut term, &mut app, b";5H\x1b[?2026l");
+    assert_eq!(term.cursor_position_report(), b"\x1b[3;5R");
+    assert_eq!(replies(&mut app), b"");
+}
+
+#[test]
+fn test_device_attributes() {
+    let (mut term, mut app) = test_terminal();
+    feed(&mut term, &mut app, b"\x1b[c\x1b[>c\x1b[1c");
+    assert_eq!(replies(&mut app), b"\x1b[?62;22c\x1b[>1;10;0c");
+}
+
+#[test]
+fn test_device_attributes_are_responses() {
+    use std::sync::{Arc, Mutex};
+
+    let (mut term, mut app) = test_terminal();
+    let log = Arc::new(Mutex::new(Vec::new()));
+    term.set_outgoing_tap({
+        let log = Arc::clone(&log);
+        move |origin, bytes: &[u8]| log.lock().unwrap().push((origin, bytes.to_vec()))
+    });
+    feed(&mut term, &mut app, b"\x1b[0c");
+    assert_eq!(
+        *log.lock().unwrap(),
+        [(WriteOrigin::Response, PRIMARY_DEVICE_ATTRIBUTES.to_vec())]
+    );
+}
+
+#[test]
+fn test_cursor_position_report_reaches_the_process() {
+    let mut term = TerminalBuilder::new()
+        .command("sh")
+        .args(&[
+            "-c",
+            "stty raw -echo; printf '\\033[3;5H\\033[6n'; \
+             reply=$(dd bs=1 count=6 2>/dev/null | tr '\\033' E); \
+             printf '\\033[H%s' \"$reply\"; sleep 5",
+        ])
+        .spawn()
+        .expect("spawn");
+    let start = Instant::now();
+    while !term.grid().to_string().contains("E[3;5R") {
+        assert!(start.elapsed() < Duration::from_secs(5), "{}", term.grid());
+        term.read().expect("read");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn test_auto_wrap() {
+    let (mut term, mut app) = test_terminal();
+    let line = "0123456789".repeat(8);
+    feed(&mut term, &mut app, line.as_bytes());
+    // The wrap waits for the next character, so a newline now doesn't leave a
+    // blank line.
+    assert_eq!(term.cursor_pos().y(), 0);
+    feed(&mut term, &mut app, b"x");
+    assert_eq!(term.get_line_text(0), line);
+    assert_eq!(term.get_line_text(1), "x");
+    assert_eq!((term.cursor_pos().x(), term.cursor_pos().y()), (1, 1));
+
+    // Without DECAWM the last column is overwritten.
+    feed(&mut term, &mut app, b"\x1b[?7l\x1b[3;1H");
+    feed(&mut term, &mut app, line.as_bytes());
+    feed(&mut term, &mut app, b"abc");
+    assert_eq!(term.get_line_text(2), format!("{}c", &line[..79]));
+    assert_eq!(term.get_line_text(3), "");
+    assert_eq!(term.cursor_pos().y(), 2);
+    feed(&mut term, &mut app, b"\x1b[?7$p");
+    assert_eq!(replies(&mut app), b"\x1b[?7;2$y");
+}
+
+#[test]
+fn test_auto_repeat_mode() {
+    let (mut term, mut app) = test_terminal();
+    assert!(term.modes().auto_repeat);
+    feed(&mut term, &mut app, b"\x1b[?8l\x1b[?8$p");
+    assert!(!term.modes().auto_repeat);
+    feed(&mut term, &mut app, b"\x1b[?8h");
+    assert!(term.modes().auto_repeat);
+    assert_eq!(replies(&mut app), b"\x1b[?8;2$y");
+}
+
+#[test]
+fn test_in_band_resize() {
+    use nix::pty::Winsize;
+
+    let winsize = |rows: u16, cols: u16| Winsize {
+        ws_row: rows,
+        ws_col: cols,
+        ws_xpixel: cols * 8,
+        ws_ypixel: rows * 16,
+    };
+    // Echo the reports back with `cat -v`, and say when SIGWINCH arrives.
+    let script = "stty raw -echo; cat -v </dev/tty & trap 'printf WINCH' WINCH; \
+        printf '\\033[?2048h'; while :; do sleep 0.01; done";
+    let mut term = TerminalBuilder::new()
+        .args(&["-c", script])
+        .size(winsize(24, 80))
+        .spawn()
+        .expect("spawn");
+    term.set_window_size(&winsize(24, 80)).expect("resize");
+    let wait_for = |term: &mut Terminal, text: &str| {
+        let start = Instant::now();
+        while !term.grid().to_string().contains(text) {
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "no {:?} in:\n{}",
+                text,
+                term.grid()
+            );
+            term.read().expect("read");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    };
+
+    // Turning the mode on reports the size straight away.
+    wait_for(&mut term, "^[[48;24;80;384;640t");
+    term.set_window_size(&winsize(30, 100)).expect("resize");
+    wait_for(&mut term, "WINCH");
+    wait_for(&mut term, "^[[48;30;100;480;800t");
+}
+
+#[test]
+fn test_wide_chars() {
+    let (mut term, mut app) = test_terminal();
+    feed(&mut term, &mut app, "a中文b".as_bytes());
+    assert_eq!(term.grid().row(0).text(), "a中文b");
+    assert_eq!(term.cursor_pos().x(), 6);
+    assert!(term.grid().row(0).cells()[2].is_spacer());
+    let matches = term.history_search("文", false).unwrap();
+    assert_eq!((matches[0].start().col, matches[0].end().col), (3, 4));
+
+    // Overwriting half of a wide character blanks the other half.
+    feed(&mut term, &mut app, b"\x1b[1;3Hx");
+    assert_eq!(term.grid().row(0).text(), "a x文b");
+
+    // One that doesn't fit at the end of a row goes on the next one.
+    feed(&mut term, &mut app, "\r\n".as_bytes());
+    feed(
+        &mut term,
+        &mut app,
+        format!("{}中", "x".repeat(79)).as_bytes(),
+    );
+    assert!(term.grid().row(1).wrapped());
+    assert!(term.grid().row(1).cells()[79].is_spacer());
+    assert_eq!(term.grid().row(2).text(), "中");
+}
+
+#[test]
+fn test_get_line_text() {
+    let (mut term, mut app) = test_terminal();
+    feed(&mut term, &mut app, "first\r\n".as_bytes());
+    feed(&mut term, &mut app, "\r\n".repeat(23).as_bytes());
+    feed(&mut term, &mut app, "中x  ".as_bytes());
+    assert_eq!(term.get_line_text(23), "中x");
+    assert_eq!(term.get_line_text(0), "");
+    assert_eq!(term.get_line_text(24), "");
+    // Rows are counted from the top of the view.
+    term.scroll_display(1);
+    assert_eq!(term.get_line_text(0), "first");
+}
+
+#[test]
+fn test_arrows_to_move_cursor_to() {
+    let (mut term, mut app) = test_terminal();
+    // Nothing happens outside of a command.
+    assert_eq!(term.arrows_to_move_cursor_to(0, 0), None);
+    feed(
+        &mut term,
+        &mut app,
+        "\x1b]133;A\x07$ \x1b]133;B\x07echo 中文 ok".as_bytes(),
+    );
+    let left = |n: usize| b"\x1b[D".repeat(n);
+    // Both halves of a wide character are the same position.
+    assert_eq!(term.arrows_to_move_cursor_to(7, 0), Some(left(5)));
+    assert_eq!(term.arrows_to_move_cursor_to(8, 0), Some(left(5)));
+    assert_eq!(term.arrows_to_move_cursor_to(9, 0), Some(left(4)));
+    // Clicks on the prompt go to the start, and past the end go to the end.
+    assert_eq!(term.arrows_to_move_cursor_to(0, 0), Some(left(10)));
+    assert_eq!(term.arrows_to_move_cursor_to(40, 0), Some(Vec::new()));
+    assert_eq!(term.arrows_to_move_cursor_to(0, 1), None);
+
+    feed(&mut term, &mut app, b"\x1b[1;4H\x1b[?1h");
+    assert_eq!(term.cursor_pos().x(), 3);
+    assert_eq!(
+        term.arrows_to_move_cursor_to(10, 0),
+        Some(b"\x1bOC".repeat(5))
+    );
+    feed(&mut term, &mut app, b"\x1b[?1l\x1b[?1000h");
+    assert_eq!(term.arrows_to_move_cursor_to(10, 0), None);
+    feed(&mut term, &mut app, b"\x1b[?1000l\r\n\x1b]133;C\x07");
+    assert_eq!(term.arrows_to_move_cursor_to(0, 1), None);
+
+    // A command that wraps, with a wide character that didn't fit at the end
+    // of the first row.
+    feed(
+        &mut term,
+        &mut app,
+        format!("\x1b]133;A\x07$ \x1b]133;B\x07{}中ab", "x".repeat(77)).as_bytes(),
+    );
+    assert_eq!(term.cursor_pos().y(), 2);
+    assert_eq!(term.arrows_to_move_cursor_to(78, 1), Some(left(4)));
+    assert_eq!(term.arrows_to_move_cursor_to(79, 1), Some(left(3)));
+    assert_eq!(term.arrows_to_move_cursor_to(1, 2), Some(left(3)));
+    assert_eq!(term.arrows_to_move_cursor_to(3, 2), Some(left(1)));
+    assert_eq!(term.arrows_to_move_cursor_to(2, 1), Some(left(80)));
+}
+
+#[test]
+fn test_write_vectored() {
+    use std::io::Read;
+
+    let (mut term, mut app) = test_terminal();
+    // A tiny send buffer, so the kernel only takes part of the data each time.
+    let size: nix::libc::c_int = 1024;
+    let result = unsafe {
+        nix::libc::setsockopt(
+            term.fd.as_raw_fd(),
+            nix::libc::SOL_SOCKET,
+            nix::libc::SO_SNDBUF,
+            &size as *const _ as *const nix::libc::c_void,
+            std::mem::size_of_val(&size) as nix::libc::socklen_t,
+        )
+    };
+    assert_eq!(result, 0);
+    let chunks: Vec<Vec<u8>> = (0..50u32)
+        .map(|i| (0..i * 97 % 5000).map(|j| (i + j) as u8).collect())
+        .collect();
+    let expected = chunks.concat();
+    let total = expected.len();
+    app.set_nonblocking(false).expect("blocking");
+    let reader = std::thread::spawn(move || {
+        let mut received = vec![0; total];
+        app.read_exact(&mut received).expect("read");
+        received
+    });
+    let slices: Vec<IoSlice> = chunks.iter().map(|chunk| IoSlice::new(chunk)).collect();
+    term.write_vectored(&slices).expect("write");
+    assert_eq!(reader.join().unwrap(), expected);
+}
+
+#[test]
+fn test_exports() {
+    let (mut term, _app) = test_terminal();
+    term.inject_bytes(b"\x1b]133;A\x07$ \x1b[1;31mred\x1b[0m <b>\r\n\x1b#6wide\r\n");
+    term.inject_bytes("x".repeat(85).as_bytes());
+    assert_eq!(
+        term.contents(),
+        format!("$ red <b>\nwide\n{}", "x".repeat(85))
+    );
+    assert_eq!(
+        term.contents_ansi(),
+        format!(
+            "\x1b]133;A\x07$ \x1b[0;1;31mred\x1b[0m <b>\r\n\x1b#6wide\r\n{}",
+            "x".repeat(85)
+        )
+    );
+    assert_eq!(
+        term.contents_html(),
+        format!(
+            "<pre>\n<!-- prompt -->$ <span style=\"color:#cd0000;font-weight:bold\">red</span> \
+             &lt;b&gt;\n<!-- double-width -->wide\n{}\n</pre>\n",
+            "x".repeat(85)
+        )
+    );
+}
+
+#[test]
+fn test_line_flags_survive_scrollback_and_export() {
+    // A tiny random number generator, so each round gets a different mix of
+    // lines without pulling in a crate for it.
+    let mut seed = 1u32;
+    let mut next = |n: u32| {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        (seed >> 16) % n
+    };
+    let flags = |term: &Terminal| {
+        term.export_rows()
+            .iter()
+            .map(|row| (row.flags(), row.text()))
+            .collect::<Vec<_>>()
+    };
+    for _ in 0..50 {
+        let (mut term, _app) = test_terminal();
+        let mut input = String::new();
+        for i in 0..next(12) + 1 {
+            input.push_str(&match next(5) {
+                0 => format!("plain {}\r\n", i),
+                1 => format!(
+                    "\x1b[3{}m{}\x1b[0m\r\n",
+                    i % 8,
+                    "w".repeat(70 + next(100) as usize)
+                ),
+                2 => format!("\x1b#6wide {}\r\n", "d".repeat(next(60) as usize)),
+                3 => format!("\x1b#3tall {i}\r\n\x1b#4tall {i}\r\n"),
+                _ => format!("\x1b]133;A\x07$ cmd {}\r\n", i),
+            });
+        }
+        term.inject_bytes(input.as_bytes());
+        let before = flags(&term);
+
+        // Into the scrollback...
+        term.inject_bytes("\r\n".repeat(30).as_bytes());
+        assert_eq!(flags(&term), before);
+        assert!(term.grid().rows().iter().all(|row| row.flags().is_empty()));
+        assert_eq!(term.freeze().scrollback, term.scrollback);
+
+        // ...and back out through the exports.
+        let (mut replay, _app) = test_terminal();
+        replay.inject_bytes(term.contents_ansi().as_bytes());
+        assert_eq!(flags(&replay), before);
+        let count = |flag| {
+            before
+                .iter()
+                .filter(|(flags, _)| flags.contains(flag))
+                .count()
+        };
+        let lines = before.len() - count(LineFlags::WRAPPED);
+        assert_eq!(term.contents().lines().count(), lines);
+        let html = term.contents_html();
+        assert_eq!(
+            html.matches("<!-- prompt -->").count(),
+            count(LineFlags::PROMPT)
+        );
+        assert_eq!(
+            html.matches("<!-- double-width -->").count(),
+            count(LineFlags::DOUBLE_WIDTH)
+        );
+        assert_eq!(
+            html.matches("<!-- double-height-top -->").count(),
+            count(LineFlags::DOUBLE_HEIGHT_TOP)
+        );
+    }
+}
+
+#[test]
+fn test_cursor_movement() {
+    let (mut term, mut app) = test_terminal();
+    let mut at = |input: &[u8]| {
+        feed(&mut term, &mut app, input);
+        (term.cursor_pos().x(), term.cursor_pos().y())
+    };
+    // Row first, and 1-based.
+    assert_eq!(at(b"\x1b[5H"), (0, 4));
+    assert_eq!(at(b"\x1b[5;7H"), (6, 4));
+    assert_eq!(at(b"\x1b[;7H"), (6, 0));
+    assert_eq!(at(b"\x1b[H"), (0, 0));
+    // HVP is the same as CUP.
+    assert_eq!(at(b"\x1b[3;4fX"), (4, 2));
+    assert_eq!(at(b"\x1b[5;10H\x1b[2A"), (9, 2));
+    assert_eq!(at(b"\x1b[3B\x1b[4C"), (13, 5));
+    assert_eq!(at(b"\x1b[D"), (12, 5));
+    assert_eq!(at(b"\x1b[2E"), (0, 7));
+    assert_eq!(at(b"\x1b[5G\x1b[F"), (0, 6));
+    assert_eq!(at(b"\x1b[30G"), (29, 6));
+    assert_eq!(at(b"\x1b[12`"), (11, 6));
+    assert_eq!(at(b"\x1b[4d"), (11, 3));
+    assert_eq!(at(b"\x1b[d"), (11, 0));
+    // Clamped at the edges of the screen.
+    assert_eq!(at(b"\x1b[500d"), (11, 23));
+    assert_eq!(at(b"\x1b[99A\x1b[99D"), (0, 0));
+    assert_eq!(at(b"\x1b[99B\x1b[99C"), (79, 23));
+    assert_eq!(at(b"\x1b[999G\x1b[99F"), (0, 0));
+}
+
+#[test]
+fn test_indexed_colors_on_cells() {
+    use crate::style::Color;
+
+    let (mut term, mut app) = test_terminal();
+    // vim and htop style 256 color output, with a truncated and an out of
+    // range one that are ignored.
+    feed(
+        &mut term,
+        &mut app,
+        b"\x1b[38;5;208;48;5;17mA\x1b[38;5m\x1b[48;5;300mB\x1b[38;5;3mC",
+    );
+    let style = |col: usize| *term.grid().row(0).cells()[col].style();
+    assert_eq!(style(0).fg, Some(Color::Indexed(208)));
+    assert_eq!(style(0).bg, Some(Color::Indexed(17)));
+    assert_eq!(style(1), style(0));
+    assert_eq!(style(2).fg, Some(Color::Yellow));
+}
+
+#[test]
+fn test_outgoing_tap() {
+    use std::{
+        io::Read,
+        sync::{Arc, Mutex},
+    };
+
+    let (mut term, mut app) = test_terminal();
+    let log = Arc::new(Mutex::new(Vec::new()));
+    term.set_outgoing_tap({
+        let log = Arc::clone(&log);
+        move |origin, bytes: &[u8]| log.lock().unwrap().push((origin, bytes.to_vec()))
+    });
+    term.write(b"ls\n").unwrap();
+    feed(&mut term, &mut app, b"\x1b[?2004$p");
+    term.write_from(WriteOrigin::Injected, b"\x1b[200~")
+        .unwrap();
+    term.write_vectored(&[IoSlice::new(b"a"), IoSlice::new(b"b")])
+        .unwrap();
+    assert_eq!(
+        *log.lock().unwrap(),
+        [
+            (WriteOrigin::Input, b"ls\n".to_vec()),
+            (WriteOrigin::Response, b"\x1b[?2004;2$y".to_vec()),
+            (WriteOrigin::Injected, b"\x1b[200~".to_vec()),
+            (WriteOrigin::Input, b"a".to_vec()),
+            (WriteOrigin::Input, b"b".to_vec()),
+        ]
+    );
+    // The tap sees the bytes in the order the application gets them.
+    let mut received = vec![0; 64];
+    let n = app.read(&mut received).unwrap();
+    let tapped: Vec<u8> = log
+        .lock()
+        .unwrap()
+        .iter()
+        .flat_map(|(_, bytes)| bytes.clone())
+        .collect();
+    assert_eq!(received[..n], tapped);
+}
+
+#[test]
+fn test_caret_notation() {
+    assert_eq!(caret_notation(b"\x1b[A\x7f\r\n\0"), "^[[A^?^M^J^@");
+    assert_eq!(caret_notation("é中".as_bytes()), "é中");
+}
+
+#[test]
+fn test_rgb_colors_on_cells() {
+    use crate::style::Color;
+
+    let (mut term, mut app) = test_terminal();
+    feed(
+        &mut term,
+        &mut app,
+        b"\x1b[38;2;255;100;0;48;2;1;2;3mA\x1b[38;2;9m\x1b[1mB",
+    );
+    let style = |col: usize| *term.grid().row(0).cells()[col].style();
+    assert_eq!(style(0).fg, Some(Color::Rgb(255, 100, 0)));
+    assert_eq!(style(0).bg, Some(Color::Rgb(1, 2, 3)));
+    // Missing components are 0, and the parameters after them still apply.
+    assert_eq!(style(1).fg, Some(Color::Rgb(9, 0, 0)));
+    assert!(style(1).bold);
+}
+
+#[test]
+fn test_bright_colors_on_cells() {
+    use crate::style::Color;
+
+    let (mut term, mut app) = test_terminal();
+    feed(&mut term, &mut app, b"\x1b[1;31mA\x1b[0;91;103mB");
+    let style = |col: usize| *term.grid().row(0).cells()[col].style();
+    // Bold red and bright red stay different, for themes to tell apart.
+    assert_eq!((style(0).fg, style(0).bold), (Some(Color::Red), true));
+    assert_eq!(
+        (style(1).fg, style(1).bold),
+        (Some(Color::BrightRed), false)
+    );
+    assert_eq!(style(1).bg, Some(Color::BrightYellow));
+}
+
+#[test]
+fn test_underline_styles_on_cells() {
+    use crate::style::Color;
+
+    let (mut term, mut app) = test_terminal();
+    feed(
+        &mut term,
+        &mut app,
+        b"\x1b[4:3;58;2;255;0;0mA\x1b[4mB\x1b[59mC\x1b[24mD",
+    );
+    let style = |col: usize| *term.grid().row(0).cells()[col].style();
+    assert_eq!(style(0).underline, UnderlineStyle::Curly);
+    assert_eq!(style(0).underline_color, Some(Color::Rgb(255, 0, 0)));
+    // A plain 4 is a single underline, and keeps the color.
+    assert_eq!(style(1).underline, UnderlineStyle::Single);
+    assert_eq!(style(1).underline_color, Some(Color::Rgb(255, 0, 0)));
+    assert_eq!(style(2).underline_color, None);
+    assert_eq!(style(3).underline, UnderlineStyle::None);
+}
+
+#[test]
+fn test_alternate_screen() {
+    let (mut term, mut app) = test_terminal();
+    let lines: String = (0..30).map(|i| format!("shell {i}\r\n")).collect();
+    feed(&mut term, &mut app, lines.as_bytes());
+    feed(&mut term, &mut app, b"$ ");
+    let scrollback = term.scrollback().len();
+    let cursor = term.cursor_pos().clone();
+
+    feed(&mut term, &mut app, b"\x1b[?1049h");
+    assert!(term.modes().alternate_screen);
+    assert_eq!(term.get_line_text(0), "");
+    let lines: String = (0..50).map(|i| format!("app {i}\r\n")).collect();
+    feed(&mut term, &mut app, lines.as_bytes());
+    feed(&mut term, &mut app, b"\x1b[H");
+    // Nothing the application scrolled off reaches the scrollback.
+    assert_eq!(term.scrollback().len(), scrollback);
+
+    feed(&mut term, &mut app, b"\x1b[?1049l");
+    assert!(!term.modes().alternate_screen);
+    assert_eq!(term.scrollback().len(), scrollback);
+    assert_eq!(term.get_line_text(term.size().rows - 1), "$");
+    assert_eq!(*term.cursor_pos(), cursor);
+
+    // 47 keeps what was on the alternate screen.
+    feed(&mut term, &mut app, b"\x1b[?47hkept\x1b[?47l\x1b[?47h");
+    assert!(term.grid().to_string().contains("kept"));
+}
+
+#[test]
+fn test_terminal_builder() {
+    let mut term = TerminalBuilder::new()
+        .command("sh")
+        .args(&["-c", "printf \"$GREETING\"; sleep 5"])
+        .env("GREETING", "hello from the builder")
+        .size(nix::pty::Winsize {
+            ws_row: 10,
+            ws_col: 40,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        })
+        .spawn()
+        .expect("spawn");
+    assert_eq!(term.size(), ScreenSize::new(40, 10));
+    let start = Instant::now();
+    while !term.grid().to_string().contains("hello from the builder") {
+        assert!(start.elapsed() < Duration::from_secs(5), "{}", term.grid());
+        term.read().expect("read");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn test_child_reaped_after_drop() {
+    let term = TerminalBuilder::new()
+        .command("cat")
+        .spawn()
+        .expect("spawn");
+    let child = term.child().expect("a child");
+    drop(term);
+    // A zombie can still be signalled, so this only fails once it's reaped.
+    let start = Instant::now();
+    while unsafe { nix::libc::kill(child.as_raw(), 0) } == 0 {
+        assert!(start.elapsed() < Duration::from_secs(5), "not reaped");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn test_failed_spawn_reports_it() {
+    let mut term = TerminalBuilder::new()
+        .command("/nonexistent/termulus-test")
+        .spawn()
+        .expect("spawn");
+    let start = Instant::now();
+    while !term.grid().to_string().contains("failed to start") {
+        assert!(start.elapsed() < Duration::from_secs(5), "{}", term.grid());
+        // The pty reports an error once the child has exited.
+        let _ = term.read();
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn test_overline_on_cells() {
+    let (mut term, mut app) = test_terminal();
+    feed(
+        &mut term,
+        &mut app,
+        b"\x1b[53mABC\x1b[55mDEF\x1b[21;53mG\x1b[0mH",
+    );
+    let style = |col: usize| *term.grid().row(0).cells()[col].style();
+    assert!((0..3).all(|col| style(col).overline));
+    assert!((3..6).all(|col| !style(col).overline));
+    assert!(style(6).overline);
+    assert_eq!(style(6).underline, UnderlineStyle::Double);
+    // A reset clears them along with everything else.
+    assert_eq!(style(7), Style::default());
+}
+
+#[test]
+fn test_default_color_resets_on_cells() {
+    use crate::style::Color;
+
+    let (mut term, mut app) = test_terminal();
+    feed(&mut term, &mut app, b"\x1b[1;31;44mX\x1b[39mY\x1b[49mZ");
+    let style = |col: usize| *term.grid().row(0).cells()[col].style();
+    assert_eq!(style(0).fg, Some(Color::Red));
+    assert!(style(1).bold);
+    assert_eq!(style(1).fg, None);
+    assert_eq!(style(1).bg, Some(Color::Blue));
+    assert!(style(2).bold);
+    assert_eq!(style(2).bg, None);
+}
+
+#[test]
+fn test_decode_utf8() {
+    let decode = |pending: &mut Vec<u8>, text: &[u8]| {
+        let mut out = String::new();
+        decode_utf8(pending, text, |c| out.push(c));
+        out
+    };
+    let mut pending = Vec::new();
+    assert_eq!(decode(&mut pending, "é日".as_bytes()), "é日");
+    assert!(pending.is_empty());
+
+    // A character split across reads comes out whole in the second one.
+    let bytes = "a日b".as_bytes();
+    assert_eq!(decode(&mut pending, &bytes[..2]), "a");
+    assert_eq!(pending, bytes[1..2]);
+    assert_eq!(decode(&mut pending, &bytes[2..3]), "");
+    assert_eq!(decode(&mut pending, &bytes[3..]), "日b");
+    assert!(pending.is_empty());
+
+    assert_eq!(
+        decode(&mut pending, b"a\xffb\xe6\x97z"),
+        "a\u{fffd}b\u{fffd}z"
+    );
+}
+
+#[test]
+fn test_utf8_split_across_reads() {
+    let (mut term, _app) = test_terminal();
+    let bytes = "é日本語".as_bytes();
+    for byte in bytes {
+        term.inject_bytes(std::slice::from_ref(byte));
+    }
+    let row = term.grid().row(0);
+    assert_eq!(row.text(), "é日本語");
+    // One column for é, two for each of the wide characters.
+    assert_eq!(term.cursor_pos().x(), 7);
+
+    // Anything but text in the middle of a sequence cuts it short.
+    term.inject_bytes(&bytes[1..2]);
+    term.inject_bytes(b"\x1b[mx");
+    assert_eq!(term.get_line_text(0), "é日本語\u{fffd}x");
+}
+
+#[test]
+fn test_clears_reset_cell_styles() {
+    let (mut term, mut app) = test_terminal();
+    feed(
+        &mut term,
+        &mut app,
+        b"\x1b[1;31mred\r\nmore\x1b[0m\x1b[1;2H\x1b[J",
+    );
+    let row = term.grid().row(0);
+    assert_eq!(row.spans().len(), 2);
+    assert_eq!(row.spans()[1].1, Style::default());
+    assert!(term
+        .grid()
+        .row(1)
+        .spans()
+        .iter()
+        .all(|(_, style)| *style == Style::default()));
+
+    feed(&mut term, &mut app, b"\x1b[31mx\x1b[2J");
+    assert_eq!(term.grid().row(0).spans()[0].1, Style::default());
+}
+
+#[test]
+fn test_display_width() {
+    let (mut term, mut app) = test_terminal();
+    let cols = term.size().cols;
+    // Each wide character takes two columns, so half as many fit on a line.
+    feed(&mut term, &mut app, "中".repeat(cols / 2 + 1).as_bytes());
+    assert_eq!(term.get_line_text(0), "中".repeat(cols / 2));
+    assert!(term.grid().row(0).wrapped());
+    assert_eq!(term.get_line_text(1), "中");
+    assert_eq!(term.cursor_pos().x(), 2);
+
+    // Combining marks join the character before them, even a wide one.
+    feed(&mut term, &mut app, "\r\ne\u{301}x中\u{302}!".as_bytes());
+    assert_eq!(term.get_line_text(2), "e\u{301}x中\u{302}!");
+    assert_eq!(term.grid().row(2).cells()[0].mark(), Some('\u{301}'));
+    assert_eq!(term.cursor_pos().x(), 5);
+}
+
+#[test]
+fn test_garbage_input() {
+    let (mut term, _app) = test_terminal();
+    for seed in 0..100 {
+        let bytes = crate::parser::garbage(seed, 4000);
+        for chunk in bytes.chunks(1 + seed as usize % 61) {
+            term.inject_bytes(chunk);
+        }
+    }
+}
+
+#[test]
+fn test_save_restore_cursor() {
+    use crate::style::Color;
+
+    let (mut term, mut app) = test_terminal();
+    feed(
+        &mut term,
+        &mut app,
+        b"\x1b[3;5H\x1b[1;31m\x1b7\x1b[10;10H\x1b[0mx\x1b8y",
+    );
+    assert_eq!((term.cursor_pos().x(), term.cursor_pos().y()), (5, 2));
+    // The pen comes back too.
+    let style = *term.grid().row(2).cells()[4].style();
+    assert!(style.bold);
+    assert_eq!(style.fg, Some(Color::Red));
+    assert_eq!(*term.grid().row(9).cells()[9].style(), Style::default());
+}
+
+#[test]
+fn test_restore_cursor_twice_and_unsaved() {
+    let (mut term, mut app) = test_terminal();
+    // With nothing saved, DECRC homes the cursor and resets the pen.
+    feed(&mut term, &mut app, b"\x1b[5;5H\x1b[1m\x1b8x");
+    assert_eq!(term.get_line_text(0), "x");
+    assert_eq!(*term.grid().row(0).cells()[0].style(), Style::default());
+
+    // A saved cursor can be restored more than once, by either form.
+    feed(&mut term, &mut app, b"\x1b[2;2H\x1b[s\x1b8a\x1b[u");
+    assert_eq!((term.cursor_pos().x(), term.cursor_pos().y()), (1, 1));
+
+    // A pending wrap is saved with the cursor.
+    let line = [b'-'; 80];
+    feed(&mut term, &mut app, b"\x1b[4;1H");
+    feed(&mut term, &mut app, &line);
+    feed(&mut term, &mut app, b"\x1b7\x1b[10;10H\x1b8z");
+    assert_eq!(term.get_line_text(4), "z");
+}
+
+#[test]
+fn test_restore_cursor_after_shrinking() {
+    let winsize = |rows, cols| nix::pty::Winsize {
+        ws_row: rows,
+        ws_col: cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    let mut term = TerminalBuilder::new()
+        .command("cat")
+        .size(winsize(24, 80))
+        .spawn()
+        .expect("spawn");
+    term.inject_bytes(b"\x1b[24;80H\x1b7");
+    term.set_window_size(&winsize(10, 40)).expect("resize");
+    term.inject_bytes(b"\x1b8x");
+    assert_eq!(term.get_line_text(9), format!("{}x", " ".repeat(39)));
+
+    // Leaving the alternate screen restores the cursor the same way.
+    term.set_window_size(&winsize(24, 80)).expect("resize");
+    term.inject_bytes(b"\x1b[24;80H\x1b[?1049h");
+    term.set_window_size(&winsize(10, 40)).expect("resize");
+    term.inject_bytes(b"\x1b[?1049ly");
+    assert_eq!((term.cursor_pos().x(), term.cursor_pos().y()), (40, 9));
+    assert!(term.get_line_text(9).ends_with('y'));
+}
+
+#[test]
+fn test_erase_in_line() {
+    let (mut term, mut app) = test_terminal();
+    feed(&mut term, &mut app, b"abcdef\r\x1b[0K");
+    assert_eq!(term.get_line_text(0), "");
+
+    feed(
+        &mut term,
+        &mut app,
+        b"abcdef\r\nabcdef\r\nabcdef\x1b[1;3H\x1b[K",
+    );
+    assert_eq!(term.get_line_text(0), "ab");
+    feed(&mut term, &mut app, b"\x1b[2;3H\x1b[1K");
+    assert_eq!(term.get_line_text(1), "   def");
+    feed(&mut term, &mut app, b"\x1b[3;3H\x1b[2K");
+    assert_eq!(term.get_line_text(2), "");
+    // Only the cursor's line is touched, and the cursor stays put.
+    assert_eq!(term.get_line_text(1), "   def");
+    assert_eq!((term.cursor_pos().x(), term.cursor_pos().y()), (2, 2));
+}
+
+#[test]
+fn test_insert_delete_chars() {
+    let (mut term, mut app) = test_terminal();
+    let line = (0..80).map(|i| b'a' + (i % 26) as u8).collect::<Vec<_>>();
+    feed(&mut term, &mut app, &line);
+    feed(&mut term, &mut app, b"\x1b[1;3H\x1b[2@");
+    let text = term.get_line_text(0);
+    assert_eq!(text.len(), 80);
+    assert_eq!(&text[..6], "ab  cd");
+    // The last two characters were pushed off the edge.
+    assert!(text.ends_with("wxyz"));
+    assert_eq!((term.cursor_pos().x(), term.cursor_pos().y()), (2, 0));
+
+    feed(&mut term, &mut app, b"\x1b[3P");
+    let text = term.get_line_text(0);
+    assert_eq!(&text[..4], "abde");
+    assert!(text.ends_with("wxyz"));
+    assert_eq!(text.len(), 77);
+}
+
+#[test]
+fn test_bell() {
+    let (mut term, mut app) = test_terminal();
+    feed(&mut term, &mut app, b"a\x07b\x1b]2;title\x07");
+    assert_eq!(term.get_line_text(0), "ab");
+    let bells = term
+        .events()
+        .filter(|event| matches!(event, TerminalEvent::Bell))
+        .count();
+    assert_eq!(bells, 1);
+}
+
+#[test]
+fn test_erase_chars() {
+    use crate::style::Color;
+
+    let (mut term, mut app) = test_terminal();
+    feed(
+        &mut term,
+        &mut app,
+        b"abcdef\r\nnext\x1b[1;3H\x1b[42m\x1b[2X",
+    );
+    assert_eq!(term.get_line_text(0), "ab  ef");
+    assert_eq!((term.cursor_pos().x(), term.cursor_pos().y()), (2, 0));
+    let cell = &term.grid().row(0).cells()[2];
+    assert_eq!(cell.style().bg, Some(Color::Green));
+    assert_eq!(term.grid().row(0).cells()[4].style().bg, None);
+
+    // Stops at the end of the line.
+    feed(&mut term, &mut app, b"\x1b[0m\x1b[1;5H\x1b[999X");
+    assert_eq!(term.get_line_text(0), "ab");
+    assert_eq!(term.get_line_text(1), "next");
+}
+
+#[test]
+fn test_background_color_erase() {
+    use crate::style::Color;
+
+    let blue = Style {
+        bg: Some(Color::Blue),
+        ..Style::default()
+    };
+    let (mut term, mut app) = test_terminal();
+    feed(&mut term, &mut app, b"text\x1b[44m\x1b[2J");
+    for row in term.grid().rows() {
+        assert_eq!(row.spans(), vec![(" ".repeat(row.cols()), blue)]);
+    }
+
+    // Lines, parts of lines, and lines scrolled in.
+    feed(&mut term, &mut app, b"\x1b[0m\x1b[2J\x1b[Hab\x1b[44m\x1b[K");
+    let spans = term.grid().row(0).spans();
+    assert_eq!(spans[0], ("ab".to_owned(), Style::default()));
+    assert_eq!(spans[1].1, blue);
+    let last = term.size().rows - 1;
+    feed(
+        &mut term,
+        &mut app,
+        format!("\x1b[{}H\n", last + 1).as_bytes(),
+    );
+    assert_eq!(term.grid().row(last).spans()[0].1, blue);
+
+    // With SGR reset, clearing goes back to the default background.
+    feed(&mut term, &mut app, b"\x1b[0m\x1b[2J");
+    for row in term.grid().rows() {
+        assert!(row.chars().all(|(_, style)| *style == Style::default()));
+    }
+}
+
+#[test]
+fn test_alignment_test() {
+    let (mut term, mut app) = test_terminal();
+    feed(
+        &mut term,
+        &mut app,
+        b"\x1b#6\x1b[2;5r\x1b[41m\x1b[10;10H\x1b#8",
+    );
+    let size = term.size();
+    for row in term.grid().rows() {
+        assert_eq!(row.spans(), vec![("E".repeat(size.cols), Style::default())]);
+        assert_eq!(row.size(), LineSize::Normal);
+    }
+    assert_eq!((term.cursor_pos().x(), term.cursor_pos().y()), (0, 0));
+    assert_eq!(term.scroll_region(), 0..size.rows);
+}
+
+#[test]
+fn test_selection_text() {
+    let (mut term, mut app) = test_terminal();
+    let cols = term.size().cols;
+    let long = "x".repeat(cols + 5);
+    feed(
+        &mut term,
+        &mut app,
+        format!("one   \r\n{long}\r\nthree").as_bytes(),
+    );
+    assert_eq!(term.selection_text(), None);
+
+    let line = term.visible_to_absolute(0);
+    let select = |term: &mut Terminal, from: (usize, usize), to: (usize, usize)| {
+        term.set_selection(Some(Selection::new(
+            AbsolutePoint::new(line + from.0, from.1),
+            AbsolutePoint::new(line + to.0, to.1),
+        )));
+        term.selection_text().unwrap()
+    };
+    assert_eq!(select(&mut term, (0, 1), (0, 5)), "ne");
+    // The wrapped line comes back as one line.
+    assert_eq!(
+        select(&mut term, (3, 2), (0, 0)),
+        format!("one\n{long}\nthr")
+    );
+    assert_eq!(select(&mut term, (1, cols - 1), (2, 1)), "xxx");
+
+    // Lines in the scrollback can be selected too.
+    let rows = term.size().rows;
+    feed(&mut term, &mut app, "\r\n".repeat(rows).as_bytes());
+    assert_eq!(select(&mut term, (0, 0), (0, 2)), "one");
+}
+
+#[test]
+fn test_repeat_char() {
+    use crate::style::Color;
+
+    let (mut term, mut app) = test_terminal();
+    // Nothing has been printed yet.
+    feed(&mut term, &mut app, b"\x1b[5b");
+    assert_eq!(term.get_line_text(0), "");
+
+    // A box drawn the way ncurses draws one, with REP for the edges.
+    feed(
+        &mut term,
+        &mut app,
+        "\x1b[31m┌─\x1b[6b┐\x1b[0m\r\n│\x1b[8C│\r\n└─\x1b[6b┘".as_bytes(),
+    );
+    assert_eq!(term.get_line_text(0), "┌───────┐");
+    assert_eq!(term.get_line_text(1), "│        │");
+    assert_eq!(term.get_line_text(2), "└───────┘");
+    let style = term.grid().row(0).cells()[5].style();
+    assert_eq!(style.fg, Some(Color::Red));
+
+    // The style is the one the character was printed with.
+    feed(&mut term, &mut app, b"\r\n\x1b[32mx\x1b[0m\x1b[2b");
+    assert_eq!(term.get_line_text(3), "xxx");
+    assert_eq!(term.grid().row(3).cells()[2].style().fg, Some(Color::Green));
+    assert_eq!(*term.pen(), Style::default());
+
+    // Each screen starts without a last character.
+    feed(&mut term, &mut app, b"\x1b[?1049h\x1b[3b");
+    assert_eq!(term.get_line_text(0), "");
+}
+
+#[test]
+fn test_insert_delete_lines() {
+    let (mut term, mut app) = test_terminal();
+    feed(&mut term, &mut app, b"a\r\nb\r\nc\r\nd\r\ne");
+    feed(&mut term, &mut app, b"\x1b[2;3H\x1b[2L");
+    let lines = (0..7).map(|i| term.get_line_text(i)).collect::<Vec<_>>();
+    assert_eq!(lines, ["a", "", "", "b", "c", "d", "e"]);
+    assert_eq!((term.cursor_pos().x(), term.cursor_pos().y()), (0, 1));
+
+    feed(&mut term, &mut app, b"\x1b[3M");
+    let lines = (0..5).map(|i| term.get_line_text(i)).collect::<Vec<_>>();
+    assert_eq!(lines, ["a", "c", "d", "e", ""]);
+
+    // Lines pushed out of the scroll region are lost, and the rows below it
+    // stay put.
+    feed(&mut term, &mut app, b"\x1b[1;3r\x1b[2;1H\x1b[L");
+    let lines = (0..5).map(|i| term.get_line_text(i)).collect::<Vec<_>>();
+    assert_eq!(lines, ["a", "", "c", "e", ""]);
+    // Outside the region nothing happens.
+    feed(&mut term, &mut app, b"\x1b[4;1H\x1b[M");
+    assert_eq!(term.get_line_text(3), "e");
+}
+
+#[test]
+fn test_tab_motion() {
+    let (mut term, mut app) = test_terminal();
+    let x = |term: &Terminal| term.cursor_pos().x();
+    feed(&mut term, &mut app, b"ab\x1b[I");
+    assert_eq!(x(&term), 8);
+    feed(&mut term, &mut app, b"\x1b[2I");
+    assert_eq!(x(&term), 24);
+    feed(&mut term, &mut app, b"\x1b[3G\x1b[Z");
+    assert_eq!(x(&term), 0);
+    feed(&mut term, &mut app, b"\x1b[17G\x1b[Z");
+    assert_eq!(x(&term), 8);
+    // Clamped to the edges of the line.
+    feed(&mut term, &mut app, b"\x1b[99I");
+    assert_eq!(x(&term), 79);
+    feed(&mut term, &mut app, b"\x1b[99Z");
+    assert_eq!(x(&term), 0);
+    // Nothing was written.
+    assert_eq!(term.get_line_text(0), "ab");
+}
+
+#[test]
+fn test_tab_stops() {
+    let (mut term, mut app) = test_terminal();
+    let x = |term: &Terminal| term.cursor_pos().x();
+    feed(&mut term, &mut app, b"abc\t");
+    assert_eq!(x(&term), 8);
+    feed(&mut term, &mut app, b"\t\t");
+    assert_eq!(x(&term), 24);
+
+    // HTS adds a stop, and TBC clears the one under the cursor.
+    feed(&mut term, &mut app, b"\r\x1b[4G\x1bH\x1b[17G\x1b[g\r\t");
+    assert_eq!(x(&term), 3);
+    feed(&mut term, &mut app, b"\t\t");
+    assert_eq!(x(&term), 24);
+    feed(&mut term, &mut app, b"\x1b[2Z");
+    assert_eq!(x(&term), 3);
+
+    // Without any stops, tabs go to the end of the line.
+    feed(&mut term, &mut app, b"\x1b[3g\r\t");
+    assert_eq!(x(&term), 79);
+    feed(&mut term, &mut app, b"\x1b[Z");
+    assert_eq!(x(&term), 0);
+}
+
+#[test]
+fn test_reverse_index() {
+    let (mut term, mut app) = test_terminal();
+    feed(&mut term, &mut app, b"a\r\nb\x1bMc\x1bM\x1bM\rd");
+    let lines = (0..4).map(|i| term.get_line_text(i)).collect::<Vec<_>>();
+    assert_eq!(lines, ["d", "", "ac", "b"]);
+
+    // Only the scroll region moves.
+    feed(&mut term, &mut app, b"\x1b[2;3r\x1b[2;1H\x1bMe");
+    let lines = (0..4).map(|i| term.get_line_text(i)).collect::<Vec<_>>();
+    assert_eq!(lines, ["d", "e", "", "b"]);
+}
+
+#[test]
+fn test_lines_in_small_scroll_region() {
+    let (mut term, mut app) = test_terminal();
+    let lines: Vec<String> = (0..24).map(|i| format!("line {i}")).collect();
+    feed(&mut term, &mut app, lines.join("\r\n").as_bytes());
+    feed(&mut term, &mut app, b"\x1b[5;9r\x1b[5H");
+    for i in 0..30 {
+        feed(&mut term, &mut app, format!("new {i}\r\n").as_bytes());
+    }
+    for row in (0..4).chain(9..24) {
+        assert_eq!(term.get_line_text(row), lines[row]);
+    }
+    let region: Vec<String> = (4..9).map(|row| term.get_line_text(row)).collect();
+    assert_eq!(region, ["new 26", "new 27", "new 28", "new 29", ""]);
+}
+
+#[test]
+fn test_scroll_region() {
+    let (mut term, mut app) = test_terminal();
+    let lines: Vec<String> = (0..24).map(|i| format!("line {i}")).collect();
+    feed(&mut term, &mut app, lines.join("\r\n").as_bytes());
+    let text = |term: &Terminal, row: usize| term.get_line_text(row);
+
+    // Scroll rows 3 to 5 (1-based) up by writing past the bottom of them.
+    feed(&mut term, &mut app, b"\x1b[3;5r\x1b[5Hx\n\n");
+    assert_eq!(text(&term, 0), "line 0");
+    assert_eq!(text(&term, 1), "line 1");
+    assert_eq!(text(&term, 2), "xine 4");
+    assert_eq!(text(&term, 3), "");
+    assert_eq!(text(&term, 4), "");
+    assert_eq!(text(&term, 5), "line 5");
+    assert_eq!(text(&term, 23), "line 23");
+    // Lines scrolled out of a region that isn't at the top are dropped.
+    assert!(term.scrollback().is_empty());
+
+    // SD and SU only move the region too.
+    feed(&mut term, &mut app, b"\x1b[T");
+    assert_eq!(text(&term, 2), "");
+    assert_eq!(text(&term, 3), "xine 4");
+    feed(&mut term, &mut app, b"\x1b[9S");
+    assert!((2..5).all(|row| text(&term, row).is_empty()));
+    assert_eq!(text(&term, 5), "line 5");
+
+    // A bare DECSTBM goes back to the whole screen, and homes the cursor.
+    feed(&mut term, &mut app, b"\x1b[r");
+    assert_eq!((term.cursor_pos().x(), term.cursor_pos().y()), (0, 0));
+    feed(&mut term, &mut app, b"\x1b[24H\n");
+    assert_eq!(text(&term, 0), "line 1");
+    assert_eq!(term.scrollback().len(), 1);
+}
+
+#[test]
+fn test_cursor_clamped_to_screen() {
+    // A pty that hasn't been given a size yet reports 0x0.
+    let pty = nix::pty::openpty(None, None).expect("openpty");
+    let mut term = Terminal::new(pty.master);
+    assert_eq!(term.size(), ScreenSize::DEFAULT);
+
+    // The classic "move to the bottom" trick lands on the last real row.
+    term.inject_bytes(b"\x1b[999;1H");
+    assert_eq!((term.cursor_pos().x(), term.cursor_pos().y()), (0, 23));
+    term.inject_bytes(b"\x1b[999;999Hx\t\t");
+    assert_eq!(term.get_line_text(23), format!("{}x", " ".repeat(79)));
+    assert_eq!(term.grid().size(), ScreenSize::DEFAULT);
+    term.inject_bytes(b"\x1b[999B\x1b[999C");
+    assert_eq!((term.cursor_pos().x(), term.cursor_pos().y()), (79, 23));
+}
+
+#[test]
+fn test_scroll_up_down() {
+    let (mut term, mut app) = test_terminal();
+    let lines: Vec<String> = (0..24).map(|i| format!("line {i}")).collect();
+    feed(&mut term, &mut app, lines.join("\r\n").as_bytes());
+    feed(&mut term, &mut app, b"\x1b[3S");
+    assert_eq!(term.get_line_text(0), "line 3");
+    assert_eq!(term.get_line_text(20), "line 23");
+    assert!((21..24).all(|row| term.get_line_text(row).is_empty()));
+    // The lines scrolled off the top are kept.
+    let scrollback: Vec<_> = term.scrollback().iter().map(Row::text).collect();
+    assert_eq!(scrollback, ["line 0", "line 1", "line 2"]);
+
+    feed(&mut term, &mut app, b"\x1b[2T");
+    assert!((0..2).all(|row| term.get_line_text(row).is_empty()));
+    assert_eq!(term.get_line_text(2), "line 3");
+    assert_eq!(term.scrollback().len(), 3);
 }